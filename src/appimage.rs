@@ -0,0 +1,151 @@
+//! Standalone AppImages, e.g. downloads kept in `~/Applications`.
+//!
+//! AppImages integrated with appimaged already come with a desktop file in
+//! the usual directories. The others are found by file name in the configured
+//! directories and listed under a name derived from it, since reading their
+//! embedded desktop file would mean mounting or running them.
+
+use crate::AppInfo;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+
+// version and architecture parts of a file name aren't part of the app name
+const ARCHITECTURES: &[&str] = &[
+    "x86_64", "x86-64", "amd64", "x64", "i386", "i686", "aarch64", "arm64", "armhf",
+];
+
+fn is_appimage(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("appimage"))
+        && path.is_file()
+}
+
+/// Derives a display name from a file name like `Krita-5.2.2-x86_64.AppImage`.
+fn display_name(file_name: &str) -> String {
+    let stem = file_name
+        .rsplit_once('.')
+        .map_or(file_name, |(stem, _)| stem);
+    let words: Vec<&str> = stem
+        .split('-')
+        .take_while(|part| {
+            !part.starts_with(|c: char| c.is_ascii_digit())
+                && !ARCHITECTURES.iter().any(|arch| part.eq_ignore_ascii_case(arch))
+        })
+        .collect();
+    if words.is_empty() {
+        return stem.to_string();
+    }
+    words.join(" ").replace('_', " ")
+}
+
+/// Lists the AppImages directly inside `dir`, a missing directory having none.
+pub(crate) fn scan(dir: &Path) -> Vec<AppInfo> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut apps: Vec<AppInfo> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| is_appimage(path))
+        .map(|path| {
+            let file_name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            AppInfo {
+                name: display_name(&file_name),
+                description: Some("AppImage".to_string()),
+                id: file_name,
+                path: path.to_string_lossy().into_owned(),
+                appimage: true,
+                ..AppInfo::default()
+            }
+            .index()
+        })
+        .collect();
+    // read_dir order isn't stable
+    apps.sort_by(|a, b| a.path.cmp(&b.path));
+    apps
+}
+
+/// Makes `path` executable by its owner if it isn't yet, downloaded
+/// AppImages usually aren't.
+fn ensure_executable(path: &Path) -> io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    if permissions.mode() & 0o100 == 0 {
+        permissions.set_mode(permissions.mode() | 0o100);
+        fs::set_permissions(path, permissions)?;
+    }
+    Ok(())
+}
+
+/// Runs the AppImage at `path` with `files` as arguments.
+pub(crate) fn launch(path: &Path, files: &[String]) -> bool {
+    if let Err(e) = ensure_executable(path) {
+        log_error!("Failed to make {} executable: {}", path.display(), e);
+        return false;
+    }
+    match Command::new(path).args(files).spawn() {
+        Ok(_) => true,
+        Err(e) => {
+            log_error!("Failed to run {}: {}", path.display(), e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDir;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn names_from_file_names() {
+        assert_eq!(display_name("Krita-5.2.2-x86_64.AppImage"), "Krita");
+        assert_eq!(display_name("balenaEtcher-1.18.11-x64.AppImage"), "balenaEtcher");
+        assert_eq!(display_name("Visual-Studio-Code-1.0.appimage"), "Visual Studio Code");
+        assert_eq!(display_name("My_App.AppImage"), "My App");
+        assert_eq!(display_name("1password.AppImage"), "1password");
+    }
+
+    #[test]
+    fn scans_appimages_only() {
+        let dir = TestDir::new("appimages");
+        dir.write("Obsidian-1.5.3.AppImage", "");
+        dir.write("notes.txt", "");
+        dir.write("Old.AppImage/readme", "a directory, not an AppImage");
+
+        let apps = scan(dir.path());
+        assert_eq!(apps.len(), 1);
+        assert_eq!(apps[0].name, "Obsidian");
+        assert_eq!(apps[0].id, "Obsidian-1.5.3.AppImage");
+        assert!(apps[0].appimage);
+        assert!(scan(&dir.path().join("missing")).is_empty());
+    }
+
+    #[test]
+    fn launch_makes_executable_and_runs() {
+        let dir = TestDir::new("appimage-launch");
+        let marker = dir.path().join("launched");
+        // a stand-in AppImage recording the file it was asked to open
+        let appimage = dir.write(
+            "Fake-1.0-x86_64.AppImage",
+            &format!("#!/bin/sh\necho \"$1\" > '{}'\n", marker.display()),
+        );
+        assert_eq!(fs::metadata(&appimage).unwrap().permissions().mode() & 0o100, 0);
+
+        assert!(launch(&appimage, &["/home/me/file.txt".to_string()]));
+        assert_ne!(fs::metadata(&appimage).unwrap().permissions().mode() & 0o100, 0);
+        let start = Instant::now();
+        while !marker.exists() && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        // the script may still be writing it
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(fs::read_to_string(&marker).unwrap(), "/home/me/file.txt\n");
+    }
+}
@@ -0,0 +1,352 @@
+//! In-memory cache of parsed desktop files, so a launcher filtering on every
+//! keystroke doesn't re-read every `.desktop` file each time.
+//!
+//! Entries are kept per `applications` directory together with the
+//! directory's mtime, and those of its subdirectories when they are scanned
+//! too. Installing, removing or replacing a desktop file bumps one of them,
+//! and the directory is then scanned again on the next lookup.
+//!
+//! The shared cache is also kept on disk, in `$XDG_CACHE_HOME/applist/index.bin`
+//! (bincode), so a new session starts from the last one's entries and only
+//! scans the directories changed since. The file is read on first lookup and
+//! written after a load that scanned something. An unreadable file, or one
+//! from another format version, is ignored and written anew.
+
+use crate::{AppInfo, DirMtime, ScanOptions};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+
+/// Version of the disk index layout, bumped whenever `CachedDir`, `AppInfo`
+/// or `ScanOptions` change.
+const DISK_FORMAT: u32 = 2;
+
+/// What the disk index holds: its format, the scan options and the
+/// directories.
+type DiskIndex = (u32, ScanOptions, Vec<(PathBuf, CachedDir)>);
+
+#[derive(Serialize, Deserialize)]
+struct CachedDir {
+    mtime: SystemTime,
+    subdirs: Vec<DirMtime>,
+    apps: Vec<AppInfo>,
+}
+
+impl CachedDir {
+    fn is_fresh(&self, mtime: SystemTime) -> bool {
+        self.mtime == mtime
+            && self.subdirs.iter().all(|(dir, mtime)| {
+                std::fs::metadata(dir).and_then(|meta| meta.modified()).is_ok_and(|m| m == *mtime)
+            })
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Cache {
+    dirs: HashMap<PathBuf, CachedDir>,
+    // the options the entries were parsed with, e.g. localized names depend
+    // on the locales
+    options: ScanOptions,
+    /// Number of desktop files read since the cache was created.
+    pub(crate) files_read: usize,
+    /// Number of directory scans since the cache was created.
+    pub(crate) scans: usize,
+    // the index on disk, `None` to keep the cache in memory only
+    disk: Option<PathBuf>,
+    disk_read: bool,
+    // scanned something the disk index doesn't have yet
+    dirty: bool,
+}
+
+impl Cache {
+    /// A cache starting from, and saved to, the index at `disk`.
+    pub(crate) fn on_disk(disk: Option<PathBuf>) -> Self {
+        Cache { disk, ..Cache::default() }
+    }
+
+    /// A cache saved to the index at `disk` but not starting from it: every
+    /// directory is scanned again, and the index written over.
+    pub(crate) fn rebuilding(disk: Option<PathBuf>) -> Self {
+        Cache { disk_read: true, dirty: true, ..Cache::on_disk(disk) }
+    }
+
+    /// The parsed apps of `dir`, scanning it again only if it changed.
+    pub(crate) fn dir_apps(&mut self, dir: &Path, options: &ScanOptions) -> Result<&[AppInfo]> {
+        if !self.disk_read {
+            self.disk_read = true;
+            self.read_disk();
+        }
+        if self.options != *options {
+            self.dirs.clear();
+            self.options = options.clone();
+        }
+
+        let mtime = std::fs::metadata(dir)?.modified()?;
+        let fresh = self.dirs.get(dir).is_some_and(|cached| cached.is_fresh(mtime));
+        if !fresh {
+            let scan = crate::scan_dir(dir, options)?;
+            self.files_read += scan.files_read;
+            self.scans += 1;
+            let cached = CachedDir { mtime, subdirs: scan.subdirs, apps: scan.apps };
+            self.dirs.insert(dir.to_path_buf(), cached);
+            self.dirty = true;
+        }
+        Ok(&self.dirs[dir].apps)
+    }
+
+    /// The apps of `dir` as last returned by `dir_apps`, none if it never was.
+    pub(crate) fn cached_apps(&self, dir: &Path) -> &[AppInfo] {
+        self.dirs.get(dir).map_or(&[], |cached| &cached.apps)
+    }
+
+    /// The subdirectories last scanned in `dir`, none unless scans are recursive.
+    pub(crate) fn subdirs(&self, dir: &Path) -> Vec<PathBuf> {
+        self.dirs.get(dir).map_or_else(Vec::new, |cached| {
+            cached.subdirs.iter().map(|(subdir, _)| subdir.clone()).collect()
+        })
+    }
+
+    /// Forgets `dir`, so the next lookup scans it again whatever its mtime.
+    pub(crate) fn invalidate(&mut self, dir: &Path) {
+        self.dirs.remove(dir);
+    }
+
+    /// Starts from the disk index, if there is a valid one.
+    fn read_disk(&mut self) {
+        let Some(path) = &self.disk else {
+            return;
+        };
+        let Ok(bytes) = fs::read(path) else {
+            return;
+        };
+        match bincode::serde::decode_from_slice::<DiskIndex, _>(&bytes, bincode::config::standard()) {
+            Ok(((DISK_FORMAT, options, dirs), _)) => {
+                self.options = options;
+                self.dirs = dirs
+                    .into_iter()
+                    .map(|(dir, mut cached)| {
+                        let apps = std::mem::take(&mut cached.apps);
+                        cached.apps = apps.into_iter().map(AppInfo::index).collect();
+                        (dir, cached)
+                    })
+                    .collect();
+            }
+            // rebuilt by the scans, and written over on the next `persist`
+            Ok(_) => log_debug!("Ignoring the index {} of another version", path.display()),
+            Err(e) => log_debug!("Ignoring the unreadable index {}: {}", path.display(), e),
+        }
+    }
+
+    /// Writes the entries to the disk index if scans changed them.
+    pub(crate) fn persist(&mut self) {
+        let Some(path) = &self.disk else {
+            return;
+        };
+        if !self.dirty {
+            return;
+        }
+        // not retried on every load when the write fails
+        self.dirty = false;
+        if let Err(e) = write_index(path, &self.options, &self.dirs) {
+            log_warn!("Failed to save the index {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn write_index(path: &Path, options: &ScanOptions, dirs: &HashMap<PathBuf, CachedDir>) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let dirs: Vec<_> = dirs.iter().collect();
+    let tmp = path.with_extension("bin.tmp");
+    let bytes = bincode::serde::encode_to_vec((DISK_FORMAT, options, dirs), bincode::config::standard())?;
+    fs::write(&tmp, bytes)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(not(test))]
+fn index_path() -> Option<PathBuf> {
+    xdg::BaseDirectories::with_prefix("applist")
+        .get_cache_home()
+        .map(|dir| dir.join("index.bin"))
+}
+
+/// Where tests keep the shared cache's index, none unless they set one: they
+/// must never touch the user's real cache.
+#[cfg(test)]
+pub(crate) static TEST_INDEX: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+#[cfg(test)]
+fn index_path() -> Option<PathBuf> {
+    TEST_INDEX.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// The cache shared by all exports.
+pub(crate) fn global() -> &'static Mutex<Cache> {
+    static CACHE: OnceLock<Mutex<Cache>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(Cache::on_disk(index_path())))
+}
+
+/// Drops everything in the shared cache, the disk index included: the next
+/// lookup scans every directory again.
+pub(crate) fn clear() {
+    *global().lock().unwrap_or_else(|e| e.into_inner()) = Cache::rebuilding(index_path());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDir;
+
+    #[test]
+    fn unchanged_directories_are_not_read_again() {
+        let dir = TestDir::new("cache");
+        dir.write("a.desktop", "[Desktop Entry]\nExec=true\nName=A\n");
+        dir.write("b.desktop", "[Desktop Entry]\nExec=true\nName=B\n");
+        let mut cache = Cache::default();
+        let options = ScanOptions::default();
+
+        assert_eq!(cache.dir_apps(dir.path(), &options).unwrap().len(), 2);
+        assert_eq!(cache.files_read, 2);
+        assert_eq!(cache.dir_apps(dir.path(), &options).unwrap().len(), 2);
+        assert_eq!(cache.files_read, 2);
+
+        // a new file bumps the directory mtime
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        dir.write("c.desktop", "[Desktop Entry]\nExec=true\nName=C\n");
+        assert_eq!(cache.dir_apps(dir.path(), &options).unwrap().len(), 3);
+        assert_eq!(cache.files_read, 5);
+    }
+
+    #[test]
+    fn changing_locale_invalidates() {
+        let dir = TestDir::new("cache-locale");
+        dir.write("a.desktop", "[Desktop Entry]\nExec=true\nName=Files\nName[fr]=Fichiers\n");
+        let mut cache = Cache::default();
+
+        assert_eq!(cache.dir_apps(dir.path(), &ScanOptions::default()).unwrap()[0].name, "Files");
+        let fr = ScanOptions {
+            locales: vec!["fr".to_string()],
+            ..ScanOptions::default()
+        };
+        assert_eq!(cache.dir_apps(dir.path(), &fr).unwrap()[0].name, "Fichiers");
+        assert_eq!(cache.files_read, 2);
+    }
+
+    fn index_dir(name: &str) -> (TestDir, PathBuf) {
+        let dir = TestDir::new(name);
+        dir.write("apps/a.desktop", "[Desktop Entry]\nExec=true\nName=Files\nKeywords=folder;\n");
+        dir.write("apps/b.desktop", "[Desktop Entry]\nExec=true\nName=B\n");
+        let index = dir.path().join("cache/index.bin");
+        (dir, index)
+    }
+
+    #[test]
+    fn disk_index_reused_while_fresh() {
+        let (dir, index) = index_dir("cache-disk-fresh");
+        let apps = dir.path().join("apps");
+        let options = ScanOptions::default();
+        let mut cache = Cache::on_disk(Some(index.clone()));
+        assert_eq!(cache.dir_apps(&apps, &options).unwrap().len(), 2);
+        cache.persist();
+        assert!(index.exists());
+
+        let mut cache = Cache::on_disk(Some(index.clone()));
+        let read = cache.dir_apps(&apps, &options).unwrap();
+        assert_eq!(read.len(), 2);
+        // searchable again, the keys aren't stored
+        let files = read.iter().find(|app| app.name == "Files").unwrap();
+        let query = crate::matcher::Query::new("folder");
+        let (mode, weights) = (crate::config::MatchMode::Fuzzy, Default::default());
+        assert!(crate::matcher::rank_app(&query, files, mode, &weights).is_some());
+        assert_eq!(cache.files_read, 0);
+        // nothing new to write
+        fs::remove_file(&index).unwrap();
+        cache.persist();
+        assert!(!index.exists());
+    }
+
+    #[test]
+    fn disk_index_rescans_changed_directories() {
+        let (dir, index) = index_dir("cache-disk-stale");
+        let apps = dir.path().join("apps");
+        let options = ScanOptions::default();
+        let mut cache = Cache::on_disk(Some(index.clone()));
+        cache.dir_apps(&apps, &options).unwrap();
+        cache.persist();
+
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        dir.write("apps/c.desktop", "[Desktop Entry]\nExec=true\nName=C\n");
+        let mut cache = Cache::on_disk(Some(index.clone()));
+        assert_eq!(cache.dir_apps(&apps, &options).unwrap().len(), 3);
+        assert_eq!(cache.files_read, 3);
+        cache.persist();
+
+        // other options don't reuse the index either
+        let fr = ScanOptions { locales: vec!["fr".to_string()], ..ScanOptions::default() };
+        let mut cache = Cache::on_disk(Some(index.clone()));
+        cache.dir_apps(&apps, &fr).unwrap();
+        assert_eq!(cache.files_read, 3);
+        let mut cache = Cache::on_disk(Some(index));
+        cache.dir_apps(&apps, &fr).unwrap();
+        assert_eq!(cache.files_read, 3);
+    }
+
+    #[test]
+    fn corrupt_disk_index_is_rebuilt() {
+        let (dir, index) = index_dir("cache-disk-corrupt");
+        let apps = dir.path().join("apps");
+        let options = ScanOptions::default();
+        dir.write("cache/index.bin", "not an index");
+        let mut cache = Cache::on_disk(Some(index.clone()));
+        assert_eq!(cache.dir_apps(&apps, &options).unwrap().len(), 2);
+        assert_eq!(cache.files_read, 2);
+        cache.persist();
+
+        let mut cache = Cache::on_disk(Some(index.clone()));
+        assert_eq!(cache.dir_apps(&apps, &options).unwrap().len(), 2);
+        assert_eq!(cache.files_read, 0);
+
+        // a valid index of another version is ignored just the same
+        let config = bincode::config::standard();
+        let stored = fs::read(&index).unwrap();
+        let ((format, scanned_with, dirs), _): (DiskIndex, _) =
+            bincode::serde::decode_from_slice(&stored, config).unwrap();
+        let other = bincode::serde::encode_to_vec((format + 1, scanned_with, dirs), config).unwrap();
+        fs::write(&index, other).unwrap();
+        let mut cache = Cache::on_disk(Some(index));
+        cache.dir_apps(&apps, &options).unwrap();
+        assert_eq!(cache.files_read, 2);
+    }
+
+    #[test]
+    fn rebuilding_ignores_the_disk_index() {
+        let (dir, index) = index_dir("cache-disk-rebuild");
+        let apps = dir.path().join("apps");
+        let options = ScanOptions::default();
+        let mut cache = Cache::on_disk(Some(index.clone()));
+        cache.dir_apps(&apps, &options).unwrap();
+        cache.persist();
+
+        // rewritten in place, which leaves the directory mtime alone
+        dir.write("apps/b.desktop", "[Desktop Entry]\nExec=true\nName=Edited\n");
+        let names = |cache: &mut Cache| -> Vec<String> {
+            let mut names: Vec<String> =
+                cache.dir_apps(&apps, &options).unwrap().iter().map(|app| app.name.clone()).collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names(&mut Cache::on_disk(Some(index.clone()))), vec!["B", "Files"]);
+        let mut cache = Cache::rebuilding(Some(index.clone()));
+        assert_eq!(names(&mut cache), vec!["Edited", "Files"]);
+        assert_eq!(cache.files_read, 2);
+        // and saved for the next session
+        cache.persist();
+        assert_eq!(names(&mut Cache::on_disk(Some(index))), vec!["Edited", "Files"]);
+    }
+}
@@ -0,0 +1,109 @@
+//! Alphabetical order of app names, for the empty query.
+//!
+//! The Unicode Collation Algorithm through ICU4X, with CLDR's tailoring for
+//! the locale: letters compare by their base letter first, accents and case
+//! only break ties, and the languages that treat some accented letters as
+//! letters of their own (`å` after `z` in Swedish, `ñ` after `n` in Spanish)
+//! get them sorted where their alphabet puts them. Unknown locales get the
+//! root order.
+
+use crate::AppInfo;
+use icu_collator::CollatorBorrowed;
+use icu_collator::options::CollatorOptions;
+use icu_locale_core::Locale;
+
+/// Compares names for one locale.
+pub(crate) struct Collator {
+    collator: CollatorBorrowed<'static>,
+}
+
+impl Default for Collator {
+    fn default() -> Self {
+        Collator::for_locale(&Locale::UNKNOWN)
+    }
+}
+
+impl Collator {
+    /// The collator for a POSIX locale like `sv_SE.UTF-8`, with the root
+    /// order for `C` and the languages without a tailoring.
+    pub(crate) fn new(locale: &str) -> Self {
+        let tag = locale.split(['.', '@']).next().unwrap_or_default().replace('_', "-");
+        Collator::for_locale(&Locale::try_from_str(&tag).unwrap_or(Locale::UNKNOWN))
+    }
+
+    fn for_locale(locale: &Locale) -> Self {
+        // the data is compiled in, a locale without its own falls back to the root
+        let collator = CollatorBorrowed::try_new(locale.into(), CollatorOptions::default());
+        Collator { collator: collator.expect("compiled collation data") }
+    }
+
+    /// The collator of the process's `LC_COLLATE` locale.
+    pub(crate) fn current() -> Self {
+        let locale = ["LC_ALL", "LC_COLLATE", "LANG"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|value| !value.is_empty());
+        Collator::new(&locale.unwrap_or_default())
+    }
+
+    /// The collation sort key of `s`, comparing bytewise like the strings.
+    fn key(&self, s: &str) -> Vec<u8> {
+        let mut key = Vec::new();
+        let Ok(()) = self.collator.write_sort_key_to(s, &mut key);
+        key
+    }
+
+    /// Key sorting apps by name. The name itself breaks ties, so the order
+    /// doesn't depend on the input order.
+    pub(crate) fn sort_key(&self, app: &AppInfo) -> (Vec<u8>, String) {
+        (self.key(app.display_name()), app.display_name().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(locale: &str, names: &[&str]) -> Vec<String> {
+        let collator = Collator::new(locale);
+        let mut names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        names.sort_by_cached_key(|name| (collator.key(name), name.clone()));
+        names
+    }
+
+    const NAMES: &[&str] = &[
+        "Zebra", "Яндекс", "Ångström", "apple", "Écran", "Øvelse", "Ελληνικά", "Straße",
+        "Stream", "Ärger", "Ёлка", "Ель", "Nuñez", "Nuzzle", "Kalkulator",
+    ];
+
+    #[test]
+    fn root_order() {
+        assert_eq!(
+            sorted("C", NAMES),
+            vec![
+                "Ångström", "apple", "Ärger", "Écran", "Kalkulator", "Nuñez", "Nuzzle", "Øvelse",
+                "Straße", "Stream", "Zebra", "Ελληνικά", "Ёлка", "Ель", "Яндекс",
+            ]
+        );
+        // decomposed accents sort like precomposed ones
+        let collator = Collator::default();
+        assert_eq!(collator.key("A\u{30a}ngstro\u{308}m"), collator.key("Ångström"));
+        assert_eq!(sorted("", &["Aft", "ÆSIR", "Adz"]), vec!["Adz", "ÆSIR", "Aft"]);
+    }
+
+    #[test]
+    fn tailored_orders() {
+        let swedish = sorted("sv_SE.UTF-8", &["Ångström", "apple", "Ärger", "Øvelse", "Zebra", "Önska"]);
+        // ø is the Danish spelling of ö
+        assert_eq!(swedish, vec!["apple", "Zebra", "Ångström", "Ärger", "Önska", "Øvelse"]);
+        let danish = sorted("da_DK", &["Ångström", "apple", "Øvelse", "Zebra", "Æble"]);
+        assert_eq!(danish, vec!["apple", "Zebra", "Æble", "Øvelse", "Ångström"]);
+        let spanish = sorted("es_ES", &["Nuzzle", "Nuñez", "Nunca", "Oso"]);
+        assert_eq!(spanish, vec!["Nunca", "Nuñez", "Nuzzle", "Oso"]);
+        // German keeps umlauts with their base letter, like the root order
+        assert_eq!(sorted("de_DE", &["Zebra", "Ärger", "Apfel"]), vec!["Apfel", "Ärger", "Zebra"]);
+        assert_eq!(sorted("pl_PL", &["Łódź", "Lublin", "Mielec"]), vec!["Lublin", "Łódź", "Mielec"]);
+        assert!(Collator::new("sv").key("Å") > Collator::new("sv").key("Z"));
+        assert!(Collator::new("").key("Å") < Collator::new("").key("Z"));
+    }
+}
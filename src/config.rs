@@ -0,0 +1,320 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+/// How `get_entries` orders apps when the query is empty.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub(crate) enum SortMode {
+    /// By name, ignoring case and accents.
+    #[default]
+    Alphabetical,
+    /// Most launched first, then by name.
+    Frecency,
+    /// In the order the desktop files were found.
+    None,
+}
+
+/// Which candidates a query matches, before ranking.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub(crate) enum MatchMode {
+    /// The name, or one of its words, starts with the query.
+    Prefix,
+    /// The name contains the query.
+    Substring,
+    /// The query's characters appear in the name in order.
+    #[default]
+    Fuzzy,
+}
+
+/// What a match in each field is worth, in percent of a name match, and
+/// `frecency` how much launches count, in percent of their usual boost.
+///
+/// A field weighing 0 isn't searched at all, its matches don't list an app.
+/// Negative weights count as 0, and none counts for more than `MAX`. A
+/// `frecency` of 0 only takes launches out of the ranking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub(crate) struct Weights {
+    pub name: i64,
+    pub generic_name: i64,
+    pub keywords: i64,
+    pub comment: i64,
+    /// The program `Exec` runs.
+    pub exec: i64,
+    /// The desktop ID, e.g. `org.mozilla.firefox`.
+    pub id: i64,
+    pub frecency: i64,
+}
+
+impl Weights {
+    pub(crate) const MAX: i64 = 1000;
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Weights {
+            name: 100,
+            generic_name: 85,
+            keywords: 70,
+            comment: 40,
+            // the command is what some users remember, but any name match beats it
+            exec: 30,
+            // for those who know the reverse-DNS name, never above a real field
+            id: 20,
+            frecency: 100,
+        }
+    }
+}
+
+/// A command run in front of the apps it matches, see `launch_wrappers`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub(crate) struct LaunchWrapper {
+    /// Desktop ID or category of the apps wrapped, e.g. `"Game"`.
+    #[serde(rename = "match")]
+    pub target: String,
+    /// Prepended to their command line, e.g. `"mangohud --dlsym"`.
+    pub command: String,
+}
+
+/// User settings, deserialized from the JSON the host passes to `init_config`.
+///
+/// Every field is optional in the JSON, missing ones take their default.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub(crate) struct Config {
+    /// Terminal used for `Terminal=true` apps instead of guessing one, the
+    /// app's command is appended to it as-is (e.g. `"foot"`, `"wezterm start --"`).
+    pub terminal_command: Option<String>,
+    /// Also list apps marked `NoDisplay=true`, like MIME handlers, so they can
+    /// be searched. `Hidden=true` ones are deleted and never listed.
+    /// `show_hidden` is the old name.
+    #[serde(alias = "show_hidden")]
+    pub include_nodisplay: bool,
+    /// Cap on the number of entries `get_entries` returns, 50 by default.
+    pub max_results: usize,
+    /// Add the command and the path of the desktop file to entry descriptions,
+    /// to tell which file an entry comes from and what it runs.
+    pub show_source_in_description: bool,
+    /// Size in pixels the host draws icons at, 48 by default. Icons resolve
+    /// to the installed size nearest to it, or to a scalable one when that
+    /// exact size isn't installed.
+    pub preferred_icon_size: u16,
+    /// Icon theme to resolve icons in, e.g. `"Papirus"`. Unset by default,
+    /// taking the one set for the desktop: in `kdeglobals` on KDE Plasma, in
+    /// GTK's `settings.ini` elsewhere.
+    pub icon_theme: Option<String>,
+    /// Apps first listed less than this many days ago get their description
+    /// prefixed with "New", for hosts to badge them. 0, the default, disables it.
+    pub recently_added_days: u64,
+    /// Directories scanned for desktop files after the XDG ones.
+    pub extra_search_paths: Vec<PathBuf>,
+    /// Directories scanned instead of the XDG and Flatpak ones, the extra
+    /// paths still following them. Unset by default.
+    pub override_search_paths: Option<Vec<PathBuf>>,
+    /// Also read desktop files in subdirectories of the scanned directories,
+    /// like `applications/kde4/`, a few levels deep.
+    pub recursive_scan: bool,
+    /// Read desktop files missing their `[Desktop Entry]` header, taking the
+    /// keys at the top of the file for the entry, and name the entries
+    /// without a `Name` after their file. Off by default, like the spec, such
+    /// files aren't listed.
+    pub lenient_parsing: bool,
+    /// Desktop files under these directories are never listed, the system
+    /// temp dir by default, where downloads and build artifacts pass through.
+    pub blocklist_path_prefixes: Vec<PathBuf>,
+    /// Watch the scanned directories with inotify so changes show up on the
+    /// next query, even files edited in place.
+    pub watch_directories: bool,
+    /// Launch apps through `systemd-run --user --scope` when it's installed,
+    /// giving each its own cgroup scope that outlives the host.
+    pub use_systemd_scope: bool,
+    /// Order of the results for an empty query.
+    pub sort_mode: SortMode,
+    /// Check desktop files with `desktop-file-validate` when they are read,
+    /// and refuse to launch invalid ones. Skipped if the tool isn't installed.
+    pub validate_desktop_files: bool,
+    /// Directories searched for standalone `.AppImage` files, e.g.
+    /// `~/Applications`. None by default.
+    pub appimage_dirs: Vec<PathBuf>,
+    /// How strictly queries have to match, all modes ignore case and accents.
+    pub match_mode: MatchMode,
+    /// Queries starting with one of these are meant for another plugin, e.g.
+    /// `["=", "!"]`, and get no entries without even looking at the apps.
+    pub ignore_query_prefixes: Vec<String>,
+    /// Queries shorter than this many characters get no entries, so a
+    /// single keystroke doesn't list half the apps. The empty query still
+    /// lists them all. 0, the default, allows any length.
+    pub min_query_length: usize,
+    /// Also search Chinese names by their pinyin, `liulanqi` finding 浏览器.
+    /// Needs the plugin built with the `pinyin` feature, ignored otherwise.
+    pub pinyin_search: bool,
+    /// Edits a query may be away from an app name to still find it when
+    /// few apps match, 1 by default. 0 turns typo tolerance off.
+    pub max_typos: usize,
+    /// Queries matching fewer apps than this also list the apps within
+    /// `max_typos` of them, after the matches. 1 by default, only looking for
+    /// typos when nothing matches.
+    pub typo_fallback_below: usize,
+    /// How much each field counts in the ranking, e.g.
+    /// `{"keywords": 120}` to put keyword matches above name matches.
+    pub weights: Weights,
+    /// Desktop IDs of apps never listed, e.g. `"org.gnome.Settings.desktop"`.
+    pub hidden_ids: Vec<String>,
+    /// Display names of apps never listed, compared exactly.
+    pub hidden_names: Vec<String>,
+    /// Rewrites of the names shown, as `[pattern, replacement]` pairs applied
+    /// in order, e.g. `[[" (GTK)", ""], ["re: — .*$", ""]]` (see `rename`).
+    /// Searching still goes by the names in the desktop files.
+    pub name_replacements: Vec<(String, String)>,
+    /// Apps in any of these `Categories` are never listed, e.g. `["Settings"]`.
+    pub excluded_categories: Vec<String>,
+    /// When set, only apps in at least one of these categories are listed.
+    pub included_categories: Option<Vec<String>>,
+    /// Apps in any of these `Categories` rank after all the others, whatever
+    /// they score, e.g. `["Settings"]` so the many settings panels don't
+    /// crowd out the apps. None by default.
+    pub demoted_categories: Vec<String>,
+    /// Vendors whose `<prefix>-Keywords` keys are searched along `Keywords`,
+    /// `["X-GNOME", "X-KDE"]` by default.
+    pub keyword_prefixes: Vec<String>,
+    /// Emoji shown for apps without an icon, by category, on top of the
+    /// built-in table (e.g. `{"Game": "👾"}`). An empty string disables one.
+    pub category_emoji: HashMap<String, String>,
+    /// Commands launching the apps they match, e.g.
+    /// `[{"match": "Game", "command": "gamemoderun"}]`. All those matching
+    /// an app stack, the first outermost. Wrapped apps always run their
+    /// `Exec` line, D-Bus activation and `gtk-launch` would bypass the wrapper.
+    pub launch_wrappers: Vec<LaunchWrapper>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            terminal_command: None,
+            include_nodisplay: false,
+            max_results: 50,
+            show_source_in_description: false,
+            preferred_icon_size: 48,
+            icon_theme: None,
+            recently_added_days: 0,
+            extra_search_paths: Vec::new(),
+            override_search_paths: None,
+            recursive_scan: false,
+            lenient_parsing: false,
+            blocklist_path_prefixes: vec![std::env::temp_dir()],
+            watch_directories: false,
+            use_systemd_scope: false,
+            sort_mode: SortMode::default(),
+            validate_desktop_files: false,
+            appimage_dirs: Vec::new(),
+            match_mode: MatchMode::default(),
+            ignore_query_prefixes: Vec::new(),
+            min_query_length: 0,
+            max_typos: 1,
+            typo_fallback_below: 1,
+            pinyin_search: false,
+            weights: Weights::default(),
+            hidden_ids: Vec::new(),
+            hidden_names: Vec::new(),
+            name_replacements: Vec::new(),
+            excluded_categories: Vec::new(),
+            included_categories: None,
+            demoted_categories: Vec::new(),
+            keyword_prefixes: vec!["X-GNOME".to_string(), "X-KDE".to_string()],
+            category_emoji: HashMap::new(),
+            launch_wrappers: Vec::new(),
+        }
+    }
+}
+
+// read on every call, written by `init_config`
+static CONFIG: RwLock<Option<Arc<Config>>> = RwLock::new(None);
+
+/// Parses a config JSON string, a blank string meaning "all defaults".
+pub(crate) fn parse(json: &str) -> Result<Config> {
+    if json.trim().is_empty() {
+        return Ok(Config::default());
+    }
+    Ok(serde_json::from_str(json)?)
+}
+
+pub(crate) fn set(config: Config) {
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(config));
+}
+
+/// Forgets the stored config, `current` returns the defaults until the next
+/// `set`.
+pub(crate) fn clear() {
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// The stored config, or the defaults when `init_config` wasn't called.
+pub(crate) fn current() -> Arc<Config> {
+    CONFIG
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .unwrap_or_default()
+}
+
+/// Serializes tests that touch the global config.
+#[cfg(test)]
+pub(crate) fn test_lock() -> std::sync::MutexGuard<'static, ()> {
+    static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_full_config() {
+        let config = parse(
+            r#"{
+                "terminal_command": "foot",
+                "include_nodisplay": true,
+                "max_results": 10,
+                "extra_search_paths": ["/opt/apps"],
+                "override_search_paths": ["/srv/apps"],
+                "sort_mode": "Frecency",
+                "match_mode": "Prefix"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.terminal_command.as_deref(), Some("foot"));
+        assert!(config.include_nodisplay);
+        assert!(parse(r#"{"show_hidden": true}"#).unwrap().include_nodisplay);
+        assert_eq!(config.max_results, 10);
+        assert_eq!(config.extra_search_paths, vec![PathBuf::from("/opt/apps")]);
+        assert_eq!(config.override_search_paths, Some(vec![PathBuf::from("/srv/apps")]));
+        assert_eq!(config.sort_mode, SortMode::Frecency);
+        assert_eq!(config.match_mode, MatchMode::Prefix);
+    }
+
+    #[test]
+    fn partial_weights() {
+        let config = parse(r#"{"weights": {"keywords": 120, "exec": -5}}"#).unwrap();
+        assert_eq!(config.weights.keywords, 120);
+        assert_eq!(config.weights.exec, -5);
+        assert_eq!(config.weights.name, 100);
+        assert_eq!(parse("{}").unwrap().weights, Weights::default());
+        assert!(parse(r#"{"weights": {"name": "high"}}"#).is_err());
+    }
+
+    #[test]
+    fn parse_defaults_and_errors() {
+        let config = parse("{}").unwrap();
+        assert!(!config.include_nodisplay);
+        assert_eq!(config.max_results, 50);
+        assert!(config.override_search_paths.is_none());
+        assert_eq!(config.sort_mode, SortMode::Alphabetical);
+        assert!(parse("").is_ok());
+
+        assert!(parse("{not json").is_err());
+        assert!(parse(r#"{"max_results": "many"}"#).is_err());
+        assert!(parse(r#"{"sort_mode": "Random"}"#).is_err());
+    }
+}
@@ -0,0 +1,188 @@
+//! D-Bus activation of `DBusActivatable=true` apps, through the
+//! `org.freedesktop.Application` interface of the Desktop Entry spec.
+//!
+//! Calls go through `gdbus` like other launches go through `gtk-launch` and
+//! `gio`, which keeps a D-Bus client out of the plugin.
+
+use std::process::Command;
+
+/// The well-known bus name of an app: its desktop ID without `.desktop`,
+/// when that is a valid bus name.
+pub(crate) fn bus_name(desktop_id: &str) -> Option<&str> {
+    let name = desktop_id.strip_suffix(".desktop")?;
+    let elements: Vec<&str> = name.split('.').collect();
+    let valid = name.len() <= 255
+        && elements.len() >= 2
+        && elements.iter().all(|element| {
+            !element.is_empty()
+                && !element.starts_with(|c: char| c.is_ascii_digit())
+                && element.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        });
+    valid.then_some(name)
+}
+
+/// The object path of an app: its bus name with `.` turned into `/` and `-`
+/// into `_`.
+pub(crate) fn object_path(bus_name: &str) -> String {
+    format!("/{}", bus_name.replace('.', "/").replace('-', "_"))
+}
+
+/// Quotes `s` as a GVariant text format string.
+fn gvariant_string(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+/// Turns a local path into a `file://` URI, leaving URIs as they are.
+fn to_uri(file: &str) -> String {
+    if !file.starts_with('/') {
+        return file.to_string();
+    }
+    let mut uri = String::from("file://");
+    for byte in file.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    uri
+}
+
+/// The `platform-data` dictionary of a call, passing the startup
+/// notification `token` if there is one.
+fn platform_data(token: Option<&str>) -> String {
+    match token {
+        Some(token) => {
+            let token = gvariant_string(token);
+            format!("{{'activation-token': <{}>, 'desktop-startup-id': <{}>}}", token, token)
+        }
+        None => "{}".to_string(),
+    }
+}
+
+/// The `gdbus` command line activating the app with desktop ID `desktop_id`,
+/// `None` if that isn't a valid bus name.
+pub(crate) fn command(desktop_id: &str, files: &[String], token: Option<&str>) -> Option<Vec<String>> {
+    let bus_name = bus_name(desktop_id)?;
+    Some(std::iter::once("gdbus".to_string()).chain(call_args(bus_name, files, token)).collect())
+}
+
+/// The `gdbus` arguments calling `Activate`, or `Open` when there are files.
+fn call_args(bus_name: &str, files: &[String], token: Option<&str>) -> Vec<String> {
+    let mut args: Vec<String> = [
+        "call",
+        "--session",
+        "--dest",
+        bus_name,
+        "--object-path",
+        &object_path(bus_name),
+        "--method",
+    ]
+    .iter()
+    .map(|arg| arg.to_string())
+    .collect();
+    if files.is_empty() {
+        args.push("org.freedesktop.Application.Activate".to_string());
+    } else {
+        args.push("org.freedesktop.Application.Open".to_string());
+        let uris: Vec<String> = files.iter().map(|file| gvariant_string(&to_uri(file))).collect();
+        args.push(format!("[{}]", uris.join(", ")));
+    }
+    args.push(platform_data(token));
+    args
+}
+
+fn activate_with(gdbus: &str, bus_name: &str, files: &[String], token: Option<&str>) -> bool {
+    match Command::new(gdbus).args(call_args(bus_name, files, token)).output() {
+        Ok(output) if output.status.success() => true,
+        Ok(output) => {
+            log_warn!(
+                "D-Bus activation of {} failed: {}",
+                bus_name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            false
+        }
+        Err(e) => {
+            log_warn!("Failed to run {}: {}", gdbus, e);
+            false
+        }
+    }
+}
+
+/// Activates the app with desktop ID `desktop_id` over the session bus,
+/// asking it to open `files` if any. `token` is the startup notification
+/// token to hand it. Returns whether the call succeeded.
+#[cfg(not(test))]
+pub(crate) fn activate(desktop_id: &str, files: &[String], token: Option<&str>) -> bool {
+    match bus_name(desktop_id) {
+        Some(bus_name) => activate_with("gdbus", bus_name, files, token),
+        None => {
+            log_warn!("{} is not a valid bus name, can't activate it", desktop_id);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDir;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn bus_names_from_desktop_ids() {
+        assert_eq!(bus_name("org.gnome.Nautilus.desktop"), Some("org.gnome.Nautilus"));
+        assert_eq!(bus_name("org.example.my-app.desktop"), Some("org.example.my-app"));
+        assert_eq!(bus_name("firefox.desktop"), None);
+        assert_eq!(bus_name("org.2048.Game.desktop"), None);
+        assert_eq!(bus_name("org..Empty.desktop"), None);
+        assert_eq!(bus_name("org.gnome.Nautilus"), None);
+        assert_eq!(object_path("org.example.my-app"), "/org/example/my_app");
+    }
+
+    #[test]
+    fn open_passes_uris() {
+        let files = vec!["/home/me/a b.txt".to_string(), "https://example.com/it's".to_string()];
+        let args = call_args("org.gnome.TextEditor", &files, None);
+        assert_eq!(args[args.len() - 3], "org.freedesktop.Application.Open");
+        assert_eq!(
+            args[args.len() - 2],
+            r"['file:///home/me/a%20b.txt', 'https://example.com/it\'s']"
+        );
+        assert_eq!(args.last().unwrap(), "{}");
+        let activate = call_args("org.gnome.TextEditor", &[], Some("token-1"));
+        assert_eq!(activate[activate.len() - 2], "org.freedesktop.Application.Activate");
+        assert_eq!(
+            activate.last().unwrap(),
+            "{'activation-token': <'token-1'>, 'desktop-startup-id': <'token-1'>}"
+        );
+    }
+
+    #[test]
+    fn activation_through_stubbed_gdbus() {
+        let dir = TestDir::new("dbus");
+        let log = dir.path().join("calls");
+        let stub = dir.write(
+            "gdbus",
+            &format!(
+                "#!/bin/sh\necho \"$@\" >> '{}'\n[ \"$4\" = org.example.Running ]\n",
+                log.display()
+            ),
+        );
+        std::fs::set_permissions(&stub, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let stub = stub.to_string_lossy();
+
+        assert!(activate_with(&stub, "org.example.Running", &[], None));
+        // the app isn't on the bus, the caller falls back to another launcher
+        assert!(!activate_with(&stub, "org.example.Missing", &[], None));
+        assert!(!activate_with("/nonexistent/gdbus", "org.example.Running", &[], None));
+
+        let calls = std::fs::read_to_string(&log).unwrap();
+        assert_eq!(
+            calls.lines().next().unwrap(),
+            "call --session --dest org.example.Running --object-path /org/example/Running \
+             --method org.freedesktop.Application.Activate {}"
+        );
+    }
+}
@@ -0,0 +1,72 @@
+//! Emoji standing in for missing icons, picked from the app's `Categories`.
+
+use std::collections::HashMap;
+
+/// Emoji for the main categories of the freedesktop menu spec, plus the
+/// additional categories common enough to deserve their own.
+const DEFAULTS: &[(&str, &str)] = &[
+    ("AudioVideo", "🎬"),
+    ("Audio", "🎵"),
+    ("Video", "🎬"),
+    ("Development", "🛠️"),
+    ("Education", "🎓"),
+    ("Game", "🎮"),
+    ("Graphics", "🎨"),
+    ("Network", "🌐"),
+    ("WebBrowser", "🌐"),
+    ("Email", "📧"),
+    ("Office", "📄"),
+    ("Science", "🔬"),
+    ("Settings", "⚙️"),
+    ("System", "🖥️"),
+    ("TerminalEmulator", "💻"),
+    ("Utility", "🧰"),
+];
+
+/// The emoji of the first of `categories` that has one, `overrides` taking
+/// precedence over the defaults. An empty override removes a default.
+pub(crate) fn for_categories(
+    categories: &[String],
+    overrides: &HashMap<String, String>,
+) -> Option<String> {
+    categories.iter().find_map(|category| {
+        let emoji = match overrides.get(category) {
+            Some(emoji) => emoji.as_str(),
+            None => DEFAULTS.iter().find(|(name, _)| name == category)?.1,
+        };
+        (!emoji.is_empty()).then(|| emoji.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn categories(list: &str) -> Vec<String> {
+        crate::split_desktop_list(list)
+    }
+
+    #[test]
+    fn first_known_category_wins() {
+        let none = HashMap::new();
+        assert_eq!(for_categories(&categories("Game;"), &none).as_deref(), Some("🎮"));
+        assert_eq!(
+            for_categories(&categories("X-Custom;Development;IDE;"), &none).as_deref(),
+            Some("🛠️")
+        );
+        assert_eq!(for_categories(&categories("X-Custom;"), &none), None);
+        assert_eq!(for_categories(&[], &none), None);
+    }
+
+    #[test]
+    fn overrides_replace_defaults() {
+        let overrides = HashMap::from([
+            ("Game".to_string(), "👾".to_string()),
+            ("Utility".to_string(), String::new()),
+            ("X-Custom".to_string(), "⭐".to_string()),
+        ]);
+        assert_eq!(for_categories(&categories("Game;"), &overrides).as_deref(), Some("👾"));
+        assert_eq!(for_categories(&categories("Utility;"), &overrides), None);
+        assert_eq!(for_categories(&categories("X-Custom;"), &overrides).as_deref(), Some("⭐"));
+    }
+}
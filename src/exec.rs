@@ -0,0 +1,415 @@
+//! Parsing and expansion of the `Exec` key, following the Desktop Entry spec.
+//!
+//! Parsing happens in two passes like the spec describes: first the generic
+//! string escapes (`\s`, `\n`, `\t`, `\r`, `\\`) are undone, then the value is
+//! split into arguments honoring double quotes and the `\"`, `` \` ``, `\$`,
+//! `\\` escapes that are allowed inside them.
+//!
+//! Environment variables aren't part of the spec, but `$HOME` and the like
+//! show up in the wild, so launches expand the unescaped `$VAR` and `${VAR}`.
+
+/// Undoes the escape sequences allowed in any desktop entry string value.
+pub(crate) fn unescape_value(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => out.push(' '),
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('\\') => out.push('\\'),
+            // unknown escapes are kept as-is so the quoting pass can see them
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Looks up an environment variable, `std::env::var` at launch.
+pub(crate) type Lookup<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+fn is_var_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Reads the `NAME` or `{NAME}` following a `$`, leaving `chars` untouched
+/// when there's no variable name there.
+fn var_name<'a>(chars: &mut std::str::Chars<'a>) -> Option<&'a str> {
+    let rest = chars.as_str();
+    let (name, len) = match rest.strip_prefix('{') {
+        Some(braced) => {
+            let end = braced.find('}')?;
+            (&braced[..end], end + 2)
+        }
+        None => {
+            let end = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+            (&rest[..end], end)
+        }
+    };
+    if !is_var_name(name) {
+        return None;
+    }
+    *chars = rest[len..].chars();
+    Some(name)
+}
+
+/// Expands `$VAR` and `${VAR}` in `s`, unset variables being empty. A `$`
+/// not followed by a name stays as it is.
+pub(crate) fn expand_env(s: &str, env: Lookup) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '$'
+            && let Some(name) = var_name(&mut chars)
+        {
+            out.push_str(&env(name).unwrap_or_default());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Splits an (already unescaped) Exec value into its arguments, expanding
+/// the unescaped variables when there is an `env` to look them up in.
+fn split_args(exec: &str, env: Option<Lookup>) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut in_quotes = false;
+    let mut chars = exec.chars();
+    // the value of the variable `$` starts, if it does
+    let variable = |chars: &mut std::str::Chars| {
+        let env = env?;
+        var_name(chars).map(|name| env(name).unwrap_or_default())
+    };
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' => in_quotes = false,
+                '\\' => match chars.next() {
+                    Some(escaped @ ('"' | '`' | '$' | '\\')) => current.push(escaped),
+                    Some(other) => {
+                        current.push('\\');
+                        current.push(other);
+                    }
+                    None => current.push('\\'),
+                },
+                '$' => match variable(&mut chars) {
+                    Some(value) => current.push_str(&value),
+                    None => current.push(c),
+                },
+                _ => current.push(c),
+            }
+            continue;
+        }
+
+        match c {
+            '"' => {
+                in_quotes = true;
+                in_arg = true;
+            }
+            c if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            // unquoted, an empty variable is no argument at all, like in a shell
+            '$' => match variable(&mut chars) {
+                Some(value) => {
+                    in_arg |= !value.is_empty();
+                    current.push_str(&value);
+                }
+                None => {
+                    current.push(c);
+                    in_arg = true;
+                }
+            },
+            _ => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+
+    // an unterminated quote just ends the last argument
+    if in_arg {
+        args.push(current);
+    }
+    args
+}
+
+/// What the `%i`, `%c` and `%k` field codes expand to, taken from the
+/// desktop file. Left empty the codes are dropped.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Fields<'a> {
+    /// `Icon`, `%i` becoming `--icon <icon>`.
+    pub icon: Option<&'a str>,
+    /// The translated `Name`, for `%c`.
+    pub name: Option<&'a str>,
+    /// Path of the desktop file, for `%k`.
+    pub location: Option<&'a str>,
+}
+
+/// Expands the field codes of a single argument into `out`.
+///
+/// A lone field code that expands to nothing pushes no argument at all,
+/// instead of passing an empty string to the program.
+fn expand_arg(arg: &str, files: &[String], fields: &Fields, out: &mut Vec<String>) {
+    match arg {
+        "%F" | "%U" => {
+            out.extend(files.iter().cloned());
+            return;
+        }
+        "%f" | "%u" => {
+            if let Some(file) = files.first() {
+                out.push(file.clone());
+            }
+            return;
+        }
+        "%i" => {
+            if let Some(icon) = fields.icon {
+                out.extend(["--icon".to_string(), icon.to_string()]);
+            }
+            return;
+        }
+        "%c" | "%k" => {
+            let value = if arg == "%c" { fields.name } else { fields.location };
+            out.extend(value.map(str::to_string));
+            return;
+        }
+        _ => {}
+    }
+
+    let mut expanded = String::with_capacity(arg.len());
+    let mut chars = arg.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => expanded.push('%'),
+            // embedded file codes can only carry a single file
+            Some('f' | 'u' | 'F' | 'U') => {
+                if let Some(file) = files.first() {
+                    expanded.push_str(file);
+                }
+            }
+            Some('c') => expanded.push_str(fields.name.unwrap_or_default()),
+            Some('k') => expanded.push_str(fields.location.unwrap_or_default()),
+            // %i only makes sense as its own two arguments, it's dropped like
+            // the deprecated codes and unknown ones
+            Some(_) | None => {}
+        }
+    }
+    out.push(expanded);
+}
+
+/// Turns a raw `Exec` value into an argv, substituting `files` for the
+/// `%f`/`%F`/`%u`/`%U` field codes and removing the others.
+pub(crate) fn expand_exec(exec: &str, files: &[String]) -> Vec<String> {
+    expand_exec_with(exec, files, &Fields::default())
+}
+
+/// Like `expand_exec`, also expanding `%i`, `%c` and `%k` from `fields`.
+pub(crate) fn expand_exec_with(exec: &str, files: &[String], fields: &Fields) -> Vec<String> {
+    expand(exec, files, fields, None)
+}
+
+/// Like `expand_exec_with`, also expanding the environment variables from
+/// `env`.
+pub(crate) fn expand_exec_env(exec: &str, files: &[String], fields: &Fields, env: Lookup) -> Vec<String> {
+    expand(exec, files, fields, Some(env))
+}
+
+fn expand(exec: &str, files: &[String], fields: &Fields, env: Option<Lookup>) -> Vec<String> {
+    let mut argv = Vec::new();
+    for arg in split_args(&unescape_value(exec), env) {
+        expand_arg(&arg, files, fields, &mut argv);
+    }
+    argv
+}
+
+/// An Exec value as a command line for people to read: the field codes
+/// removed and the arguments quoted only where they need it. Launches use
+/// `expand_exec` instead.
+pub(crate) fn display_exec(exec: &str) -> String {
+    let args: Vec<String> = expand_exec(exec, &[])
+        .into_iter()
+        .map(|arg| {
+            if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || "\"'\\$`".contains(c)) {
+                return arg;
+            }
+            let escaped: String = arg
+                .chars()
+                .flat_map(|c| ("\"\\$`".contains(c).then_some('\\')).into_iter().chain([c]))
+                .collect();
+            format!("\"{}\"", escaped)
+        })
+        .collect();
+    args.join(" ")
+}
+
+/// Splits the assignments off an `env VAR=value program` command line, for
+/// running `program` with them in its environment rather than through
+/// `env(1)`. Other command lines and ones passing `env` options are returned
+/// as they are, with no assignments.
+pub(crate) fn split_env(argv: Vec<String>) -> (Vec<(String, String)>, Vec<String>) {
+    if argv.first().is_none_or(|arg| arg.rsplit('/').next() != Some("env")) {
+        return (Vec::new(), argv);
+    }
+    let mut vars = Vec::new();
+    for (index, arg) in argv.iter().enumerate().skip(1) {
+        match arg.split_once('=') {
+            Some((name, value)) if is_var_name(name) => vars.push((name.to_string(), value.to_string())),
+            _ if arg.starts_with('-') => break,
+            _ => return (vars, argv[index..].to_vec()),
+        }
+    }
+    // options, or nothing to run and `env` prints the environment
+    (Vec::new(), argv)
+}
+
+/// The name of the program an Exec value runs, e.g. `google-chrome-stable`
+/// for `env GDK_BACKEND=x11 /usr/bin/google-chrome-stable %U`.
+pub(crate) fn program_name(exec: &str) -> Option<String> {
+    let argv = expand_exec(exec, &[]);
+    let mut args = argv.iter().peekable();
+    // `env VAR=value program` runs `program`
+    if args.peek().is_some_and(|arg| arg.rsplit('/').next() == Some("env")) {
+        args.next();
+        while args.next_if(|arg| arg.contains('=') || arg.starts_with('-')).is_some() {}
+    }
+    let program = args.next()?;
+    program.rsplit('/').next().map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_exec_strips_field_codes() {
+        let argv = expand_exec("env FOO=bar /usr/bin/app %U", &[]);
+        assert_eq!(argv, vec!["env", "FOO=bar", "/usr/bin/app"]);
+    }
+
+    #[test]
+    fn expand_exec_quoted_paths_with_spaces() {
+        let argv = expand_exec(
+            r#""/opt/My App/bin/app" --config "/home/me/my file.conf" %f"#,
+            &["/tmp/a b.txt".to_string()],
+        );
+        assert_eq!(
+            argv,
+            vec!["/opt/My App/bin/app", "--config", "/home/me/my file.conf", "/tmp/a b.txt"]
+        );
+    }
+
+    #[test]
+    fn expand_exec_unescapes_quotes_and_backslashes() {
+        // `\\\\` in the file is `\\` after the string pass and `\` after quoting
+        let argv = expand_exec(r#"sh -c "echo \\"hi\\" \\\\ \\$HOME" 100%%"#, &[]);
+        assert_eq!(argv, vec!["sh", "-c", r#"echo "hi" \ $HOME"#, "100%"]);
+    }
+
+    #[test]
+    fn expand_exec_variables() {
+        let env = |name: &str| match name {
+            "HOME" => Some("/home/me".to_string()),
+            "APP_DIR" => Some("/opt/My App".to_string()),
+            _ => None,
+        };
+        let exec = r#"$HOME/bin/app --data "${APP_DIR}/data" $UNSET %f"#;
+        assert_eq!(
+            expand_exec_env(exec, &[], &Fields::default(), &env),
+            vec!["/home/me/bin/app", "--data", "/opt/My App/data"]
+        );
+        // escaped in quotes it's for the program, like the shell in `sh -c`
+        assert_eq!(
+            expand_exec_env(r#"sh -c "echo \\$HOME" 5$ ${HOME"#, &[], &Fields::default(), &env),
+            vec!["sh", "-c", "echo $HOME", "5$", "${HOME"]
+        );
+        assert_eq!(expand_exec("$HOME/app", &[]), vec!["$HOME/app"]);
+        assert_eq!(expand_env("${HOME}/$APP_DIR-x/$1", &env), "/home/me//opt/My App-x/$1");
+    }
+
+    #[test]
+    fn env_prefix_split_off() {
+        let strings = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        let (vars, argv) = split_env(expand_exec("env GDK_BACKEND=x11 A= /usr/bin/app --flag=1 %U", &[]));
+        let var = |name: &str, value: &str| (name.to_string(), value.to_string());
+        assert_eq!(vars, vec![var("GDK_BACKEND", "x11"), var("A", "")]);
+        assert_eq!(argv, strings(&["/usr/bin/app", "--flag=1"]));
+        // env(1) still runs what it has to handle itself
+        for argv in [&["env", "-u", "DISPLAY", "app"][..], &["/usr/bin/env", "A=b"], &["app", "A=b"]] {
+            assert_eq!(split_env(strings(argv)), (Vec::new(), strings(argv)));
+        }
+    }
+
+    #[test]
+    fn displayed_commands() {
+        assert_eq!(display_exec("app %U --foo"), "app --foo");
+        assert_eq!(
+            display_exec(r#""/opt/My App/app" --title=%c "it's" %F"#),
+            r#""/opt/My App/app" --title= "it's""#
+        );
+        assert_eq!(display_exec(r#"sh -c "echo \\$HOME""#), r#"sh -c "echo \$HOME""#);
+    }
+
+    #[test]
+    fn program_names() {
+        assert_eq!(program_name("chromium %U").as_deref(), Some("chromium"));
+        assert_eq!(
+            program_name("env GDK_BACKEND=x11 /usr/bin/google-chrome-stable %U").as_deref(),
+            Some("google-chrome-stable")
+        );
+        assert_eq!(program_name(r#""/opt/My App/my-app" --new"#).as_deref(), Some("my-app"));
+        assert_eq!(program_name("%U"), None);
+    }
+
+    #[test]
+    fn expand_exec_file_lists() {
+        let files = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(expand_exec("app %F", &files), vec!["app", "a", "b"]);
+        assert_eq!(expand_exec("app %u", &files), vec!["app", "a"]);
+        assert_eq!(expand_exec("app --open=%f %i %c %k", &files), vec!["app", "--open=a"]);
+    }
+
+    #[test]
+    fn expand_exec_desktop_file_codes() {
+        let fields = Fields {
+            icon: Some("org.gnome.Editor"),
+            name: Some("Éditeur de texte"),
+            location: Some("/usr/share/applications/org.gnome.Editor.desktop"),
+        };
+        assert_eq!(
+            expand_exec_with("editor %i --title=%c %k 50%% %F", &["a".to_string()], &fields),
+            vec![
+                "editor",
+                "--icon",
+                "org.gnome.Editor",
+                "--title=Éditeur de texte",
+                "/usr/share/applications/org.gnome.Editor.desktop",
+                "50%",
+                "a",
+            ]
+        );
+        // without an icon %i goes away entirely, not leaving a bare --icon
+        let fields = Fields { icon: None, ..fields };
+        assert_eq!(expand_exec_with("editor %i %c", &[], &fields), vec!["editor", "Éditeur de texte"]);
+    }
+}
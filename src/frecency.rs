@@ -0,0 +1,266 @@
+//! Launch history used to boost frequently and recently used apps.
+//!
+//! Launches are stored per desktop ID in `$XDG_DATA_HOME/applist/frecency.json`,
+//! along with when each app was first listed, for the "recently added" badge.
+//! Updates take an exclusive `flock` on a lock file next to it and replace the
+//! file atomically, so several processes recording launches at once don't lose
+//! each other's updates or leave a half-written file behind.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A launch loses half its weight every three days.
+const HALF_LIFE_SECS: f64 = 3.0 * 24.0 * 3600.0;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct Record {
+    count: u32,
+    /// Unix time of the last launch, in seconds.
+    last_used: u64,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub(crate) struct Frecency {
+    apps: HashMap<String, Record>,
+    /// Unix time each desktop ID was first listed, in seconds.
+    #[serde(default)]
+    first_seen: HashMap<String, u64>,
+}
+
+impl Frecency {
+    /// Reads the history at `path`, a missing or corrupt file being empty.
+    pub(crate) fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    /// Writes the history to a temporary file and renames it over `path`.
+    fn save(&self, path: &Path) -> Result<()> {
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub(crate) fn record(&mut self, id: &str, now: u64) {
+        let record = self.apps.entry(id.to_string()).or_default();
+        record.count = record.count.saturating_add(1);
+        record.last_used = now;
+    }
+
+    /// Notes `now` as the first sighting of the `ids` not seen before. When
+    /// nothing was tracked yet every app was already there, none is new.
+    pub(crate) fn see(&mut self, ids: &[&str], now: u64) {
+        let now = if self.first_seen.is_empty() { 0 } else { now };
+        for id in ids {
+            self.first_seen.entry(id.to_string()).or_insert(now);
+        }
+    }
+
+    /// Unix time `id` was first listed, `None` if it never was.
+    pub(crate) fn first_seen(&self, id: &str) -> Option<u64> {
+        self.first_seen.get(id).copied()
+    }
+
+    /// The launch count of `id`, decayed by the time since its last launch.
+    pub(crate) fn value(&self, id: &str, now: u64) -> f64 {
+        self.apps.get(id).map_or(0.0, |record| {
+            let age = now.saturating_sub(record.last_used) as f64;
+            record.count as f64 * 0.5f64.powf(age / HALF_LIFE_SECS)
+        })
+    }
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Runs `f` while holding an exclusive lock shared with other processes.
+fn with_file_lock<T>(path: &Path, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let lock = File::create(path.with_extension("json.lock"))?;
+    if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    // the lock is released when `lock` is closed
+    f()
+}
+
+/// Loads the history from disk, records a launch and writes it back.
+pub(crate) fn record_at(path: &Path, id: &str, now: u64) -> Result<Frecency> {
+    with_file_lock(path, || {
+        let mut frecency = Frecency::load(path);
+        frecency.record(id, now);
+        frecency.save(path)?;
+        Ok(frecency)
+    })
+}
+
+/// Loads the history from disk, notes the first sighting of `ids` and
+/// writes it back.
+pub(crate) fn see_at(path: &Path, ids: &[&str], now: u64) -> Result<Frecency> {
+    with_file_lock(path, || {
+        let mut frecency = Frecency::load(path);
+        frecency.see(ids, now);
+        frecency.save(path)?;
+        Ok(frecency)
+    })
+}
+
+#[cfg(not(test))]
+fn default_path() -> Option<PathBuf> {
+    xdg::BaseDirectories::with_prefix("applist")
+        .get_data_home()
+        .map(|dir| dir.join("frecency.json"))
+}
+
+// tests must never touch the user's real history
+#[cfg(test)]
+fn default_path() -> Option<PathBuf> {
+    None
+}
+
+// `None` until first used, then the history as last read or written
+static STATE: Mutex<Option<Frecency>> = Mutex::new(None);
+
+/// The current history, read from disk on first use.
+pub(crate) fn current() -> Frecency {
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    state
+        .get_or_insert_with(|| default_path().map(|path| Frecency::load(&path)).unwrap_or_default())
+        .clone()
+}
+
+/// Records a successful launch of the app with desktop ID `id`.
+pub(crate) fn record_launch(id: &str) {
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let now = now();
+    match default_path() {
+        Some(path) => match record_at(&path, id, now) {
+            Ok(frecency) => *state = Some(frecency),
+            Err(e) => log_warn!("Failed to save launch history {}: {}", path.display(), e),
+        },
+        None => state.get_or_insert_with(Frecency::default).record(id, now),
+    }
+}
+
+/// Notes the apps with desktop IDs `ids` as listed, returning the history
+/// with their first sightings. Only writes to disk when one is new.
+pub(crate) fn record_seen(ids: &[&str]) -> Frecency {
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let history = state
+        .get_or_insert_with(|| default_path().map(|path| Frecency::load(&path)).unwrap_or_default());
+    if ids.iter().all(|id| history.first_seen.contains_key(*id)) {
+        return history.clone();
+    }
+    let now = now();
+    if let Some(path) = default_path() {
+        match see_at(&path, ids, now) {
+            Ok(frecency) => *history = frecency,
+            Err(e) => log_warn!("Failed to save launch history {}: {}", path.display(), e),
+        }
+    }
+    // kept in memory even if it couldn't be saved, not to retry on every query
+    history.see(ids, now);
+    history.clone()
+}
+
+/// Drops the in-memory history, it is read from disk again on next use.
+///
+/// Launches are saved as they are recorded, so there is nothing to flush.
+pub(crate) fn unload() {
+    *STATE.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Forgets every recorded launch, on disk too.
+pub(crate) fn reset() {
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    *state = Some(Frecency::default());
+    if let Some(path) = default_path()
+        && let Err(e) = with_file_lock(&path, || Ok(fs::remove_file(&path)?))
+        && path.exists()
+    {
+        log_warn!("Failed to remove launch history {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDir;
+
+    #[test]
+    fn recent_launches_weigh_more() {
+        let mut frecency = Frecency::default();
+        let now = 1_000_000_000;
+        frecency.record("old.desktop", now - 30 * 24 * 3600);
+        frecency.record("old.desktop", now - 30 * 24 * 3600);
+        frecency.record("new.desktop", now);
+
+        assert!(frecency.value("new.desktop", now) > frecency.value("old.desktop", now));
+        assert!((frecency.value("new.desktop", now) - 1.0).abs() < f64::EPSILON);
+        assert_eq!(frecency.value("never.desktop", now), 0.0);
+    }
+
+    #[test]
+    fn launches_persist_across_loads() {
+        let dir = TestDir::new("frecency");
+        let path = dir.path().join("applist/frecency.json");
+        record_at(&path, "firefox.desktop", 100).unwrap();
+        record_at(&path, "firefox.desktop", 200).unwrap();
+
+        let loaded = Frecency::load(&path);
+        assert_eq!(loaded.apps["firefox.desktop"].count, 2);
+        assert_eq!(loaded.apps["firefox.desktop"].last_used, 200);
+    }
+
+    #[test]
+    fn first_sightings_persist() {
+        let dir = TestDir::new("frecency-seen");
+        let path = dir.path().join("frecency.json");
+        record_at(&path, "firefox.desktop", 100).unwrap();
+        // the apps there on the first run aren't new
+        see_at(&path, &["firefox.desktop", "gimp.desktop"], 200).unwrap();
+        see_at(&path, &["gimp.desktop", "krita.desktop"], 300).unwrap();
+
+        let loaded = Frecency::load(&path);
+        assert_eq!(loaded.first_seen("firefox.desktop"), Some(0));
+        assert_eq!(loaded.first_seen("gimp.desktop"), Some(0));
+        assert_eq!(loaded.first_seen("krita.desktop"), Some(300));
+        assert_eq!(loaded.first_seen("inkscape.desktop"), None);
+        assert_eq!(loaded.apps["firefox.desktop"].count, 1);
+    }
+
+    #[test]
+    fn concurrent_records_are_not_lost() {
+        let dir = TestDir::new("frecency-threads");
+        let path = dir.path().join("frecency.json");
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| record_at(&path, "app.desktop", 1).unwrap());
+            }
+        });
+        assert_eq!(Frecency::load(&path).apps["app.desktop"].count, 8);
+    }
+
+    #[test]
+    fn corrupt_file_is_empty() {
+        let dir = TestDir::new("frecency-corrupt");
+        let path = dir.write("frecency.json", "{not json");
+        assert!(Frecency::load(&path).apps.is_empty());
+        record_at(&path, "app.desktop", 1).unwrap();
+        assert_eq!(Frecency::load(&path).apps["app.desktop"].count, 1);
+    }
+}
@@ -0,0 +1,83 @@
+//! Keeps panics from unwinding out of the exports into the host.
+//!
+//! A panic crossing an `extern "C"` function takes the host down with it. Each
+//! export runs its body through `guard`, which turns a panic into an error
+//! on the log callback and the export's safe default.
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+
+/// Runs `body`, the body of the export named `export`, returning `default`
+/// if it panics.
+///
+/// Shared state lives behind mutexes whose users all recover from poisoning,
+/// so the plugin stays usable after a panic.
+pub(crate) fn guard<T>(export: &str, default: T, body: impl FnOnce() -> T) -> T {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        #[cfg(test)]
+        injected_panic(export);
+        body()
+    }));
+    match result {
+        Ok(value) => value,
+        Err(payload) => {
+            log_error!("Panic in {}: {}", export, message(payload.as_ref()));
+            default
+        }
+    }
+}
+
+/// The message a panic was raised with, when it has one.
+fn message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    // the export made to panic on this test thread, see `inject_panic`
+    static PANICKING_EXPORT: std::cell::Cell<Option<&'static str>> = const { std::cell::Cell::new(None) };
+}
+
+/// Makes the export named `export` panic as soon as it's called from this
+/// thread, `None` undoing it.
+#[cfg(test)]
+pub(crate) fn inject_panic(export: Option<&'static str>) {
+    PANICKING_EXPORT.with(|panicking| panicking.set(export));
+}
+
+#[cfg(test)]
+fn injected_panic(export: &str) {
+    if PANICKING_EXPORT.with(|panicking| panicking.get()) == Some(export) {
+        panic!("injected panic");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panic_gives_default() {
+        assert_eq!(guard("test", 0, || 42), 42);
+        assert_eq!(guard("test", 0, || -> i32 { panic!("boom") }), 0);
+        assert_eq!(guard("test", None, || -> Option<u8> { panic!("{} {}", "formatted", 1) }), None);
+        // a guarded export keeps working after a panic
+        assert_eq!(guard("test", 0, || 7), 7);
+    }
+
+    #[test]
+    fn panic_messages() {
+        let payload = panic::catch_unwind(|| panic!("static")).unwrap_err();
+        assert_eq!(message(payload.as_ref()), "static");
+        let payload = panic::catch_unwind(|| panic!("{}", String::from("owned"))).unwrap_err();
+        assert_eq!(message(payload.as_ref()), "owned");
+        let payload = panic::catch_unwind(|| panic::panic_any(3)).unwrap_err();
+        assert_eq!(message(payload.as_ref()), "unknown panic");
+    }
+}
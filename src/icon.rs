@@ -0,0 +1,556 @@
+//! Resolution of `Icon=` theme names to image files, so the host gets a path
+//! it can load directly.
+//!
+//! This follows the icon theme spec: the user's theme first, then the themes
+//! it inherits from, then `hicolor`, then the legacy pixmaps directory. In
+//! each theme the directories its `index.theme` lists for the size come
+//! first, raster ones before scalable ones, then the one with the nearest
+//! size. Themes without an `index.theme` are searched in their common
+//! layouts the same way.
+//!
+//! The user's theme is `icon_theme` when configured, else the one their
+//! desktop's settings name: `kdeglobals` on KDE Plasma, GTK's `settings.ini`
+//! elsewhere.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+const EXTENSIONS: [&str; 3] = ["png", "svg", "xpm"];
+
+/// Sizes looked for in themes without an `index.theme`.
+const COMMON_SIZES: [u16; 10] = [16, 22, 24, 32, 48, 64, 96, 128, 256, 512];
+
+// (name, size) -> resolved path, misses included
+type ResolvedIcons = HashMap<(String, u16), Option<String>>;
+
+/// Where to look for icons and in which theme.
+pub(crate) struct IconResolver {
+    /// Directories containing themes, e.g. `/usr/share/icons`.
+    pub(crate) base_dirs: Vec<PathBuf>,
+    /// Directories with loose icons, e.g. `/usr/share/pixmaps`.
+    pub(crate) pixmap_dirs: Vec<PathBuf>,
+    pub(crate) theme: Option<String>,
+    /// The theme, its ancestors and `hicolor`, read on first use.
+    pub(crate) chain: OnceLock<Vec<Theme>>,
+}
+
+/// How a theme directory's icons fit sizes, its `Type`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DirKind {
+    Fixed,
+    Scalable,
+    Threshold,
+}
+
+/// A directory of a theme, from its `index.theme` group.
+#[derive(Debug, Clone, PartialEq)]
+struct ThemeDir {
+    path: String,
+    size: u16,
+    kind: DirKind,
+    min_size: u16,
+    max_size: u16,
+    threshold: u16,
+}
+
+impl ThemeDir {
+    /// The smallest and largest sizes the directory's icons are meant for.
+    fn sizes(&self) -> (u16, u16) {
+        match self.kind {
+            DirKind::Fixed => (self.size, self.size),
+            DirKind::Scalable => (self.min_size, self.max_size),
+            DirKind::Threshold => {
+                (self.size.saturating_sub(self.threshold), self.size.saturating_add(self.threshold))
+            }
+        }
+    }
+
+    fn matches(&self, size: u16) -> bool {
+        let (min, max) = self.sizes();
+        (min..=max).contains(&size)
+    }
+
+    /// How far `size` is from the sizes of the directory.
+    fn distance(&self, size: u16) -> u16 {
+        let (min, max) = self.sizes();
+        min.saturating_sub(size).max(size.saturating_sub(max))
+    }
+}
+
+/// What an `index.theme` says about looking up icons.
+#[derive(Debug, Default, PartialEq)]
+struct ThemeIndex {
+    /// `Inherits`, the themes searched after this one.
+    inherits: Vec<String>,
+    /// `Directories`, in the order they are listed.
+    dirs: Vec<ThemeDir>,
+}
+
+/// Parses an `index.theme`. Directories without a `Size`, or meant for a
+/// scale other than 1, are left out.
+fn parse_index(content: &str) -> ThemeIndex {
+    let mut groups: HashMap<&str, HashMap<&str, &str>> = HashMap::new();
+    let mut group = "";
+    for line in content.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            group = name;
+        } else if let Some((key, value)) = line.split_once('=') {
+            groups.entry(group).or_default().insert(key.trim(), value.trim());
+        }
+    }
+    let list = |value: Option<&&str>| -> Vec<String> {
+        value
+            .map(|value| {
+                value.split(',').map(str::trim).filter(|s| !s.is_empty()).map(str::to_string).collect()
+            })
+            .unwrap_or_default()
+    };
+    let header = groups.get("Icon Theme");
+    let dirs = list(header.and_then(|header| header.get("Directories")))
+        .into_iter()
+        .filter_map(|path| {
+            let keys = groups.get(path.as_str())?;
+            let number = |key: &str| keys.get(key).and_then(|value| value.parse::<u16>().ok());
+            let size = number("Size")?;
+            if number("Scale").is_some_and(|scale| scale != 1) {
+                return None;
+            }
+            let kind = match keys.get("Type").copied() {
+                Some("Fixed") => DirKind::Fixed,
+                Some("Scalable") => DirKind::Scalable,
+                _ => DirKind::Threshold,
+            };
+            Some(ThemeDir {
+                min_size: number("MinSize").unwrap_or(size),
+                max_size: number("MaxSize").unwrap_or(size),
+                threshold: number("Threshold").unwrap_or(2),
+                path,
+                size,
+                kind,
+            })
+        })
+        .collect();
+    ThemeIndex { inherits: list(header.and_then(|header| header.get("Inherits"))), dirs }
+}
+
+/// A theme of the lookup chain.
+#[derive(Debug)]
+pub(crate) struct Theme {
+    name: String,
+    /// `None` when no base directory has an `index.theme` for it.
+    index: Option<ThemeIndex>,
+}
+
+impl IconResolver {
+    /// The resolver for the current user: `~/.icons`, `$XDG_DATA_HOME/icons`
+    /// and `icons` in every XDG data dir, with the `configured` theme or the
+    /// desktop's.
+    pub(crate) fn from_env(configured: Option<&str>) -> Self {
+        let xdg_dirs = xdg::BaseDirectories::new();
+        let mut base_dirs = Vec::new();
+        if let Some(home_dir) = home::home_dir() {
+            base_dirs.push(home_dir.join(".icons"));
+        }
+        if let Some(data_home) = xdg_dirs.get_data_home() {
+            base_dirs.push(data_home.join("icons"));
+        }
+        base_dirs.extend(xdg_dirs.get_data_dirs().into_iter().map(|dir| dir.join("icons")));
+
+        let theme = match configured.filter(|theme| !theme.is_empty()) {
+            Some(theme) => Some(theme.to_string()),
+            None => xdg_dirs
+                .get_config_home()
+                .and_then(|config| desktop_icon_theme(&config, &crate::current_desktops())),
+        };
+
+        IconResolver {
+            base_dirs,
+            pixmap_dirs: vec![PathBuf::from("/usr/share/pixmaps")],
+            theme,
+            chain: OnceLock::new(),
+        }
+    }
+
+    /// The themes to search in order: the theme, then depth first the ones
+    /// it inherits from, `hicolor` last if nothing inherited from it.
+    fn chain(&self) -> &[Theme] {
+        self.chain.get_or_init(|| {
+            let mut chain = Vec::new();
+            let mut pending: Vec<String> = self.theme.iter().cloned().collect();
+            pending.push("hicolor".to_string());
+            // a stack, so the parents of a theme come right after it
+            pending.reverse();
+            while let Some(name) = pending.pop() {
+                if chain.iter().any(|theme: &Theme| theme.name == name) {
+                    continue;
+                }
+                let index = self
+                    .base_dirs
+                    .iter()
+                    .find_map(|base| std::fs::read_to_string(base.join(&name).join("index.theme")).ok())
+                    .map(|content| parse_index(&content));
+                if let Some(index) = &index {
+                    pending.extend(index.inherits.iter().rev().cloned());
+                }
+                chain.push(Theme { name, index });
+            }
+            chain
+        })
+    }
+
+    /// Finds `name` in one theme, in the directories for `size` first, the
+    /// raster ones before the scalable ones, and then in the nearest one.
+    fn lookup(&self, theme: &Theme, name: &str, size: u16) -> Option<String> {
+        let find = |dir: &str| {
+            self.base_dirs.iter().find_map(|base| find_file(&base.join(&theme.name).join(dir), name))
+        };
+        let guessed;
+        let dirs = match &theme.index {
+            Some(index) => &index.dirs,
+            None => {
+                guessed = guessed_dirs();
+                &guessed
+            }
+        };
+        let mut matching: Vec<&ThemeDir> = dirs.iter().filter(|dir| dir.matches(size)).collect();
+        // stable, the index order otherwise
+        matching.sort_by_key(|dir| dir.kind == DirKind::Scalable);
+        if let Some(found) = matching.into_iter().find_map(|dir| find(&dir.path)) {
+            return Some(found);
+        }
+        let mut nearest: Vec<&ThemeDir> = dirs.iter().collect();
+        // stable, equally near directories keep the order of the index
+        nearest.sort_by_key(|dir| dir.distance(size));
+        nearest.into_iter().find_map(|dir| find(&dir.path))
+    }
+
+    /// Finds the file for an icon name at roughly `size` pixels.
+    pub(crate) fn resolve(&self, name: &str, size: u16) -> Option<String> {
+        if Path::new(name).is_absolute() {
+            return Some(name.to_string());
+        }
+
+        self.chain()
+            .iter()
+            .find_map(|theme| self.lookup(theme, name, size))
+            .or_else(|| self.pixmap_dirs.iter().find_map(|dir| find_file(dir, name)))
+    }
+}
+
+/// The directories of the common layouts, `48x48/apps` and `apps/48` alike,
+/// for themes without an `index.theme`.
+fn guessed_dirs() -> Vec<ThemeDir> {
+    let fixed = |path: String, size: u16| ThemeDir {
+        path,
+        size,
+        kind: DirKind::Fixed,
+        min_size: size,
+        max_size: size,
+        threshold: 0,
+    };
+    let mut dirs: Vec<ThemeDir> = COMMON_SIZES
+        .iter()
+        .flat_map(|&size| [fixed(format!("{size}x{size}/apps"), size), fixed(format!("apps/{size}"), size)])
+        .collect();
+    dirs.push(ThemeDir {
+        kind: DirKind::Scalable,
+        min_size: 1,
+        max_size: u16::MAX,
+        ..fixed("scalable/apps".to_string(), 64)
+    });
+    dirs
+}
+
+/// Looks for `name` with one of the supported extensions in `dir`.
+fn find_file(dir: &Path, name: &str) -> Option<String> {
+    // some desktop files wrongly include the extension in `Icon=`
+    if EXTENSIONS.iter().any(|ext| name.ends_with(&format!(".{ext}"))) && dir.join(name).is_file() {
+        return Some(dir.join(name).to_string_lossy().into_owned());
+    }
+    EXTENSIONS
+        .iter()
+        .map(|ext| dir.join(format!("{name}.{ext}")))
+        .find(|path| path.is_file())
+        .map(|path| path.to_string_lossy().into_owned())
+}
+
+/// The theme set in the settings of the desktop, under `config_home`: KDE's
+/// in `kdeglobals` on Plasma, falling back to GTK's, which Plasma also keeps
+/// in sync, and GTK's anywhere else.
+fn desktop_icon_theme(config_home: &Path, desktops: &[String]) -> Option<String> {
+    let read = |file: &str| std::fs::read_to_string(config_home.join(file)).ok();
+    let gtk_theme = || read("gtk-3.0/settings.ini").and_then(|settings| gtk_icon_theme(&settings));
+    if desktops.iter().any(|desktop| desktop.eq_ignore_ascii_case("KDE")) {
+        read("kdeglobals").and_then(|globals| kde_icon_theme(&globals)).or_else(gtk_theme)
+    } else {
+        gtk_theme()
+    }
+}
+
+/// Reads `Theme` in the `[Icons]` group of KDE's `kdeglobals`.
+fn kde_icon_theme(globals: &str) -> Option<String> {
+    let mut in_icons = false;
+    globals.lines().map(str::trim).find_map(|line| {
+        if line.starts_with('[') {
+            in_icons = line == "[Icons]";
+            return None;
+        }
+        let (key, value) = line.split_once('=')?;
+        (in_icons && key.trim() == "Theme")
+            .then(|| value.trim().to_string())
+            .filter(|theme| !theme.is_empty())
+    })
+}
+
+/// Reads `gtk-icon-theme-name` from a GTK `settings.ini`.
+fn gtk_icon_theme(settings: &str) -> Option<String> {
+    settings.lines().find_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        (key.trim() == "gtk-icon-theme-name")
+            .then(|| value.trim().trim_matches('"').to_string())
+            .filter(|theme| !theme.is_empty())
+    })
+}
+
+struct IconCache {
+    // the `icon_theme` the resolver was made with
+    configured: Option<String>,
+    resolver: IconResolver,
+    resolved: ResolvedIcons,
+}
+
+// `None` until the first lookup, the resolver reads the environment then
+static ICONS: Mutex<Option<IconCache>> = Mutex::new(None);
+
+/// Resolves an icon for the current user, in the `configured` theme if any,
+/// caching results since every query asks for the same icons again.
+pub(crate) fn resolve_icon(name: &str, size: u16, configured: Option<&str>) -> Option<String> {
+    let mut icons = ICONS.lock().unwrap_or_else(|e| e.into_inner());
+    if icons.as_ref().is_some_and(|icons| icons.configured.as_deref() != configured) {
+        *icons = None;
+    }
+    let icons = icons.get_or_insert_with(|| IconCache {
+        configured: configured.map(str::to_string),
+        resolver: IconResolver::from_env(configured),
+        resolved: ResolvedIcons::new(),
+    });
+
+    let key = (name.to_string(), size);
+    if let Some(path) = icons.resolved.get(&key) {
+        return path.clone();
+    }
+    let path = icons.resolver.resolve(name, size);
+    icons.resolved.insert(key, path.clone());
+    path
+}
+
+/// Forgets resolved icons and the theme, both are looked up again when next
+/// needed.
+pub(crate) fn clear_cache() {
+    *ICONS.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDir;
+
+    fn resolver(dir: &TestDir, theme: Option<&str>) -> IconResolver {
+        IconResolver {
+            base_dirs: vec![dir.path().join("icons")],
+            pixmap_dirs: vec![dir.path().join("pixmaps")],
+            theme: theme.map(str::to_string),
+            chain: OnceLock::new(),
+        }
+    }
+
+    #[test]
+    fn resolves_in_theme_hicolor_and_pixmaps() {
+        let dir = TestDir::new("icons");
+        let themed = dir.write("icons/Papirus/48x48/apps/firefox.svg", "");
+        dir.write("icons/hicolor/48x48/apps/firefox.png", "");
+        let hicolor = dir.write("icons/hicolor/scalable/apps/gimp.svg", "");
+        let pixmap = dir.write("pixmaps/python3.11.xpm", "");
+
+        let resolver = resolver(&dir, Some("Papirus"));
+        let path = |p: PathBuf| Some(p.to_string_lossy().into_owned());
+        assert_eq!(resolver.resolve("firefox", 48), path(themed));
+        assert_eq!(resolver.resolve("gimp", 48), path(hicolor));
+        assert_eq!(resolver.resolve("python3.11", 48), path(pixmap));
+        assert_eq!(resolver.resolve("missing", 48), None);
+    }
+
+    #[test]
+    fn without_theme_falls_back_to_hicolor() {
+        let dir = TestDir::new("icons-hicolor");
+        let hicolor = dir.write("icons/hicolor/48x48/apps/firefox.png", "");
+        dir.write("icons/Papirus/48x48/apps/firefox.svg", "");
+
+        let resolved = resolver(&dir, None).resolve("firefox", 48);
+        assert_eq!(resolved, Some(hicolor.to_string_lossy().into_owned()));
+    }
+
+    const PARENT_INDEX: &str = "\
+[Icon Theme]
+Name=Parent
+Inherits=hicolor
+Directories=16x16/apps,32x32/apps,scalable/apps,48x48@2/apps
+
+[16x16/apps]
+Size=16
+Type=Fixed
+
+[32x32/apps]
+Size=32
+Type=Fixed
+
+[scalable/apps]
+Size=64
+MinSize=16
+MaxSize=256
+Type=Scalable
+
+[48x48@2/apps]
+Size=48
+Scale=2
+";
+
+    #[test]
+    fn inherited_themes_in_order() {
+        let dir = TestDir::new("icons-inherits");
+        dir.write(
+            "icons/Child/index.theme",
+            "[Icon Theme]\nInherits=Parent\nDirectories=48x48/apps\n\n[48x48/apps]\nSize=48\n",
+        );
+        dir.write("icons/Parent/index.theme", PARENT_INDEX);
+        let child = dir.write("icons/Child/48x48/apps/files.png", "");
+        let scalable = dir.write("icons/Parent/scalable/apps/editor.svg", "");
+        let small = dir.write("icons/Parent/16x16/apps/chat.png", "");
+        let nearer = dir.write("icons/Parent/32x32/apps/chat.png", "");
+        dir.write("icons/Parent/48x48@2/apps/chat.png", "");
+        dir.write("icons/hicolor/48x48/apps/editor.png", "");
+        dir.write("icons/hicolor/48x48/apps/chat.png", "");
+        dir.write("icons/hicolor/48x48/apps/files.png", "");
+        let hicolor = dir.write("icons/hicolor/48x48/apps/terminal.png", "");
+
+        let resolver = resolver(&dir, Some("Child"));
+        let names: Vec<&str> = resolver.chain().iter().map(|theme| theme.name.as_str()).collect();
+        assert_eq!(names, ["Child", "Parent", "hicolor"]);
+        let path = |p: PathBuf| Some(p.to_string_lossy().into_owned());
+        assert_eq!(resolver.resolve("files", 48), path(child));
+        // only in the parent, which comes before hicolor
+        assert_eq!(resolver.resolve("editor", 48), path(scalable));
+        // the nearest size of the parent, not the 2x directory
+        assert_eq!(resolver.resolve("chat", 48), path(nearer));
+        assert_eq!(resolver.resolve("chat", 16), path(small));
+        assert_eq!(resolver.resolve("terminal", 48), path(hicolor));
+    }
+
+    #[test]
+    fn inheritance_cycles_end() {
+        let dir = TestDir::new("icons-cycle");
+        dir.write("icons/A/index.theme", "[Icon Theme]\nInherits=B\n");
+        dir.write("icons/B/index.theme", "[Icon Theme]\nInherits=A, hicolor\n");
+        let resolver = resolver(&dir, Some("A"));
+        let names: Vec<&str> = resolver.chain().iter().map(|theme| theme.name.as_str()).collect();
+        assert_eq!(names, ["A", "B", "hicolor"]);
+        assert_eq!(resolver.resolve("missing", 48), None);
+    }
+
+    #[test]
+    fn index_directories() {
+        let index = parse_index(PARENT_INDEX);
+        assert_eq!(index.inherits, ["hicolor"]);
+        let paths: Vec<&str> = index.dirs.iter().map(|dir| dir.path.as_str()).collect();
+        assert_eq!(paths, ["16x16/apps", "32x32/apps", "scalable/apps"]);
+        let scalable = &index.dirs[2];
+        assert!(scalable.matches(200) && !scalable.matches(512));
+        assert_eq!(scalable.distance(8), 8);
+        let threshold = ThemeDir {
+            path: String::new(),
+            size: 48,
+            kind: DirKind::Threshold,
+            min_size: 48,
+            max_size: 48,
+            threshold: 2,
+        };
+        assert!(threshold.matches(50) && !threshold.matches(51));
+        assert_eq!(threshold.distance(40), 6);
+    }
+
+    #[test]
+    fn nearest_size_or_scalable() {
+        let dir = TestDir::new("icons-sizes");
+        dir.write(
+            "icons/Sized/index.theme",
+            "[Icon Theme]\nDirectories=scalable/apps,48x48/apps,256x256/apps\n\n\
+             [scalable/apps]\nSize=128\nMinSize=8\nMaxSize=512\nType=Scalable\n\n\
+             [48x48/apps]\nSize=48\nType=Fixed\n\n[256x256/apps]\nSize=256\nType=Fixed\n",
+        );
+        let indexed = [48, 256].map(|size| dir.write(&format!("icons/Sized/{size}x{size}/apps/app.png"), ""));
+        let svg = dir.write("icons/Sized/scalable/apps/app.svg", "");
+        dir.write("icons/Sized/48x48/apps/raster.png", "");
+        let large = dir.write("icons/Sized/256x256/apps/raster.png", "");
+        // no index.theme, the common layout
+        let guessed =
+            [48, 256].map(|size| dir.write(&format!("icons/hicolor/{size}x{size}/apps/plain.png"), ""));
+        let plain_svg = dir.write("icons/hicolor/scalable/apps/plain.svg", "");
+        dir.write("icons/hicolor/apps/16/tiny.png", "");
+        let tiny = dir.write("icons/hicolor/apps/32/tiny.png", "");
+
+        let resolver = resolver(&dir, Some("Sized"));
+        let path = |p: &PathBuf| Some(p.to_string_lossy().into_owned());
+        // the exact raster before the scalable directory listed first
+        assert_eq!(resolver.resolve("app", 48), path(&indexed[0]));
+        assert_eq!(resolver.resolve("app", 256), path(&indexed[1]));
+        // no 128px raster, scalable it is
+        assert_eq!(resolver.resolve("app", 128), path(&svg));
+        // without a scalable one, the nearest raster
+        assert_eq!(resolver.resolve("raster", 200), path(&large));
+        assert_eq!(resolver.resolve("plain", 48), path(&guessed[0]));
+        assert_eq!(resolver.resolve("plain", 256), path(&guessed[1]));
+        assert_eq!(resolver.resolve("plain", 24), path(&plain_svg));
+        assert_eq!(resolver.resolve("tiny", 48), path(&tiny));
+    }
+
+    #[test]
+    fn absolute_paths_pass_through() {
+        let dir = TestDir::new("icons-absolute");
+        let resolver = resolver(&dir, None);
+        assert_eq!(resolver.resolve("/opt/app/icon.png", 48).as_deref(), Some("/opt/app/icon.png"));
+    }
+
+    #[test]
+    fn reads_gtk_settings() {
+        let settings = "[Settings]\ngtk-theme-name=Adwaita\ngtk-icon-theme-name = Papirus-Dark\n";
+        assert_eq!(gtk_icon_theme(settings).as_deref(), Some("Papirus-Dark"));
+        assert_eq!(gtk_icon_theme("[Settings]\n"), None);
+    }
+
+    #[test]
+    fn reads_kdeglobals() {
+        let globals = "[General]\nTheme=ignored\n\n[Icons]\nTheme=breeze-dark\n[KDE]\nTheme=other\n";
+        assert_eq!(kde_icon_theme(globals).as_deref(), Some("breeze-dark"));
+        assert_eq!(kde_icon_theme("[General]\nTheme=ignored\n[Icons]\n"), None);
+    }
+
+    #[test]
+    fn theme_of_the_current_desktop() {
+        let config = TestDir::new("theme-config");
+        config.write("gtk-3.0/settings.ini", "[Settings]\ngtk-icon-theme-name=Adwaita\n");
+        let desktops = |value| crate::parse_current_desktops(Some(value));
+        let theme = |value| desktop_icon_theme(config.path(), &desktops(value));
+
+        // Plasma without an icon theme of its own, and other desktops, go by GTK
+        assert_eq!(theme("KDE").as_deref(), Some("Adwaita"));
+        config.write("kdeglobals", "[Icons]\nTheme=Papirus\n");
+        assert_eq!(theme("KDE").as_deref(), Some("Papirus"));
+        assert_eq!(theme("ubuntu:GNOME").as_deref(), Some("Adwaita"));
+        assert_eq!(theme("").as_deref(), Some("Adwaita"));
+        assert_eq!(desktop_icon_theme(&config.path().join("missing"), &desktops("KDE")), None);
+
+        // a configured theme wins over both
+        assert_eq!(IconResolver::from_env(Some("Numix")).theme.as_deref(), Some("Numix"));
+    }
+}
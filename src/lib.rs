@@ -1,10 +1,63 @@
+// The exports below are called from C with pointers the host owns, marking
+// them `unsafe` would not change anything for the caller.
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
+// Every export may be called from any host thread. The state they share lives
+// in statics behind locks, never in leaked or forgotten allocations:
+//
+// - `config::CONFIG` and the log callback, `RwLock`s read on every call
+// - `cache::global()`, the parsed desktop files, mirrored in the disk index
+// - `frecency::STATE`, the launch history
+// - `icon::ICONS`, resolved icon paths
+// - `watch::WATCHER`, the inotify thread
+// - the strings of `get_entries_into`, `explain_match` and
+//   `resolve_launch_command` results, valid until the next call
+//
+// A lock is only taken while holding one listed before it: the watcher, then
+// the cache, then the scratch entries, then icons, then the log callback.
+// The others are never held while taking another lock.
+//
+// Raw pointers only exist in the `repr(C)` types handed to the host; inside
+// the plugin apps are `AppInfo`s, turned into `Entry`s last by `app_to_entry`
+// or `into_entry_list`. The strings of an `EntryList` live in an arena owned
+// by that list alone, nothing else frees or reuses them.
+
 use anyhow::Result;
-use std::ffi::CStr;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::ffi::{CStr, CString};
 use std::fs;
-use std::os::raw::c_char;
-use std::path::PathBuf;
+use std::os::raw::{c_char, c_int};
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+
+#[macro_use]
+mod log;
+mod appimage;
+
+mod cache;
+mod collate;
+mod config;
+mod dbus;
+mod emoji;
+mod exec;
+mod frecency;
+mod guard;
+mod icon;
+mod matcher;
+mod mimeapps;
+#[cfg(feature = "pinyin")]
+mod pinyin;
+mod rename;
+mod running;
+mod terminal;
+mod watch;
+#[cfg(test)]
+mod test_util;
+
+use config::{Config, SortMode};
+use guard::guard;
 
 /// Converts a string literal into a C-compatible string pointer (`*const c_char`).
 ///
@@ -31,330 +84,4316 @@ pub struct PluginInfo {
 }
 
 #[allow(dead_code)]
-#[derive(Clone)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 struct AppInfo {
+    id: String, // desktop file ID, e.g. `org.gnome.Terminal.desktop`
     name: String,
+    // `name` after `name_replacements` when they change it, only shown,
+    // matching and `%c` still use `name`
+    display_name: Option<String>,
     description: Option<String>,
+    generic_name: Option<String>, // e.g. "Web Browser"
     path: String,
     icon: Option<String>,
     emoji: Option<String>,
     terminal: bool,
+    dbus_activatable: bool, // launched over D-Bus rather than by running Exec
+    exec: Option<String>, // raw Exec value, expanded with `exec::expand_exec` at launch
+    only_show_in: Vec<String>,
+    not_show_in: Vec<String>,
+    keywords: Vec<String>,
+    // `X-GNOME-Keywords` and the like by prefix, see `merge_vendor_keywords`
+    vendor_keywords: Vec<(String, Vec<String>)>,
+    categories: Vec<String>,
+    mime_types: Vec<String>, // `MimeType=`, what the app can open
+    implements: Vec<String>, // `Implements=`, D-Bus interfaces the app provides
+    no_display: bool, // only listed when `include_nodisplay` is set
+    try_exec: Option<String>,
+    startup_wm_class: Option<String>,
+    startup_notify: bool, // `StartupNotify=true`, the app reports when it has started
+    // what `desktop-file-validate` reported, when validation is on and failed
+    validation_error: Option<String>,
+    appimage: bool, // a standalone AppImage, `path` is the AppImage itself
+    work_dir: Option<String>, // `Path=`, the directory to run the app in
+    recently_added: bool, // first listed within `recently_added_days`, see `mark_recently_added`
+    // `X-<vendor>-Version=..` if there is one, else the spec `Version=..`,
+    // only logged when a copy is shadowed
+    version: Option<String>,
+    // `Hidden=true`, the file deletes the entry: all that's kept of it is
+    // its ID, shadowing the copies of lower priority directories
+    hidden: bool,
+    #[serde(skip)] // rebuilt by `index` when read back from the disk cache
+    search: matcher::SearchKeys, // folded searchable fields, see `index`
+}
+
+impl AppInfo {
+    /// Computes the search keys, once the searchable fields are final.
+    fn index(mut self) -> Self {
+        self.search = matcher::SearchKeys::new(&self);
+        self
+    }
+
+    /// The name shown to the user.
+    fn display_name(&self) -> &str {
+        self.display_name.as_deref().unwrap_or(&self.name)
+    }
 }
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct Entry {
     pub name: *const c_char,        // the display name
     pub description: *const c_char, // still not sure what ill use this for, optional
     pub value: *const c_char,       // the value that is gonna be passed to `handle_selection`
     pub icon: *const c_char,        // icon path (can be null)
     pub emoji: *const c_char,       // emoji (can be null)
+    // `StartupWMClass` of the app (can be null), for hosts matching entries to
+    // open windows. Added in 2.0.0, a breaking change: it makes `Entry` bigger,
+    // and with it the step between the entries of a list, so hosts built for
+    // 1.0.0 must be rebuilt.
+    pub startup_wm_class: *const c_char,
 }
 
-unsafe impl Send for Entry {}
-unsafe impl Sync for Entry {}
+impl Entry {
+    /// An entry with every string null.
+    const fn null() -> Self {
+        let null = std::ptr::null();
+        Entry { name: null, description: null, value: null, icon: null, emoji: null, startup_wm_class: null }
+    }
+}
 
+/// A list of entries returned by `get_entries` or `get_all_entries`.
+///
+/// The list and every string it points to are owned by the list itself: they
+/// stay valid, whatever else the host calls, until it hands the list back to
+/// `free_entry_list`, and must not be touched afterwards.
+///
+/// On failure the list is empty with null `entries`, the reason going to the
+/// log callback.
 #[repr(C)]
 pub struct EntryList {
     pub entries: *const Entry,
     pub length: usize,
 }
 
+impl EntryList {
+    fn empty() -> Self {
+        EntryList {
+            entries: std::ptr::null(),
+            length: 0,
+        }
+    }
+}
+
+/// Bytes `start..end` of an entry's name matched by the query.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MatchRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// The matched ranges of one entry's name, in order and not overlapping.
+#[repr(C)]
+pub struct MatchRanges {
+    pub ranges: *const MatchRange,
+    pub length: usize,
+}
+
+/// The entries of `get_entries_highlighted`, with `ranges[i]` the matched
+/// ranges of `list.entries[i]`.
+///
+/// Owned by the plugin like an `EntryList`, it goes back to
+/// `free_highlighted_entry_list` as a whole.
+#[repr(C)]
+pub struct HighlightedEntryList {
+    pub list: EntryList,
+    pub ranges: *const MatchRanges,
+}
+
+// SAFETY: the pointers are to static string literals, never written
 unsafe impl Send for PluginInfo {}
 unsafe impl Sync for PluginInfo {}
 
 #[unsafe(no_mangle)]
 pub static PLUGIN_INFO: PluginInfo = PluginInfo {
     name: literal_as_c_char!("Application finder"),
-    version: literal_as_c_char!("1.0.0"),
+    version: literal_as_c_char!("2.0.0"),
     description: literal_as_c_char!("Find applications on your system"),
     author: literal_as_c_char!("Ri"),
     default_prefix: literal_as_c_char!(""),
 };
 
+/// Version of the C interface, see `abi_version`.
+const ABI_VERSION: u32 = 5;
+
+/// What `supports` knows about: the exports by name, `get_entries_begin`
+/// standing for its `_next` and `_end`, and the additions to the `repr(C)`
+/// types.
+const FEATURES: &[&str] = &[
+    "get_entries",
+    "get_all_entries",
+    "free_entry_list",
+    "handle_selection",
+    "handle_selection_ex",
+    "handle_selections",
+    "set_log_callback",
+    "init_config",
+    "reload_config",
+    "get_entries_highlighted",
+    "get_entries_for_mime",
+    "get_entries_implementing",
+    "get_entries_into",
+    "get_entries_begin",
+    "app_count",
+    "explain_match",
+    "is_running",
+    "warm_index",
+    "reset_frecency",
+    "set_activation_token",
+    "resolve_launch_command",
+    "plugin_cleanup",
+    // `Entry::startup_wm_class`
+    "entry_startup_wm_class",
+];
+
+/// Version of the plugin's C interface:
+///
+/// 1. `PLUGIN_INFO` 1.0.0: `get_entries`, `handle_selection` and `init_config`.
+/// 2. `PLUGIN_INFO` 2.0.0: `Entry::startup_wm_class`, which makes `Entry`
+///    bigger, and every other export of `FEATURES` up to `abi_version` and
+///    `supports` themselves.
+/// 3. `handle_selections`.
+/// 4. `get_entries_implementing`.
+/// 5. `is_running`.
+///
+/// From version 2 on, each version only adds exports, so a host built for
+/// version N works with any later one. Nothing is ever removed or changed
+/// under the same name. Appending a field to `Entry` changes the step between
+/// the entries of a list, and so needs a new major `PLUGIN_INFO` version.
+/// Hosts wanting one feature rather than a version can ask `supports`.
+#[unsafe(no_mangle)]
+pub extern "C" fn abi_version() -> u32 {
+    ABI_VERSION
+}
+
+/// Whether this build has `feature`: an export like `"get_entries_for_mime"`,
+/// or `"entry_startup_wm_class"` for the `Entry` field. False for null and
+/// for anything unknown.
+#[unsafe(no_mangle)]
+pub extern "C" fn supports(feature: *const c_char) -> bool {
+    guard("supports", false, || {
+        c_str_arg(feature)
+            .is_some_and(|feature| FEATURES.iter().any(|known| known.as_bytes() == feature.to_bytes()))
+    })
+}
+
+/// Registers the function the plugin sends its log messages to, replacing
+/// any previous one. Passing null goes back to logging on stderr.
+///
+/// `level` is 0 for errors, 1 warnings, 2 info and 3 debug. `msg` is a nul
+/// terminated string only valid during the call.
+#[unsafe(no_mangle)]
+pub extern "C" fn set_log_callback(cb: Option<extern "C" fn(level: c_int, msg: *const c_char)>) {
+    guard("set_log_callback", (), || log::set_callback(cb));
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn init_config(config: *const c_char) -> bool {
-    if config.is_null() {
-        println!("Config is null");
-        return false;
-    }
-    
-    let config_str = unsafe { CStr::from_ptr(config) };
+    guard("init_config", false, || parse_config_arg(config).map(apply_config).is_some())
+}
+
+/// Replaces the config of a running plugin, e.g. after the user changed a
+/// setting. Takes the same JSON as `init_config`.
+///
+/// Cached apps are dropped so changed search paths and filters apply to the
+/// next query. An invalid config leaves the current one in place.
+#[unsafe(no_mangle)]
+pub extern "C" fn reload_config(config: *const c_char) -> bool {
+    guard("reload_config", false, || {
+        let Some(parsed) = parse_config_arg(config) else {
+            return false;
+        };
+        apply_config(parsed);
+        cache::clear();
+        true
+    })
+}
+
+/// Reads the config JSON passed to `init_config` or `reload_config`, logging
+/// why it was rejected.
+fn parse_config_arg(config: *const c_char) -> Option<Config> {
+    let Some(config_str) = c_str_arg(config) else {
+        log_error!("Config is null");
+        return None;
+    };
     let config_json = config_str.to_str().unwrap_or("");
-    println!("Applist Plugin received config: {}", config_json);
-    
-    // For now, just acknowledge the config
-    // You can add actual config parsing here later if needed
-    true
+    log_info!("Applist Plugin received config: {}", config_json);
+
+    config::parse(config_json)
+        .map_err(|e| log_error!("Invalid config: {}", e))
+        .ok()
+}
+
+/// Stores `config` and starts or stops the watcher to match it.
+fn apply_config(config: Config) {
+    if config.watch_directories {
+        watch::restart(search_dirs(&config));
+    } else {
+        watch::stop();
+    }
+    rename::check(&config.name_replacements);
+    config::set(config);
 }
 
+/// Launches the app whose desktop file path is `selection`, the `value` of
+/// one of its entries.
+///
+/// Files or URIs to open with the app can follow the path, each prefixed by
+/// a `\x1f` (unit separator) byte, e.g. `"/path/app.desktop\x1f/home/me/a.txt"`.
+/// They are substituted into the `%f`/`%F`/`%u`/`%U` field codes of its Exec.
+///
+/// Returns whether the app was launched, `handle_selection_ex` tells why not.
 #[unsafe(no_mangle)]
 pub extern "C" fn handle_selection(selection: *const c_char) -> bool {
-    let sel = unsafe { CStr::from_ptr(selection) };
-    let path = sel.to_str().unwrap();
-    
-    // Load applications to check if this is a terminal app
-    if let Ok(apps) = load_applications() {
-        if let Some(app) = apps.iter().find(|app| app.path == path) {
-            return execute_gio_launch(path, app.terminal);
-        }
-    }
-    
-    // Fallback to default behavior
-    execute_gio_launch(path, false)
+    guard("handle_selection", false, || launch_selection(selection) == LaunchStatus::Launched)
 }
 
+/// Like `handle_selection`, returning a `LaunchStatus` code instead so the
+/// host can tell the user what went wrong: 0 when launched, 1 when the
+/// selection isn't an app, 2 when its desktop file failed validation and 3
+/// when it couldn't be started.
 #[unsafe(no_mangle)]
-pub extern "C" fn get_entries(query: *const c_char) -> EntryList {
-    let apps = load_applications().unwrap();
-    
-    let mut entries = Vec::new();
-    for app in apps {
-        let name = Box::leak(format!("{}\0", app.name).into_boxed_str());
-        let path = Box::leak(format!("{}\0", app.path).into_boxed_str());
-        let description = app
-            .description
-            .map(|d| Box::leak(format!("{}\0", d).into_boxed_str()));
-        let icon = app
-            .icon
-            .map(|i| Box::leak(format!("{}\0", i).into_boxed_str()));
-        let emoji = std::ptr::null();
-
-        entries.push(Entry {
-            name: name.as_ptr() as *const c_char,
-            value: path.as_ptr() as *const c_char,
-            description: description.map_or(std::ptr::null(), |s| s.as_ptr() as *const c_char),
-            icon: icon.map_or(std::ptr::null(), |s| s.as_ptr() as *const c_char),
-            emoji: emoji,
-        });
-    }
+pub extern "C" fn handle_selection_ex(selection: *const c_char) -> c_int {
+    let status = guard("handle_selection_ex", LaunchStatus::SpawnFailed, || launch_selection(selection));
+    status as c_int
+}
 
-    let mut filtered_entries = Vec::new();
-    let query_str = if query.is_null() || query as usize == 1 {
-        "".to_string()
-    } else {
-        unsafe { CStr::from_ptr(query).to_string_lossy().into_owned() }
+/// Launches the `count` selections in `values` one after the other, each
+/// like `handle_selection`, for hosts opening several results at once.
+/// Returns how many were launched, 0 if `values` is null.
+#[unsafe(no_mangle)]
+pub extern "C" fn handle_selections(values: *const *const c_char, count: usize) -> c_int {
+    guard("handle_selections", 0, || {
+        if values.is_null() {
+            log_error!("Selections are null");
+            return 0;
+        }
+        let values = unsafe { std::slice::from_raw_parts(values, count) };
+        let launched = values
+            .iter()
+            .filter(|&&selection| launch_selection(selection) == LaunchStatus::Launched)
+            .count();
+        c_int::try_from(launched).unwrap_or(c_int::MAX)
+    })
+}
+
+/// Outcome of a selection, the codes returned by `handle_selection_ex`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LaunchStatus {
+    Launched = 0,
+    /// The selection is no listed app, and no launcher knew it either.
+    NotFound = 1,
+    /// `validate_desktop_files` is on and the desktop file is invalid.
+    ValidationFailed = 2,
+    /// The app is listed, but every way of starting it failed.
+    SpawnFailed = 3,
+}
+
+fn launch_selection(selection: *const c_char) -> LaunchStatus {
+    let Some(sel) = c_str_arg(selection) else {
+        log_error!("Selection is null");
+        return LaunchStatus::NotFound;
     };
-    let query_str = query_str.to_lowercase();
-    
-    for entry in entries {
-        let name = unsafe { CStr::from_ptr(entry.name).to_string_lossy() };
-        let name_lower = name.to_lowercase();
-        if query_str.is_empty() || name_lower.contains(&query_str) {
-            filtered_entries.push(entry);
-        }
-    }
-    
-    let list = EntryList {
-        entries: filtered_entries.as_ptr() as *const Entry,
-        length: filtered_entries.len(),
+    let Ok(sel) = sel.to_str() else {
+        log_error!("Selection is not valid UTF-8: {}", sel.to_string_lossy());
+        return LaunchStatus::NotFound;
     };
-    std::mem::forget(filtered_entries);
-    list
+    let (path, files) = split_selection(sel);
+
+    log_debug!("Launching {} {:?}", path, files);
+
+    // Load applications to know how this one wants to be launched
+    let config = config::current();
+    let apps = load_applications(&config).unwrap_or_default();
+    let app = apps.iter().find(|app| app.path == path);
+    let status = launch(path, app, &files, &config);
+    if let Some(app) = app
+        && status == LaunchStatus::Launched
+    {
+        frecency::record_launch(&app.id);
+    }
+    status
 }
 
-fn parse_desktop_file(content: &str, path: &str) -> Option<AppInfo> {
-    let mut name = None;
-    let mut icon = None;
-    let mut description = None;
-    let mut _emoji: Option<String> = None; // i don't use emoji in this plugin
-    let mut in_desktop_entry = false;
-    let mut no_display = false;
-    let mut hidden = false;
-    let mut terminal = false; // Add terminal detection
+/// Launches `app`, or whatever `path` is to the system launchers when it
+/// isn't a listed app.
+fn launch(path: &str, app: Option<&AppInfo>, files: &[String], config: &Config) -> LaunchStatus {
+    // Refuse files that failed validation, when it's enabled
+    if let Some(error) = app.and_then(|app| app.validation_error.as_deref()) {
+        log_error!("Desktop file validation failed: {}", error);
+        return LaunchStatus::ValidationFailed;
+    }
+    match (execute_gio_launch(path, app, files, config), app) {
+        (true, _) => LaunchStatus::Launched,
+        (false, Some(_)) => LaunchStatus::SpawnFailed,
+        (false, None) => LaunchStatus::NotFound,
+    }
+}
 
-    for line in content.lines() {
-        let line = line.trim();
+/// Separates the files to open from the desktop file path in a selection.
+const ARG_SEPARATOR: char = '\x1f';
 
-        if line == "[Desktop Entry]" {
-            in_desktop_entry = true;
-            continue;
-        } else if line.starts_with('[') {
-            in_desktop_entry = false;
-            continue;
-        }
+/// Splits a selection into the desktop file path and the files following it.
+fn split_selection(selection: &str) -> (&str, Vec<String>) {
+    let mut parts = selection.split(ARG_SEPARATOR);
+    let path = parts.next().unwrap_or_default();
+    (path, parts.filter(|file| !file.is_empty()).map(str::to_string).collect())
+}
 
-        if !in_desktop_entry {
-            continue;
+#[unsafe(no_mangle)]
+pub extern "C" fn get_entries(query: *const c_char) -> EntryList {
+    guard("get_entries", EntryList::empty(), || {
+        let config = config::current();
+        let query = query_from_ptr(query);
+        if is_ignored_query(&query, &config) {
+            return EntryList::empty();
         }
+        let apps = match load_applications(&config) {
+            Ok(apps) => apps,
+            Err(e) => {
+                log_error!("Failed to load applications: {}", e);
+                return EntryList::empty();
+            }
+        };
 
-        if let Some((key, value)) = line.split_once('=') {
-            match key.trim() {
-                "Name" => name = Some(value.trim().to_string()),
-                "Icon" => icon = Some(value.trim().to_string()),
-                "Comment" => description = Some(value.trim().to_string()),
-                "NoDisplay" => no_display = value.trim().eq_ignore_ascii_case("true"),
-                "Hidden" => hidden = value.trim().eq_ignore_ascii_case("true"),
-                "Terminal" => terminal = value.trim().eq_ignore_ascii_case("true"), // Detect terminal apps
-                _ => {}
+        // only the returned apps get C strings allocated
+        let results = query_apps(&apps, &query, &config, &frecency::current(), frecency::now());
+        into_entry_list(results.into_iter().map(|found| entry_text(found.app, &config)).collect())
+    })
+}
+
+/// Every listed app, for hosts doing their own matching, in the order the
+/// desktop files were found. Filtered by the config like `get_entries`, but
+/// not capped by `max_results`.
+///
+/// Same ownership as `get_entries`, the list goes back to `free_entry_list`.
+#[unsafe(no_mangle)]
+pub extern "C" fn get_all_entries() -> EntryList {
+    guard("get_all_entries", EntryList::empty(), || {
+        let config = config::current();
+        match load_applications(&config) {
+            Ok(apps) => into_entry_list(apps.iter().map(|app| entry_text(app, &config)).collect()),
+            Err(e) => {
+                log_error!("Failed to load applications: {}", e);
+                EntryList::empty()
             }
         }
-    }
+    })
+}
 
-    if no_display || hidden {
-        return None;
-    }
+/// Like `get_entries`, with the bytes of each name matched by the query so the
+/// host can bold them. The ranges always fall on UTF-8 char boundaries of the
+/// name, an empty query matching nothing.
+///
+/// Added in 2.0.0, the list goes back to `free_highlighted_entry_list`.
+#[unsafe(no_mangle)]
+pub extern "C" fn get_entries_highlighted(query: *const c_char) -> HighlightedEntryList {
+    let empty = || HighlightedEntryList { list: EntryList::empty(), ranges: std::ptr::null() };
+    guard("get_entries_highlighted", empty(), || {
+        let config = config::current();
+        let query_str = query_from_ptr(query);
+        if is_ignored_query(&query_str, &config) {
+            return empty();
+        }
+        let apps = match load_applications(&config) {
+            Ok(apps) => apps,
+            Err(e) => {
+                log_error!("Failed to load applications: {}", e);
+                return empty();
+            }
+        };
 
-    match (name, icon) {
-        (Some(name), Some(icon)) => Some(AppInfo {
-            name,
-            description,
-            path: path.to_string(),
-            icon: Some(icon),
-            emoji: None,
-            terminal, // Set the terminal flag
-        }),
-        _ => None,
-    }
+        let results = query_apps(&apps, &query_str, &config, &frecency::current(), frecency::now());
+        let query = matcher::Query::new(&query_str);
+        let ranges: Box<[MatchRanges]> = results
+            .iter()
+            .map(|&ScoredApp { app, .. }| {
+                let highlighted = match &app.display_name {
+                    // the ranges are of the name the host shows, only the name
+                    // counts for them
+                    Some(display_name) => {
+                        let shown = AppInfo { name: display_name.clone(), ..AppInfo::default() }.index();
+                        matcher::highlight(&query, &shown, config.match_mode)
+                    }
+                    None => matcher::highlight(&query, app, config.match_mode),
+                };
+                let ranges: Box<[MatchRange]> = highlighted
+                    .into_iter()
+                    .map(|(start, end)| MatchRange { start, end })
+                    .collect();
+                let length = ranges.len();
+                MatchRanges { ranges: Box::into_raw(ranges) as *const MatchRange, length }
+            })
+            .collect();
+        HighlightedEntryList {
+            list: into_entry_list(results.into_iter().map(|found| entry_text(found.app, &config)).collect()),
+            ranges: Box::into_raw(ranges) as *const MatchRanges,
+        }
+    })
 }
 
-fn load_applications() -> Result<Vec<AppInfo>> {
-    let xdg_dirs = xdg::BaseDirectories::new();
-    let mut apps = Vec::new();
-    let mut seen_names = HashSet::new();
-    let home_dir = home::home_dir();
-    let mut paths: Vec<PathBuf> = Vec::new();
-    if let Some(home_dir) = home_dir {
-        let local_apps = home_dir.join(".local/share/applications");
-        paths.push(local_apps);
-        let xdg_paths = xdg_dirs.get_data_dirs();
-        paths.extend(xdg_paths);
-    } else {
-        return Err(anyhow::anyhow!("Failed to get home directory"));
-    }
-
-    for path in paths {
-        let apps_dir = if path.ends_with("applications") {
-            path
-        } else {
-            path.join("applications")
+/// The apps that can open files of type `mime` (e.g. `"image/png"`), for
+/// "open with" menus, as associated by their `MimeType` and the
+/// `mimeapps.list` files. The defaults come first, then the added
+/// associations, then the others in load order. Filtered by the config like
+/// `get_entries`, but not capped by `max_results`.
+///
+/// Same ownership as `get_entries`. A null `mime` gives an empty list.
+#[unsafe(no_mangle)]
+pub extern "C" fn get_entries_for_mime(mime: *const c_char) -> EntryList {
+    guard("get_entries_for_mime", EntryList::empty(), || {
+        let Some(mime) = c_str_arg(mime) else {
+            return EntryList::empty();
         };
-        if !apps_dir.exists() {
-            continue;
+        let mime = mime.to_string_lossy();
+        let config = config::current();
+        match load_applications(&config) {
+            Ok(apps) => {
+                let associations = mimeapps::Associations::load(&mime, &current_desktops());
+                let apps = apps_for_mime(&apps, &mime, &associations);
+                into_entry_list(apps.into_iter().map(|app| entry_text(app, &config)).collect())
+            }
+            Err(e) => {
+                log_error!("Failed to load applications: {}", e);
+                EntryList::empty()
+            }
         }
+    })
+}
 
-        for entry in std::fs::read_dir(apps_dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            
-            if path.extension().and_then(|s| s.to_str()) != Some("desktop") {
-                continue;
+/// The apps whose `Implements` lists the D-Bus interface `iface` (e.g.
+/// `"org.freedesktop.FileManager1"`), in load order. Filtered by the config
+/// like `get_entries`, but not capped by `max_results`.
+///
+/// Same ownership as `get_entries`. A null `iface` gives an empty list.
+#[unsafe(no_mangle)]
+pub extern "C" fn get_entries_implementing(iface: *const c_char) -> EntryList {
+    guard("get_entries_implementing", EntryList::empty(), || {
+        let Some(iface) = c_str_arg(iface) else {
+            return EntryList::empty();
+        };
+        let iface = iface.to_string_lossy();
+        let config = config::current();
+        match load_applications(&config) {
+            Ok(apps) => into_entry_list(
+                apps.iter()
+                    .filter(|app| app.implements.iter().any(|i| *i == iface))
+                    .map(|app| entry_text(app, &config))
+                    .collect(),
+            ),
+            Err(e) => {
+                log_error!("Failed to load applications: {}", e);
+                EntryList::empty()
             }
+        }
+    })
+}
 
-            if path.starts_with("/tmp") {
-                continue;
-            }
+/// The apps offered for `mime`, in `associations` order.
+fn apps_for_mime<'a>(
+    apps: &'a [AppInfo],
+    mime: &str,
+    associations: &mimeapps::Associations,
+) -> Vec<&'a AppInfo> {
+    let mut matching: Vec<&AppInfo> = apps
+        .iter()
+        .filter(|app| {
+            let declared = app.mime_types.iter().any(|m| m.eq_ignore_ascii_case(mime));
+            associations.offers(&app.id, declared)
+        })
+        .collect();
+    // stable, the others keep the load order
+    matching.sort_by_key(|app| associations.rank(&app.id));
+    matching
+}
 
-            if !path.exists() || !path.is_file() {
-                continue;
-            }
+/// Entries of the last `get_entries_into` call, whose strings the host reads.
+struct Scratch(Vec<Entry>);
 
-            let content = match fs::read_to_string(&path) {
-                Ok(content) => content,
-                Err(e) => {
-                    println!("Failed to read desktop file: {}", e);
-                    continue;
-                }
-            };
-            if let Some(app_info) = parse_desktop_file(&content, &path.to_string_lossy()) {
-                if seen_names.insert(app_info.name.clone()) {
-                    apps.push(app_info);
-                }
+// SAFETY: the strings are owned by the scratch entries alone, they're only
+// read through the lock and freed through it
+unsafe impl Send for Scratch {}
+
+static SCRATCH: std::sync::Mutex<Scratch> = std::sync::Mutex::new(Scratch(Vec::new()));
+
+/// Like `get_entries`, but writes the entries into `out`, an array of `cap`
+/// entries owned by the host, so there's no list to free.
+///
+/// Returns the number of results. When that's more than `cap`, only the first
+/// `cap` were written and the call can be repeated with a bigger array; a null
+/// `out` with a `cap` of 0 just counts them. Returns 0 if the apps can't be
+/// loaded.
+///
+/// The strings stay owned by the plugin. They remain valid until the next
+/// `get_entries_into` call or `plugin_cleanup`, so a host keeping them longer
+/// must copy them. Calls from several threads at once invalidate each
+/// other's strings.
+#[unsafe(no_mangle)]
+pub extern "C" fn get_entries_into(query: *const c_char, out: *mut Entry, cap: usize) -> usize {
+    guard("get_entries_into", 0, || {
+        let config = config::current();
+        let query = query_from_ptr(query);
+        if is_ignored_query(&query, &config) {
+            return 0;
+        }
+        let apps = match load_applications(&config) {
+            Ok(apps) => apps,
+            Err(e) => {
+                log_error!("Failed to load applications: {}", e);
+                return 0;
             }
+        };
+        let results = query_apps(&apps, &query, &config, &frecency::current(), frecency::now());
+        let cap = if out.is_null() { 0 } else { cap };
+
+        let Scratch(scratch) = &mut *SCRATCH.lock().unwrap_or_else(|e| e.into_inner());
+        scratch.iter().for_each(free_entry);
+        *scratch = results.iter().take(cap).map(|found| app_to_entry(found.app, &config)).collect();
+        for (i, entry) in scratch.iter().enumerate() {
+            unsafe { out.add(i).write(*entry) };
+        }
+        results.len()
+    })
+}
+
+/// Frees the strings kept for the last `get_entries_into` call.
+fn clear_scratch() {
+    let Scratch(scratch) = &mut *SCRATCH.lock().unwrap_or_else(|e| e.into_inner());
+    scratch.iter().for_each(free_entry);
+    scratch.clear();
+}
+
+/// The results of a query handed out one at a time, see `get_entries_begin`.
+/// Opaque to the host.
+pub struct EntryIter {
+    apps: std::vec::IntoIter<AppInfo>,
+    config: std::sync::Arc<Config>,
+    // the entry handed out last, whose strings the host may still be reading
+    current: Option<Entry>,
+}
+
+impl Drop for EntryIter {
+    fn drop(&mut self) {
+        if let Some(entry) = self.current.take() {
+            free_entry(&entry);
         }
     }
-    Ok(apps)
 }
 
-#[cfg(not(test))]
-fn execute_gio_launch(path: &str, terminal: bool) -> bool {
-    // First, validate the desktop file
-    let validate_result = Command::new("desktop-file-validate")
-        .arg(path)
-        .output();
-    
-    if let Ok(output) = validate_result {
-        if !output.status.success() {
-            println!("Desktop file validation failed: {}", String::from_utf8_lossy(&output.stderr));
+/// Starts iterating over the results of `query`, ranked and capped like
+/// `get_entries`, so the host can show them as they come and stop early.
+///
+/// The handle goes to `get_entries_next` and must be passed to
+/// `get_entries_end` exactly once. Null if the apps can't be loaded, which
+/// both accept as an empty iteration.
+#[unsafe(no_mangle)]
+pub extern "C" fn get_entries_begin(query: *const c_char) -> *mut EntryIter {
+    guard("get_entries_begin", std::ptr::null_mut(), || {
+        let config = config::current();
+        let query = query_from_ptr(query);
+        if is_ignored_query(&query, &config) {
+            let apps = Vec::new().into_iter();
+            return Box::into_raw(Box::new(EntryIter { apps, config, current: None }));
+        }
+        let apps = match load_applications(&config) {
+            Ok(apps) => apps,
+            Err(e) => {
+                log_error!("Failed to load applications: {}", e);
+                return std::ptr::null_mut();
+            }
+        };
+        // entries are only built as they are asked for
+        let results = query_apps(&apps, &query, &config, &frecency::current(), frecency::now());
+        let results: Vec<AppInfo> = results.into_iter().map(|found| found.app.clone()).collect();
+        Box::into_raw(Box::new(EntryIter { apps: results.into_iter(), config, current: None }))
+    })
+}
+
+/// Writes the next result of `iter` into `out`, returning false once there
+/// are none left.
+///
+/// The strings of the entry are owned by the plugin and valid until the next
+/// call with the same handle or `get_entries_end`.
+#[unsafe(no_mangle)]
+pub extern "C" fn get_entries_next(iter: *mut EntryIter, out: *mut Entry) -> bool {
+    guard("get_entries_next", false, || {
+        if iter.is_null() || out.is_null() {
             return false;
         }
-    }
-    
-    // For terminal applications, launch them directly in a terminal
-    if terminal {
-        // Try to find a terminal emulator
-        let terminals = ["gnome-terminal", "konsole", "xterm", "alacritty", "kitty", "urxvt", "st"];
-        
-        for terminal_cmd in &terminals {
-            // Extract the executable from the desktop file
-            if let Ok(content) = std::fs::read_to_string(path) {
-                if let Some(exec_line) = content.lines().find(|line| line.starts_with("Exec=")) {
-                    let exec_cmd = exec_line.strip_prefix("Exec=").unwrap_or("");
-                    // Remove % parameters and clean up the command
-                    let clean_cmd = exec_cmd.split_whitespace()
-                        .next()
-                        .unwrap_or("")
-                        .trim();
-                    
-                    if !clean_cmd.is_empty() {
-                        let result = Command::new(terminal_cmd)
-                            .args(["-e", clean_cmd])
-                            .spawn();
-                        
-                        if let Ok(_) = result {
-                            return true;
-                        }
-                    }
-                }
+        let iter = unsafe { &mut *iter };
+        if let Some(entry) = iter.current.take() {
+            free_entry(&entry);
+        }
+        let Some(app) = iter.apps.next() else {
+            return false;
+        };
+        let entry = app_to_entry(&app, &iter.config);
+        iter.current = Some(entry);
+        unsafe { out.write(entry) };
+        true
+    })
+}
+
+/// Frees `iter` and the strings of its last entry, whether or not it was
+/// iterated to the end.
+#[unsafe(no_mangle)]
+pub extern "C" fn get_entries_end(iter: *mut EntryIter) {
+    guard("get_entries_end", (), || {
+        if !iter.is_null() {
+            drop(unsafe { Box::from_raw(iter) });
+        }
+    })
+}
+
+/// An app `query_apps` found.
+#[derive(Clone, Copy)]
+struct ScoredApp<'a> {
+    app: &'a AppInfo,
+    /// What it ranked with, launches included. `None` when only found as a
+    /// typo of the query. Only the tests look at it yet, the order says it all
+    /// for the exports.
+    #[cfg_attr(not(test), allow(dead_code))]
+    score: Option<i64>,
+}
+
+/// The apps matching `query_str`, best first and at most `max_results`.
+///
+/// Everything `get_entries` does short of loading the apps and handing out C
+/// strings, the part to test and time. Launches in `history` count as of `now`.
+fn query_apps<'a>(
+    apps: &'a [AppInfo],
+    query_str: &str,
+    config: &Config,
+    history: &frecency::Frecency,
+    now: u64,
+) -> Vec<ScoredApp<'a>> {
+    let query = matcher::Query::new(query_str);
+
+    let mut scored = Vec::new();
+    let mut unmatched = Vec::new();
+    for app in apps {
+        match matcher::rank_app(&query, app, config.match_mode, &config.weights) {
+            Some(score) => {
+                let score = score + matcher::frecency_bonus(history.value(&app.id, now), &config.weights);
+                scored.push((score, app));
             }
+            None => unmatched.push(app),
         }
     }
-    
-    // For non-terminal apps, try gtk-launch first
-    let gtk_result = Command::new("gtk-launch")
-        .arg(path)
-        .status();
-    
-    if let Ok(status) = gtk_result {
-        if status.success() {
-            return true;
+    // the sorts are stable, so equal scores keep the order they had before
+    if query.is_empty() {
+        // every app ranks the same, only its launches count
+        match config.sort_mode {
+            SortMode::Alphabetical => {
+                let collator = collate::Collator::current();
+                scored.sort_by_cached_key(|(_, app)| collator.sort_key(app))
+            }
+            SortMode::Frecency => {
+                let collator = collate::Collator::current();
+                scored.sort_by_cached_key(|(_, app)| collator.sort_key(app));
+                scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+            }
+            SortMode::None => {}
         }
+    } else {
+        scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
     }
-    
-    // Fallback to gio launch
-    Command::new("gio")
-        .args(["launch", path])
-        .status()
-        .map(|status| status.success())
-        .unwrap_or(false)
-}
+    // stable too, the demoted apps keep their order among themselves
+    scored.sort_by_key(|(_, app)| is_demoted(app, config));
+    let mut results: Vec<ScoredApp> =
+        scored.into_iter().map(|(score, app)| ScoredApp { app, score: Some(score) }).collect();
 
-#[cfg(test)]
-fn execute_gio_launch(_path: &str, _terminal: bool) -> bool {
-    true
+    // few apps match, the query may have a typo in it; the apps that didn't
+    // match at all come after those that did
+    if results.len() < config.typo_fallback_below && config.max_typos > 0 {
+        let mut near: Vec<(usize, &AppInfo)> = unmatched
+            .into_iter()
+            .filter_map(|app| Some((matcher::typo_distance(&query, app, config.max_typos)?, app)))
+            .collect();
+        near.sort_by_key(|(distance, _)| *distance);
+        results.extend(near.into_iter().map(|(_, app)| ScoredApp { app, score: None }));
+    }
+    results.truncate(config.max_results);
+    results
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Address some hosts pass for an empty query: Rust hosts calling with
+/// `"".as_ptr()` hand over the dangling pointer of an empty slice, which is 1
+/// for bytes rather than null.
+const EMPTY_QUERY_SENTINEL: usize = 1;
 
-    #[test]
-    fn test_load_applications() {
-        let apps = load_applications().unwrap();
-        assert!(!apps.is_empty());
+/// Nothing is ever mapped in the first page, any pointer in it is bogus.
+const NULL_PAGE_SIZE: usize = 4096;
+
+/// Borrows a C string argument from the host. Null and other pointers into
+/// the null page give `None` instead of being dereferenced.
+fn c_str_arg<'a>(ptr: *const c_char) -> Option<&'a CStr> {
+    if (ptr as usize) < NULL_PAGE_SIZE {
+        if !ptr.is_null() && ptr as usize != EMPTY_QUERY_SENTINEL {
+            log_warn!("Ignoring invalid string pointer {:p}", ptr);
+        }
+        return None;
     }
+    Some(unsafe { CStr::from_ptr(ptr) })
+}
 
-    #[test]
-    fn get_entries_test() {
-        let entries = get_entries(literal_as_c_char!(""));
-        assert!(!entries.entries.is_null());
-        assert!(entries.length > 0);
+/// Reads the query passed to `get_entries`, null or the empty-slice sentinel
+/// meaning an empty query.
+fn query_from_ptr(query: *const c_char) -> String {
+    c_str_arg(query).map_or_else(String::new, |q| q.to_string_lossy().into_owned())
+}
+
+/// Whether `query` starts with one of the `ignore_query_prefixes`, meaning
+/// it's for another plugin, or is shorter than `min_query_length`, and no app
+/// should be looked for.
+fn is_ignored_query(query: &str, config: &Config) -> bool {
+    let query = query.trim_start();
+    let length = query.trim_end().chars().count();
+    if length > 0 && length < config.min_query_length {
+        return true;
     }
+    config.ignore_query_prefixes.iter().any(|prefix| !prefix.is_empty() && query.starts_with(prefix.as_str()))
+}
 
-    #[test]
-    fn handle_selection_test() {
-        let selection = literal_as_c_char!("firefox");
-        let result = handle_selection(selection);
-        assert!(result);
+/// Number of apps `get_entries` picks from, shadowed duplicates counted once.
+///
+/// Shares the cache with `get_entries`, so calling it early warms the index.
+/// Returns 0 if the apps can't be loaded.
+#[unsafe(no_mangle)]
+pub extern "C" fn app_count() -> usize {
+    guard("app_count", 0, || {
+        match load_applications(&config::current()) {
+            Ok(apps) => apps.len(),
+            Err(e) => {
+                log_error!("Failed to load applications: {}", e);
+                0
+            }
+        }
+    })
+}
+
+/// Why the app with desktop ID `desktop_id` ranks where it does for `query`,
+/// as a JSON object with the weighted rank of each field, the launch history
+/// bonus and the total `get_entries` sorts by:
+///
+/// `{"name": 1234, "generic_name": null, "keywords": null, "comment": 56,
+/// "exec": null, "frecency": 0, "total": 1234}`
+///
+/// A `total` of null means the app doesn't match. Meant for tuning the
+/// matcher, not for showing to users.
+///
+/// Returns null if no listed app has that ID. The string is owned by the
+/// plugin and valid until the next call.
+#[unsafe(no_mangle)]
+pub extern "C" fn explain_match(query: *const c_char, desktop_id: *const c_char) -> *const c_char {
+    guard("explain_match", std::ptr::null(), || {
+        static EXPLANATION: std::sync::Mutex<Option<CString>> = std::sync::Mutex::new(None);
+
+        let Some(desktop_id) = c_str_arg(desktop_id).map(CStr::to_string_lossy) else {
+            log_error!("Desktop ID is null");
+            return std::ptr::null();
+        };
+        let config = config::current();
+        let apps = match load_applications(&config) {
+            Ok(apps) => apps,
+            Err(e) => {
+                log_error!("Failed to load applications: {}", e);
+                return std::ptr::null();
+            }
+        };
+        let Some(app) = apps.iter().find(|app| app.id == desktop_id) else {
+            log_warn!("No application with ID {}", desktop_id);
+            return std::ptr::null();
+        };
+
+        let query = matcher::Query::new(&query_from_ptr(query));
+        let fields = matcher::explain(&query, app, config.match_mode, &config.weights);
+        let launches = frecency::current().value(&app.id, frecency::now());
+        let frecency = matcher::frecency_bonus(launches, &config.weights);
+        let mut json = serde_json::to_value(&fields).unwrap_or_default();
+        json["frecency"] = frecency.into();
+        json["total"] = fields.best().map(|best| best + frecency).into();
+
+        let mut explanation = EXPLANATION.lock().unwrap_or_else(|e| e.into_inner());
+        let explanation = explanation.insert(CString::new(json.to_string()).unwrap_or_default());
+        explanation.as_ptr()
+    })
+}
+
+/// Whether the app with desktop ID `desktop_id` seems to be running, for
+/// hosts focusing it rather than launching it again: a process goes by the
+/// name of its Exec binary or its `StartupWMClass` (see `running`).
+///
+/// Best effort, false for null, unknown IDs and when `/proc` can't be read.
+#[unsafe(no_mangle)]
+pub extern "C" fn is_running(desktop_id: *const c_char) -> bool {
+    guard("is_running", false, || {
+        let Some(desktop_id) = c_str_arg(desktop_id).map(CStr::to_string_lossy) else {
+            log_error!("Desktop ID is null");
+            return false;
+        };
+        let apps = match load_applications(&config::current()) {
+            Ok(apps) => apps,
+            Err(e) => {
+                log_error!("Failed to load applications: {}", e);
+                return false;
+            }
+        };
+        let Some(app) = apps.iter().find(|app| app.id == desktop_id) else {
+            log_warn!("No application with ID {}", desktop_id);
+            return false;
+        };
+        running::is_running(app_binary(app).as_deref(), app.startup_wm_class.as_deref())
+    })
+}
+
+/// The basename of the program `app` runs, past any `env` prefix.
+fn app_binary(app: &AppInfo) -> Option<String> {
+    let (_, argv) = exec::split_env(exec_argv(app, &[]));
+    let program = argv.into_iter().next()?;
+    Some(program.rsplit('/').next().unwrap_or_default().to_string())
+}
+
+/// Starts scanning the application directories on a background thread, so
+/// the first `get_entries` finds them cached instead of reading every file.
+///
+/// Returns right away. Calling `get_entries` meanwhile is fine, it waits for
+/// the scan to finish rather than starting another one.
+#[unsafe(no_mangle)]
+pub extern "C" fn warm_index() {
+    guard("warm_index", (), || {
+        spawn_warm_up();
+    });
+}
+
+fn spawn_warm_up() -> Option<std::thread::JoinHandle<()>> {
+    let spawned = std::thread::Builder::new()
+        .name("applist-warm".to_string())
+        .spawn(|| {
+            // the cache keeps the result, the apps themselves aren't needed
+            if let Err(e) = load_applications(&config::current()) {
+                log_warn!("Failed to warm up the application index: {}", e);
+            }
+        });
+    spawned.map_err(|e| log_warn!("Failed to start warming up: {}", e)).ok()
+}
+
+/// Forgets all recorded launches, so ranking no longer favors any app.
+#[unsafe(no_mangle)]
+pub extern "C" fn reset_frecency() {
+    guard("reset_frecency", (), frecency::reset);
+}
+
+/// Releases everything the plugin holds: the directory watcher, the cached
+/// apps and icons, the launch history, the stored config, the strings of
+/// `get_entries_into` and the log callback.
+///
+/// Meant for hosts unloading or reloading the plugin. Launches are written to
+/// disk as they happen, so no history is lost. The plugin stays usable
+/// afterwards, starting from scratch as if freshly loaded.
+#[unsafe(no_mangle)]
+pub extern "C" fn plugin_cleanup() {
+    guard("plugin_cleanup", (), || {
+        watch::stop();
+        cache::clear();
+        icon::clear_cache();
+        frecency::unload();
+        clear_scratch();
+        config::clear();
+        log::set_callback(None);
+    })
+}
+
+/// Releases a list returned by `get_entries` or `get_all_entries`, including
+/// all of its strings.
+///
+/// Every list must be freed exactly once, after which its entries and strings
+/// are dangling.
+#[unsafe(no_mangle)]
+pub extern "C" fn free_entry_list(list: EntryList) {
+    guard("free_entry_list", (), || {
+        if list.entries.is_null() {
+            return;
+        }
+        // rebuilds exactly the boxed slice leaked by `into_entry_list`, with
+        // the arena entry after the last one
+        let entries = unsafe {
+            Box::from_raw(std::ptr::slice_from_raw_parts_mut(list.entries as *mut Entry, list.length + 1))
+        };
+        let arena = entries[list.length];
+        let length = unsafe { arena.value.offset_from(arena.name) } as usize;
+        drop(unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(arena.name as *mut u8, length)) });
+        #[cfg(test)]
+        LIVE_ARENAS.with(|live| live.set(live.get() - 1));
+    })
+}
+
+/// Frees a list returned by `get_entries_highlighted`, entries and ranges
+/// alike, the same way as `free_entry_list`.
+#[unsafe(no_mangle)]
+pub extern "C" fn free_highlighted_entry_list(list: HighlightedEntryList) {
+    guard("free_highlighted_entry_list", (), || {
+        if !list.ranges.is_null() {
+            // one `MatchRanges` per entry, as leaked by `get_entries_highlighted`
+            let ranges = unsafe {
+                Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+                    list.ranges as *mut MatchRanges,
+                    list.list.length,
+                ))
+            };
+            for ranges in ranges.iter() {
+                drop(unsafe {
+                    Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+                        ranges.ranges as *mut MatchRange,
+                        ranges.length,
+                    ))
+                });
+            }
+        }
+        free_entry_list(list.list);
+    })
+}
+
+fn free_entry(entry: &Entry) {
+    free_c_string(entry.name);
+    free_c_string(entry.description);
+    free_c_string(entry.value);
+    free_c_string(entry.icon);
+    free_c_string(entry.emoji);
+    free_c_string(entry.startup_wm_class);
+}
+
+#[cfg(test)]
+thread_local! {
+    // strings handed out by `to_c_string` and not freed yet, per test thread
+    static LIVE_C_STRINGS: std::cell::Cell<isize> = const { std::cell::Cell::new(0) };
+    // arenas of `into_entry_list` not freed yet
+    static LIVE_ARENAS: std::cell::Cell<isize> = const { std::cell::Cell::new(0) };
+}
+
+/// Allocates a C string for the entries outside of an `EntryList`, freed by
+/// `free_entry`.
+fn to_c_string(s: &str) -> *const c_char {
+    // interior nul bytes can't be represented, drop them
+    let s = CString::new(s.replace('\0', "")).unwrap_or_default();
+    #[cfg(test)]
+    LIVE_C_STRINGS.with(|live| live.set(live.get() + 1));
+    s.into_raw()
+}
+
+fn free_c_string(s: *const c_char) {
+    if s.is_null() {
+        return;
+    }
+    #[cfg(test)]
+    LIVE_C_STRINGS.with(|live| live.set(live.get() - 1));
+    drop(unsafe { CString::from_raw(s as *mut c_char) });
+}
+
+/// The strings of the entry of an app, before they become C strings.
+struct EntryText {
+    name: String,
+    description: Option<String>,
+    value: String,
+    icon: Option<String>,
+    emoji: Option<String>,
+    startup_wm_class: Option<String>,
+}
+
+impl EntryText {
+    /// The fields, in the order of `Entry`.
+    fn fields(&self) -> [Option<&str>; 6] {
+        [
+            Some(&self.name),
+            self.description.as_deref(),
+            Some(&self.value),
+            self.icon.as_deref(),
+            self.emoji.as_deref(),
+            self.startup_wm_class.as_deref(),
+        ]
+    }
+}
+
+/// An entry with its strings allocated one by one, see `free_entry`.
+fn app_to_entry(app: &AppInfo, config: &Config) -> Entry {
+    let [name, description, value, icon, emoji, startup_wm_class] =
+        entry_text(app, config).fields().map(|field| field.map_or(std::ptr::null(), to_c_string));
+    Entry { name, description, value, icon, emoji, startup_wm_class }
+}
+
+fn entry_text(app: &AppInfo, config: &Config) -> EntryText {
+    // the generic name tells more than nothing when there's no comment
+    let description = app.description.as_deref().or(app.generic_name.as_deref());
+    let source = || match app.exec.as_deref().map(exec::display_exec) {
+        Some(command) if !command.is_empty() => format!("{} from {}", command, app.path),
+        _ => app.path.clone(),
+    };
+    let description = match description {
+        _ if !config.show_source_in_description => description.map(str::to_string),
+        Some(description) => Some(format!("{} ({})", description, source())),
+        None => Some(source()),
+    };
+    let description = match description {
+        _ if !app.recently_added => description,
+        Some(description) => Some(format!("New · {}", description)),
+        None => Some("New".to_string()),
+    };
+    EntryText {
+        name: app.display_name().to_string(),
+        value: app.path.clone(),
+        description,
+        icon: app.icon.as_deref().map(|icon| {
+            // the raw name is still useful to hosts doing their own lookup
+            let size = config.preferred_icon_size;
+            icon::resolve_icon(icon, size, config.icon_theme.as_deref()).unwrap_or_else(|| icon.to_string())
+        }),
+        emoji: app.emoji.clone(),
+        startup_wm_class: app.startup_wm_class.clone(),
+    }
+}
+
+/// Leaks `entries` as one boxed slice for the host, `free_entry_list` takes
+/// it back with `Box::from_raw`.
+///
+/// All the strings go into one arena, a boxed byte slice of nul-terminated
+/// strings leaked along with the entries. An extra entry after the last one,
+/// not counted in `length`, points to the start of the arena in `name` and
+/// to its end in `value` for `free_entry_list`.
+fn into_entry_list(entries: Vec<EntryText>) -> EntryList {
+    let mut arena = Vec::new();
+    let offsets: Vec<[Option<usize>; 6]> = entries
+        .iter()
+        .map(|entry| {
+            entry.fields().map(|field| {
+                field.map(|s| {
+                    let offset = arena.len();
+                    // interior nul bytes can't be represented, drop them
+                    arena.extend(s.bytes().filter(|&byte| byte != 0));
+                    arena.push(0);
+                    offset
+                })
+            })
+        })
+        .collect();
+    let length = arena.len();
+    let start = Box::into_raw(arena.into_boxed_slice()) as *mut u8 as *const c_char;
+    #[cfg(test)]
+    LIVE_ARENAS.with(|live| live.set(live.get() + 1));
+    let at = |offset: Option<usize>| offset.map_or(std::ptr::null(), |offset| unsafe { start.add(offset) });
+    let mut list: Vec<Entry> = offsets
+        .into_iter()
+        .map(|offsets| {
+            let [name, description, value, icon, emoji, startup_wm_class] = offsets.map(at);
+            Entry { name, description, value, icon, emoji, startup_wm_class }
+        })
+        .collect();
+    list.push(Entry {
+        name: start,
+        value: unsafe { start.add(length) },
+        ..Entry::null()
+    });
+    let entries = list.into_boxed_slice();
+    EntryList {
+        length: entries.len() - 1,
+        entries: Box::into_raw(entries) as *const Entry,
+    }
+}
+
+/// `parse_desktop_file_with`, strict.
+#[cfg(test)]
+fn parse_desktop_file(content: &str, path: &str, locales: &[String]) -> Option<AppInfo> {
+    parse_desktop_file_with(content, path, locales, false)
+}
+
+/// Parses the `[Desktop Entry]` group of a desktop file.
+///
+/// `locales` are the locale keys to try for `Name[..]`/`Comment[..]`, most
+/// specific first (see `locale_candidates`). When `lenient`, a file without
+/// any `[Desktop Entry]` header has the keys at its top, before any other
+/// group, read as the entry instead of being no app at all, and an entry
+/// without a `Name` is named after its file, `foo.desktop` becoming `foo`.
+fn parse_desktop_file_with(content: &str, path: &str, locales: &[String], lenient: bool) -> Option<AppInfo> {
+    // localized values are stored with the rank of their locale in `locales`,
+    // the unlocalized value ranks last
+    let mut name: Option<(usize, String)> = None;
+    let mut icon = None;
+    let mut description: Option<(usize, String)> = None;
+    let mut generic_name: Option<(usize, String)> = None;
+    let mut keywords: Option<(usize, Vec<String>)> = None;
+    let mut vendor_keywords: Vec<(String, Localized<Vec<String>>)> = Vec::new();
+    let mut in_desktop_entry = false;
+    let mut no_display = false;
+    let mut hidden = false;
+    let mut terminal = false; // Add terminal detection
+    let mut dbus_activatable = false;
+    let mut exec = None;
+    let mut try_exec = None;
+    let mut entry_type = None;
+    let mut startup_wm_class = None;
+    let mut startup_notify = false;
+    let mut work_dir = None;
+    let mut only_show_in = Vec::new();
+    let mut categories = Vec::new();
+    let mut mime_types = Vec::new();
+    let mut implements = Vec::new();
+    let mut not_show_in = Vec::new();
+    let mut spec_version = None;
+    let mut vendor_version = None;
+    // keys already read, the spec has the first occurrence of a key win
+    let mut seen_keys = HashSet::new();
+
+    // some editors on Windows start files with a byte order mark, which would
+    // hide the group header
+    let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    if lenient && !content.lines().any(|line| line.trim() == "[Desktop Entry]") {
+        in_desktop_entry = true;
+    }
+    // `lines` splits on both "\n" and "\r\n", `trim` then drops a stray "\r"
+    for line in content.lines() {
+        let line = line.trim();
+
+        if line == "[Desktop Entry]" {
+            in_desktop_entry = true;
+            continue;
+        } else if line.starts_with('[') {
+            in_desktop_entry = false;
+            continue;
+        }
+
+        if !in_desktop_entry {
+            continue;
+        }
+
+        if let Some((key, value)) = line.split_once('=') {
+            let key = key.trim();
+            if !seen_keys.insert(key) {
+                continue;
+            }
+            if let Some((key, locale)) = key.split_once('[') {
+                let locale = locale.trim_end_matches(']');
+                if let Some(rank) = locales.iter().position(|l| l == locale) {
+                    match key {
+                        "Name" => set_localized(&mut name, rank, value.trim().to_string()),
+                        "Comment" => set_localized(&mut description, rank, value.trim().to_string()),
+                        "GenericName" => set_localized(&mut generic_name, rank, value.trim().to_string()),
+                        "Keywords" => set_localized(&mut keywords, rank, split_desktop_list(value)),
+                        _ => set_vendor_keywords(&mut vendor_keywords, key, rank, value),
+                    }
+                }
+                continue;
+            }
+
+            match key {
+                "Name" => set_localized(&mut name, locales.len(), value.trim().to_string()),
+                "Icon" => icon = Some(value.trim().to_string()),
+                "Comment" => set_localized(&mut description, locales.len(), value.trim().to_string()),
+                "GenericName" => set_localized(&mut generic_name, locales.len(), value.trim().to_string()),
+                "Keywords" => set_localized(&mut keywords, locales.len(), split_desktop_list(value)),
+                "NoDisplay" => no_display = value.trim().eq_ignore_ascii_case("true"),
+                "Hidden" => hidden = value.trim().eq_ignore_ascii_case("true"),
+                "Terminal" => terminal = value.trim().eq_ignore_ascii_case("true"), // Detect terminal apps
+                "Exec" => exec = Some(value.trim().to_string()),
+                "DBusActivatable" => dbus_activatable = value.trim().eq_ignore_ascii_case("true"),
+                "TryExec" => try_exec = Some(value.trim().to_string()),
+                "StartupWMClass" => startup_wm_class = Some(value.trim().to_string()),
+                "StartupNotify" => startup_notify = value.trim().eq_ignore_ascii_case("true"),
+                "Path" => work_dir = Some(value.trim().to_string()).filter(|dir| !dir.is_empty()),
+                "Type" => entry_type = Some(value.trim().to_string()),
+                "OnlyShowIn" => only_show_in = split_desktop_list(value),
+                "Categories" => categories = split_desktop_list(value),
+                "MimeType" => mime_types = split_desktop_list(value),
+                "Implements" => implements = split_desktop_list(value),
+                "NotShowIn" => not_show_in = split_desktop_list(value),
+                "Version" => spec_version = Some(format!("Version={}", value.trim())),
+                _ if key.starts_with("X-") && key.ends_with("-Version") => {
+                    vendor_version = Some(format!("{}={}", key, value.trim()))
+                }
+                _ => set_vendor_keywords(&mut vendor_keywords, key, locales.len(), value),
+            }
+        }
+    }
+
+    // `scan_dir` knows the applications directory and computes the real ID
+    let id = Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    // Hidden means the entry was deleted, NoDisplay only that it isn't listed.
+    // Whatever else it has, a deleted entry still hides the other copies.
+    if hidden {
+        return Some(AppInfo { id, path: path.to_string(), hidden: true, ..AppInfo::default() });
+    }
+
+    // Links and Directories can't be launched. The spec requires Type, but
+    // enough files in the wild omit it that a missing one means Application.
+    if entry_type.is_some_and(|t| t != "Application") {
+        return None;
+    }
+
+    // nothing to run, it would show up and then fail to launch
+    let exec = exec.filter(|exec| !exec.trim().is_empty());
+    if exec.is_none() && !dbus_activatable {
+        return None;
+    }
+
+    // only the name is required, launchers without an icon are still valid;
+    // leniently the file name stands in for it
+    let name = match name {
+        Some((_, name)) => name,
+        None if lenient => Path::new(path).file_stem()?.to_string_lossy().into_owned(),
+        None => return None,
+    };
+    Some(AppInfo {
+        id,
+        name,
+        display_name: None,
+        description: description.map(|(_, d)| d),
+        generic_name: generic_name.map(|(_, g)| g),
+        path: path.to_string(),
+        icon,
+        emoji: None,
+        terminal, // Set the terminal flag
+        dbus_activatable,
+        exec,
+        only_show_in,
+        not_show_in,
+        keywords: keywords.map(|(_, k)| k).unwrap_or_default(),
+        vendor_keywords: vendor_keywords
+            .into_iter()
+            .filter_map(|(prefix, keywords)| Some((prefix, keywords?.1)))
+            .collect(),
+        categories,
+        mime_types,
+        implements,
+        no_display,
+        try_exec,
+        startup_wm_class,
+        startup_notify,
+        validation_error: None,
+        appimage: false,
+        work_dir,
+        recently_added: false,
+        version: vendor_version.or(spec_version),
+        hidden: false,
+        search: Default::default(),
+    }
+    .index())
+}
+
+/// A value with the rank of its locale, see `set_localized`.
+type Localized<T> = Option<(usize, T)>;
+
+/// Stores `value` as the keywords of its vendor if `key` is an
+/// `X-<vendor>-Keywords` key, ignoring any other key.
+fn set_vendor_keywords(
+    slots: &mut Vec<(String, Localized<Vec<String>>)>,
+    key: &str,
+    rank: usize,
+    value: &str,
+) {
+    let Some(prefix) = key.strip_suffix("-Keywords").filter(|prefix| prefix.starts_with("X-")) else {
+        return;
+    };
+    let index = match slots.iter().position(|(p, _)| p == prefix) {
+        Some(index) => index,
+        None => {
+            slots.push((prefix.to_string(), None));
+            slots.len() - 1
+        }
+    };
+    set_localized(&mut slots[index].1, rank, split_desktop_list(value));
+}
+
+/// Adds the vendor keywords of the honored `prefixes` (e.g. `X-GNOME`) to
+/// the app's keywords, searched like its own.
+fn merge_vendor_keywords(mut app: AppInfo, prefixes: &[String]) -> AppInfo {
+    let mut merged = false;
+    for (prefix, keywords) in &app.vendor_keywords {
+        if !prefixes.contains(prefix) {
+            continue;
+        }
+        for keyword in keywords {
+            if !app.keywords.contains(keyword) {
+                app.keywords.push(keyword.clone());
+                merged = true;
+            }
+        }
+    }
+    // the search keys were computed without them
+    if merged { app.index() } else { app }
+}
+
+/// `app` with the pinyin reading of its name as one more keyword.
+#[cfg(feature = "pinyin")]
+fn with_pinyin(mut app: AppInfo) -> AppInfo {
+    match pinyin::reading(&app.name) {
+        Some(reading) if !app.keywords.contains(&reading) => {
+            app.keywords.push(reading);
+            app.index()
+        }
+        _ => app,
+    }
+}
+
+/// Without the `pinyin` feature there are no readings to add.
+#[cfg(not(feature = "pinyin"))]
+fn with_pinyin(app: AppInfo) -> AppInfo {
+    app
+}
+
+/// Keeps `value` unless the slot already holds a better localized one.
+fn set_localized<T>(slot: &mut Option<(usize, T)>, rank: usize, value: T) {
+    if slot.as_ref().is_none_or(|(current, _)| rank <= *current) {
+        *slot = Some((rank, value));
+    }
+}
+
+/// Returns the keys to look up localized values with for a POSIX locale,
+/// most specific first as the spec orders them: `ca_ES.UTF-8@valencia` gives
+/// `ca_ES@valencia`, `ca_ES`, `ca@valencia` and `ca`.
+fn locale_candidates(locale: &str) -> Vec<String> {
+    let (rest, modifier) = match locale.split_once('@') {
+        Some((rest, modifier)) => (rest, Some(modifier)),
+        None => (locale, None),
+    };
+    // the encoding is never part of the key
+    let rest = rest.split('.').next().unwrap_or("");
+    let (lang, country) = match rest.split_once('_') {
+        Some((lang, country)) => (lang, Some(country)),
+        None => (rest, None),
+    };
+    if lang.is_empty() || lang == "C" || lang == "POSIX" {
+        return Vec::new();
+    }
+
+    let mut candidates = Vec::new();
+    if let Some(country) = country {
+        if let Some(modifier) = modifier {
+            candidates.push(format!("{}_{}@{}", lang, country, modifier));
+        }
+        candidates.push(format!("{}_{}", lang, country));
+    }
+    if let Some(modifier) = modifier {
+        candidates.push(format!("{}@{}", lang, modifier));
+    }
+    candidates.push(lang.to_string());
+    candidates
+}
+
+/// Locale candidates for the messages locale of the current process.
+fn current_locales() -> Vec<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .map(|locale| locale_candidates(&locale))
+        .unwrap_or_default()
+}
+
+/// Splits a semicolon separated list value, dropping empty segments such as
+/// the one after the usual trailing `;`.
+///
+/// `\;` is a semicolon inside an element. The other string escapes are undone
+/// in the same pass, so `\\;` is a backslash ending an element.
+fn split_desktop_list(value: &str) -> Vec<String> {
+    let mut elements = Vec::new();
+    let mut current = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => elements.push(std::mem::take(&mut current)),
+            '\\' => match chars.next() {
+                Some(';') => current.push(';'),
+                Some('s') => current.push(' '),
+                Some('n') => current.push('\n'),
+                Some('t') => current.push('\t'),
+                Some('r') => current.push('\r'),
+                Some('\\') => current.push('\\'),
+                Some(other) => {
+                    current.push('\\');
+                    current.push(other);
+                }
+                None => current.push('\\'),
+            },
+            _ => current.push(c),
+        }
+    }
+    elements.push(current);
+    elements
+        .into_iter()
+        .map(|element| element.trim().to_string())
+        .filter(|element| !element.is_empty())
+        .collect()
+}
+
+/// Reads the desktop environments from `XDG_CURRENT_DESKTOP`, which can be a
+/// colon separated list like `ubuntu:GNOME`.
+fn current_desktops() -> Vec<String> {
+    parse_current_desktops(std::env::var("XDG_CURRENT_DESKTOP").ok().as_deref())
+}
+
+fn parse_current_desktops(value: Option<&str>) -> Vec<String> {
+    value
+        .unwrap_or("")
+        .split(':')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Applies `OnlyShowIn`/`NotShowIn`. With no known desktop everything is shown.
+///
+/// Desktop names compare ignoring case, like the desktops themselves do:
+/// files in the wild say `gnome` as often as `GNOME`. An `OnlyShowIn` with
+/// nothing but empty segments restricts nothing.
+fn is_shown_in(app: &AppInfo, desktops: &[String]) -> bool {
+    if desktops.is_empty() {
+        return true;
+    }
+    let listed = |list: &[String]| desktops.iter().any(|d| list.iter().any(|l| l.eq_ignore_ascii_case(d)));
+    if !app.only_show_in.is_empty() && !listed(&app.only_show_in) {
+        return false;
+    }
+    !listed(&app.not_show_in)
+}
+
+/// Checks that a `TryExec` value points to an executable, either as an
+/// absolute path or as a bare name found in one of the `path_var` entries.
+fn find_executable(name: &str, path_var: Option<&std::ffi::OsStr>) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    let is_executable = |path: &Path| {
+        fs::metadata(path)
+            .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+    };
+
+    let name = Path::new(name);
+    if name.is_absolute() {
+        return is_executable(name);
+    }
+    path_var.is_some_and(|path_var| {
+        std::env::split_paths(path_var).any(|dir| is_executable(&dir.join(name)))
+    })
+}
+
+/// The directories to scan for desktop files, in precedence order.
+fn search_dirs(config: &Config) -> Vec<PathBuf> {
+    let xdg_dirs = xdg::BaseDirectories::new();
+    search_dirs_in(
+        home::home_dir().as_deref(),
+        xdg_dirs.get_data_home().as_deref(),
+        xdg_dirs.get_data_dirs(),
+        config,
+    )
+}
+
+/// System-wide Flatpak exports, scanned after the XDG data dirs.
+const FLATPAK_SYSTEM_EXPORTS: &str = "/var/lib/flatpak/exports/share";
+
+/// The `applications` directories to scan, most important first.
+///
+/// The order is the precedence of the Desktop Entry spec, an ID found in an
+/// earlier directory shadowing the same ID in later ones:
+/// 1. the user's data home (`$XDG_DATA_HOME`, then `~/.local/share`),
+/// 2. the user's Flatpak exports,
+/// 3. `$XDG_DATA_DIRS`, in the order given,
+/// 4. the system Flatpak exports,
+/// 5. `extra_search_paths`.
+///
+/// `override_search_paths` replaces the first four. A directory listed twice
+/// keeps its first place. Without a home directory, e.g. in a container with
+/// `HOME` unset, the directories under it are skipped:
+/// `~/.local/share/applications` and the user's Flatpak exports there. Those
+/// under an `$XDG_DATA_HOME` set elsewhere are still scanned.
+fn search_dirs_in(
+    home_dir: Option<&Path>,
+    data_home: Option<&Path>,
+    data_dirs: Vec<PathBuf>,
+    config: &Config,
+) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    if let Some(override_paths) = &config.override_search_paths {
+        // the defaults are replaced, so the home directory isn't needed
+        paths.extend(override_paths.iter().cloned());
+    } else {
+        if let Some(data_home) = data_home {
+            paths.push(data_home.join("applications"));
+        }
+        if let Some(home_dir) = home_dir {
+            let local_apps = home_dir.join(".local/share/applications");
+            paths.push(local_apps);
+            // per-user Flatpak installs export their desktop files here, which
+            // isn't part of the default XDG data dirs
+            paths.push(home_dir.join(".local/share/flatpak/exports/share"));
+        } else {
+            // every load gets here, once is enough to tell
+            static WARNED: std::sync::Once = std::sync::Once::new();
+            WARNED.call_once(|| log_warn!("No home directory, only scanning the system directories"));
+        }
+        if let Some(data_home) = data_home {
+            paths.push(data_home.join("flatpak/exports/share"));
+        }
+        paths.extend(data_dirs);
+        paths.push(PathBuf::from(FLATPAK_SYSTEM_EXPORTS));
+    }
+    paths.extend(config.extra_search_paths.iter().cloned());
+
+    let mut seen = HashSet::new();
+    paths
+        .into_iter()
+        .map(|path| {
+            if path.ends_with("applications") {
+                path
+            } else {
+                path.join("applications")
+            }
+        })
+        // flatpak's profile script often adds its exports to XDG_DATA_DIRS too
+        .filter(|path| seen.insert(path.clone()))
+        .collect()
+}
+
+/// How desktop files are read, the cache scans again when these change.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+struct ScanOptions {
+    /// Locales to pick localized keys for, most specific first.
+    locales: Vec<String>,
+    /// Validator run on every file, `None` to skip validation.
+    validator: Option<String>,
+    /// Vendor keyword keys merged into the keywords, see `merge_vendor_keywords`.
+    keyword_prefixes: Vec<String>,
+    /// Also read the subdirectories, see `desktop_files`.
+    recursive: bool,
+    /// Read files without a group header, see `parse_desktop_file_with`.
+    lenient: bool,
+    /// Search names by their pinyin, see `with_pinyin`.
+    pinyin: bool,
+}
+
+/// A directory and its mtime from before it was read.
+pub(crate) type DirMtime = (PathBuf, std::time::SystemTime);
+
+/// What `scan_dir` found in an `applications` directory.
+pub(crate) struct Scan {
+    pub(crate) apps: Vec<AppInfo>,
+    /// How many desktop files were read.
+    pub(crate) files_read: usize,
+    /// The subdirectories read.
+    pub(crate) subdirs: Vec<DirMtime>,
+}
+
+const DESKTOP_FILE_VALIDATOR: &str = "desktop-file-validate";
+
+/// Runs `validator` on `path`, returning its complaints if the file is
+/// invalid. A validator that isn't installed accepts everything.
+fn validate_desktop_file(validator: &str, path: &Path) -> Option<String> {
+    match Command::new(validator).arg(path).output() {
+        Ok(output) if !output.status.success() => {
+            // desktop-file-validate reports on stdout, other tools may not
+            let report = if output.stdout.is_empty() { &output.stderr } else { &output.stdout };
+            Some(String::from_utf8_lossy(report).trim().to_string())
+        }
+        Ok(_) => None,
+        Err(e) => {
+            log_debug!("Skipping validation of {}, {} failed: {}", path.display(), validator, e);
+            None
+        }
+    }
+}
+
+/// Reads and parses every desktop file inside `apps_dir`, only those directly
+/// inside it unless the scan is recursive.
+///
+/// Files are read and parsed in parallel, the result keeps the `read_dir`
+/// order so deduplication afterwards stays deterministic.
+fn scan_dir(apps_dir: &Path, options: &ScanOptions) -> Result<Scan> {
+    let (paths, subdirs) = desktop_files(apps_dir, options.recursive)?;
+    let apps = paths
+        .par_iter()
+        .filter_map(|path| {
+            let content = match fs::read_to_string(path) {
+                Ok(content) => content,
+                Err(e) => {
+                    log_warn!("Failed to read desktop file {}: {}", path.display(), e);
+                    return None;
+                }
+            };
+            let path_str = path.to_string_lossy();
+            let app = parse_desktop_file_with(&content, &path_str, &options.locales, options.lenient)?;
+            let mut app = merge_vendor_keywords(app, &options.keyword_prefixes);
+            if options.pinyin {
+                app = with_pinyin(app);
+            }
+            let id = desktop_id(apps_dir, path);
+            // searched too, the keys were computed with the file name
+            if id != app.id {
+                app.id = id;
+                app = app.index();
+            }
+            if let Some(validator) = &options.validator
+                && !app.hidden
+            {
+                app.validation_error = validate_desktop_file(validator, path);
+            }
+            Some(app)
+        })
+        .collect();
+    Ok(Scan { apps, files_read: paths.len(), subdirs })
+}
+
+/// The desktop file ID: the path relative to the `applications` directory
+/// with `/` replaced by `-`, so `kde4/foo.desktop` is `kde4-foo.desktop`.
+fn desktop_id(apps_dir: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(apps_dir).unwrap_or(path);
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// How deep a recursive scan goes below the `applications` directory.
+const MAX_SCAN_DEPTH: usize = 4;
+
+/// Lists the desktop files inside `apps_dir`, and with `recursive` those of
+/// its subdirectories down to `MAX_SCAN_DEPTH`, along with the subdirectories
+/// read. Symlinked directories are followed, each directory is read once
+/// so a link to a parent can't loop.
+fn desktop_files(
+    apps_dir: &Path,
+    recursive: bool,
+) -> Result<(Vec<PathBuf>, Vec<DirMtime>)> {
+    use std::os::unix::fs::MetadataExt;
+    let mut paths = read_desktop_files(apps_dir)?;
+    let mut subdirs = Vec::new();
+    if !recursive {
+        return Ok((paths, subdirs));
+    }
+
+    let meta = fs::metadata(apps_dir)?;
+    let mut seen = HashSet::from([(meta.dev(), meta.ino())]);
+    let mut pending = vec![(apps_dir.to_path_buf(), 0)];
+    while let Some((dir, depth)) = pending.pop() {
+        if depth == MAX_SCAN_DEPTH {
+            continue;
+        }
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut children: Vec<PathBuf> = entries.filter_map(|entry| Some(entry.ok()?.path())).collect();
+        // the same order on every scan, for deduplication
+        children.sort();
+        for child in children.into_iter().rev() {
+            // following symlinks
+            let Ok(meta) = fs::metadata(&child) else {
+                continue;
+            };
+            if !meta.is_dir() || !seen.insert((meta.dev(), meta.ino())) {
+                continue;
+            }
+            let Ok(mtime) = meta.modified() else {
+                continue;
+            };
+            match read_desktop_files(&child) {
+                Ok(found) => paths.extend(found),
+                Err(e) => log_warn!("Failed to read {}: {}", child.display(), e),
+            }
+            subdirs.push((child.clone(), mtime));
+            pending.push((child, depth + 1));
+        }
+    }
+    Ok((paths, subdirs))
+}
+
+/// Lists the desktop files directly inside `dir`.
+fn read_desktop_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        // one bad entry doesn't lose the others
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let path = entry.path();
+
+        if path.extension().and_then(|s| s.to_str()) != Some("desktop") {
+            continue;
+        }
+
+        if !path.exists() || !path.is_file() {
+            continue;
+        }
+
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+fn load_applications(config: &Config) -> Result<Vec<AppInfo>> {
+    #[cfg(test)]
+    APP_LOADS.with(|loads| loads.set(loads.get() + 1));
+    let mut apps = {
+        let mut cache = cache::global().lock().unwrap_or_else(|e| e.into_inner());
+        let apps = load_applications_with(&mut cache, &search_dirs(config), config)?;
+        cache.persist();
+        apps
+    };
+    if config.recently_added_days > 0 {
+        let ids: Vec<&str> = apps.iter().map(|app| app.id.as_str()).collect();
+        let history = frecency::record_seen(&ids);
+        mark_recently_added(&mut apps, &history, frecency::now(), config.recently_added_days);
+    }
+    Ok(apps)
+}
+
+/// Flags the apps `history` first saw less than `days` days before `now`.
+fn mark_recently_added(apps: &mut [AppInfo], history: &frecency::Frecency, now: u64, days: u64) {
+    let since = now.saturating_sub(days.saturating_mul(24 * 3600));
+    for app in apps {
+        app.recently_added = history.first_seen(&app.id).is_some_and(|seen| seen > since);
+    }
+}
+
+/// Whether the config keeps `app` out of the list: its desktop ID is in
+/// `hidden_ids`, its name in `hidden_names`, its file is under one of the
+/// `blocklist_path_prefixes`, it's in one of the `excluded_categories`, or
+/// `included_categories` is set and it's in none of them.
+fn hidden_by_config(app: &AppInfo, config: &Config) -> bool {
+    let in_any = |categories: &[String]| app.categories.iter().any(|c| categories.contains(c));
+    config.hidden_ids.contains(&app.id)
+        || config.hidden_names.contains(&app.name)
+        || is_blocked(Path::new(&app.path), config)
+        || in_any(&config.excluded_categories)
+        || config.included_categories.as_deref().is_some_and(|included| !in_any(included))
+}
+
+/// Whether `app` is in one of the `demoted_categories`, ranking after the
+/// other apps.
+fn is_demoted(app: &AppInfo, config: &Config) -> bool {
+    app.categories.iter().any(|category| config.demoted_categories.contains(category))
+}
+
+/// Whether `path` is under one of the `blocklist_path_prefixes`.
+fn is_blocked(path: &Path, config: &Config) -> bool {
+    config.blocklist_path_prefixes.iter().any(|prefix| path.starts_with(prefix))
+}
+
+/// Tells at debug level that `winner` shadows `shadowed`, with their versions
+/// if they declare one, for when an unexpected copy launches.
+fn log_shadowed(winner: &AppInfo, shadowed: &AppInfo) {
+    let with_version = |app: &AppInfo| match &app.version {
+        Some(version) => format!("{} ({})", app.path, version),
+        None => app.path.clone(),
+    };
+    log_debug!("{}: {} shadows {}", winner.id, with_version(winner), with_version(shadowed));
+}
+
+fn load_applications_with(
+    cache: &mut cache::Cache,
+    dirs: &[PathBuf],
+    config: &Config,
+) -> Result<Vec<AppInfo>> {
+    let desktops = current_desktops();
+    let options = ScanOptions {
+        locales: current_locales(),
+        validator: config
+            .validate_desktop_files
+            .then(|| DESKTOP_FILE_VALIDATOR.to_string()),
+        keyword_prefixes: config.keyword_prefixes.clone(),
+        recursive: config.recursive_scan,
+        lenient: config.lenient_parsing,
+        pinyin: config.pinyin_search,
+    };
+    let path_var = std::env::var_os("PATH");
+
+    // scanned first, so the apps of every directory can be borrowed at once
+    let scans = cache.scans;
+    let mut scanned = Vec::new();
+    for apps_dir in dirs {
+        // nothing in a blocked directory would be listed
+        if !apps_dir.exists() || is_blocked(apps_dir, config) {
+            continue;
+        }
+        // an unreadable directory loses its own apps, not everyone's
+        match cache.dir_apps(apps_dir, &options) {
+            Ok(_) => scanned.push(apps_dir),
+            Err(e) => log_warn!("Failed to read {}: {}", apps_dir.display(), e),
+        }
+    }
+    let appimages: Vec<AppInfo> = config.appimage_dirs.iter().flat_map(|dir| appimage::scan(dir)).collect();
+    // what shadows what only changes with a scan, told once and not on every query
+    let rescanned = cache.scans != scans;
+
+    // the first directory providing an ID wins and later ones are shadowed,
+    // even when the winner isn't listed: that's how a user's copy that is
+    // `Hidden`, `NoDisplay` or for another desktop hides the system one
+    let mut seen_ids: HashMap<&str, &AppInfo> = HashMap::new();
+    let mut apps: Vec<AppInfo> = Vec::new();
+    let candidates = scanned.iter().flat_map(|dir| cache.cached_apps(dir)).chain(&appimages);
+    for app_info in candidates {
+        if let Some(winner) = seen_ids.get(app_info.id.as_str()) {
+            if rescanned {
+                log_shadowed(winner, app_info);
+            }
+            continue;
+        }
+        seen_ids.insert(&app_info.id, app_info);
+        if !app_info.hidden
+            && (config.include_nodisplay || !app_info.no_display)
+            && !hidden_by_config(app_info, config)
+            && is_shown_in(app_info, &desktops)
+            && app_info
+                .try_exec
+                .as_deref()
+                .is_none_or(|try_exec| find_executable(try_exec, path_var.as_deref()))
+        {
+            apps.push(app_info.clone());
+        }
+    }
+
+    let rules = rename::Rules::new(&config.name_replacements);
+    for app in &mut apps {
+        app.display_name = rules.apply(&app.name);
+    }
+    // an emoji stands in for a missing icon
+    for app in apps.iter_mut().filter(|app| app.icon.is_none()) {
+        app.emoji = emoji::for_categories(&app.categories, &config.category_emoji);
+    }
+    Ok(apps)
+}
+
+/// A command running `argv` (never empty), in `work_dir` if that exists.
+///
+/// The environment variables in `work_dir` are expanded, and the variables
+/// an `env VAR=value` prefix of `argv` sets are set on the command itself.
+fn app_command(argv: &[String], work_dir: Option<&str>) -> Command {
+    let (vars, argv) = exec::split_env(argv.to_vec());
+    let mut command = Command::new(&argv[0]);
+    command.args(&argv[1..]).envs(vars);
+    let work_dir = work_dir.map(|dir| exec::expand_env(dir, &|name| std::env::var(name).ok()));
+    match work_dir {
+        Some(dir) if Path::new(&dir).is_dir() => {
+            command.current_dir(dir);
+        }
+        Some(dir) => log_warn!("Ignoring missing working directory {}", dir),
+        None => {}
+    }
+    command
+}
+
+/// Prefix running a command in its own transient systemd scope.
+const SYSTEMD_SCOPE: &[&str] = &["systemd-run", "--user", "--scope", "--quiet", "--"];
+
+/// `argv` run in a transient scope of the user's systemd instance when
+/// `use_systemd_scope` is set, so the app gets its own cgroup and isn't
+/// stopped along with the host. As-is when `systemd-run` isn't in `path_var`.
+fn scoped_argv(argv: Vec<String>, config: &Config, path_var: Option<&std::ffi::OsStr>) -> Vec<String> {
+    if !config.use_systemd_scope || !find_executable(SYSTEMD_SCOPE[0], path_var) {
+        return argv;
+    }
+    SYSTEMD_SCOPE.iter().map(|arg| arg.to_string()).chain(argv).collect()
+}
+
+/// Startup notification token for the next launch, see `set_activation_token`.
+static ACTIVATION_TOKEN: std::sync::Mutex<Option<String>> = std::sync::Mutex::new(None);
+
+/// Gives the plugin the startup notification token of the next launch, an
+/// `xdg_activation_v1` token on Wayland or a startup ID on X11, so the
+/// compositor can show startup feedback and focus the app once it's up.
+///
+/// The token goes to the next app launched that has `StartupNotify=true`,
+/// then is forgotten. Null clears it.
+#[unsafe(no_mangle)]
+pub extern "C" fn set_activation_token(token: *const c_char) {
+    guard("set_activation_token", (), || {
+        let token = c_str_arg(token).map(|token| token.to_string_lossy().into_owned());
+        *ACTIVATION_TOKEN.lock().unwrap_or_else(|e| e.into_inner()) = token;
+    })
+}
+
+/// The token for launching `app`, consumed if the app can use it.
+fn take_activation_token(app: &AppInfo) -> Option<String> {
+    if !app.startup_notify {
+        return None;
+    }
+    ACTIVATION_TOKEN.lock().unwrap_or_else(|e| e.into_inner()).take()
+}
+
+/// Passes `token` to a spawned app in the variables both protocols use. One
+/// inherited from the host's own launch is never passed on, it was used up.
+fn set_startup_env(command: &mut Command, token: Option<&str>) {
+    for var in ["XDG_ACTIVATION_TOKEN", "DESKTOP_STARTUP_ID"] {
+        match token {
+            Some(token) => command.env(var, token),
+            None => command.env_remove(var),
+        };
+    }
+}
+
+/// The command of a `Spawn` or `Run` attempt with the startup `token`, which
+/// a launcher like `gtk-launch` passes on to the app just the same.
+fn attempt_command(argv: &[String], work_dir: Option<&str>, token: Option<&str>) -> Command {
+    let mut command = app_command(argv, work_dir);
+    set_startup_env(&mut command, token);
+    command
+}
+
+/// The command line of `app` opening `files`, empty without an Exec.
+fn exec_argv(app: &AppInfo, files: &[String]) -> Vec<String> {
+    let fields = exec::Fields {
+        icon: app.icon.as_deref(),
+        name: Some(&app.name),
+        location: Some(&app.path),
+    };
+    app.exec
+        .as_deref()
+        .map(|exec| exec::expand_exec_env(exec, files, &fields, &|name| std::env::var(name).ok()))
+        .unwrap_or_default()
+}
+
+/// `argv` behind the `launch_wrappers` matching `app`, the first outermost.
+fn wrapped_argv(app: &AppInfo, argv: Vec<String>, config: &Config) -> Vec<String> {
+    let wrappers = config.launch_wrappers.iter().filter(|wrapper| {
+        app.id == wrapper.target || app.categories.contains(&wrapper.target)
+    });
+    wrappers.rev().fold(argv, |argv, wrapper| {
+        exec::expand_exec(&wrapper.command, &[]).into_iter().chain(argv).collect()
+    })
+}
+
+/// One way of launching a selection, `execute_gio_launch` tries them in
+/// order until one works.
+#[derive(Debug, PartialEq)]
+enum LaunchAttempt {
+    /// Run the AppImage itself, made executable first.
+    AppImage,
+    /// Activate the app over D-Bus.
+    DBus,
+    /// Spawn the command in the app's working directory, without waiting.
+    Spawn(Vec<String>),
+    /// Run a launcher and wait for it to report success.
+    Run(Vec<String>),
+}
+
+impl LaunchAttempt {
+    /// The command this attempt runs, given what it launches.
+    fn argv(&self, path: &str, app: Option<&AppInfo>, files: &[String]) -> Vec<String> {
+        match self {
+            LaunchAttempt::AppImage => {
+                std::iter::once(path.to_string()).chain(files.iter().cloned()).collect()
+            }
+            LaunchAttempt::DBus => {
+                let id = app.map_or("", |app| &app.id);
+                dbus::command(id, files, None).unwrap_or_default()
+            }
+            LaunchAttempt::Spawn(argv) | LaunchAttempt::Run(argv) => argv.clone(),
+        }
+    }
+}
+
+/// The ways of launching `app`, or `path` when it isn't a listed app, best
+/// first. `path_var` is where `systemd-run` is looked for.
+fn launch_attempts(
+    path: &str,
+    app: Option<&AppInfo>,
+    files: &[String],
+    config: &Config,
+    path_var: Option<&std::ffi::OsStr>,
+) -> Vec<LaunchAttempt> {
+    let scoped = |argv: Vec<String>| scoped_argv(argv, config, path_var);
+    let mut attempts = Vec::new();
+    if let Some(app) = app {
+        // AppImages are their own launcher
+        if app.appimage {
+            return vec![LaunchAttempt::AppImage];
+        }
+        // Expand the whole Exec line, field codes replaced by the files, so
+        // arguments and `env VAR=value` prefixes survive
+        let argv = exec_argv(app, files);
+        let unwrapped = argv.len();
+        let argv = if argv.is_empty() { argv } else { wrapped_argv(app, argv, config) };
+        let wrapped = argv.len() > unwrapped;
+
+        // D-Bus activation keeps single-instance apps to a single instance
+        if app.dbus_activatable && !wrapped && dbus::bus_name(&app.id).is_some() {
+            attempts.push(LaunchAttempt::DBus);
+        }
+        if app.terminal && !argv.is_empty() {
+            // Try the configured terminal, then $TERMINAL, then the known ones
+            let env_terminal = std::env::var("TERMINAL").ok();
+            let terminals =
+                terminal::candidates(config.terminal_command.as_deref(), env_terminal.as_deref());
+            for terminal_cmd in &terminals {
+                attempts.push(LaunchAttempt::Spawn(scoped(terminal::build_argv(terminal_cmd, &argv))));
+            }
+        }
+        // Files are substituted by our own Exec parsing when we can, and
+        // wrappers only apply to our own
+        if (!files.is_empty() || wrapped) && !argv.is_empty() {
+            attempts.push(LaunchAttempt::Spawn(scoped(argv)));
+        }
+    }
+
+    // Then the system launchers, gtk-launch first
+    for launcher in [&["gtk-launch"][..], &["gio", "launch"]] {
+        let argv = launcher.iter().copied().chain([path]).chain(files.iter().map(String::as_str));
+        attempts.push(LaunchAttempt::Run(scoped(argv.map(str::to_string).collect())));
+    }
+    attempts
+}
+
+#[cfg(not(test))]
+fn execute_gio_launch(path: &str, app: Option<&AppInfo>, files: &[String], config: &Config) -> bool {
+    let path_var = std::env::var_os("PATH");
+    let attempts = launch_attempts(path, app, files, config, path_var.as_deref());
+    if attempts.first() == Some(&LaunchAttempt::AppImage) {
+        return appimage::launch(Path::new(path), files);
+    }
+    let token = app.and_then(take_activation_token);
+
+    for attempt in attempts {
+        let launched = match attempt {
+            LaunchAttempt::AppImage => appimage::launch(Path::new(path), files),
+            LaunchAttempt::DBus => {
+                let id = app.map_or("", |app| &app.id);
+                let activated = dbus::activate(id, files, token.as_deref());
+                if !activated {
+                    log_info!("Launching {} without D-Bus", id);
+                }
+                activated
+            }
+            LaunchAttempt::Spawn(argv) => {
+                let work_dir = app.and_then(|app| app.work_dir.as_deref());
+                attempt_command(&argv, work_dir, token.as_deref()).spawn().is_ok()
+            }
+            LaunchAttempt::Run(argv) => attempt_command(&argv, None, token.as_deref())
+                .status()
+                .is_ok_and(|status| status.success()),
+        };
+        if launched {
+            return true;
+        }
+    }
+    false
+}
+
+/// The command launching `selection` would run, as a JSON array of strings,
+/// without running anything: what `handle_selection` tries first, skipping
+/// the commands whose program isn't installed. D-Bus activations are shown as
+/// the equivalent `gdbus` call.
+///
+/// Returns null if the selection is null or names an app that failed
+/// validation. The string is owned by the plugin and valid until the next
+/// call.
+#[unsafe(no_mangle)]
+pub extern "C" fn resolve_launch_command(selection: *const c_char) -> *const c_char {
+    guard("resolve_launch_command", std::ptr::null(), || {
+        static COMMAND: std::sync::Mutex<Option<CString>> = std::sync::Mutex::new(None);
+
+        let Some(selection) = c_str_arg(selection) else {
+            log_error!("Selection is null");
+            return std::ptr::null();
+        };
+        let selection = selection.to_string_lossy();
+        let (path, files) = split_selection(&selection);
+        let config = config::current();
+        let apps = load_applications(&config).unwrap_or_default();
+        let app = apps.iter().find(|app| app.path == path);
+        if app.is_some_and(|app| app.validation_error.is_some()) {
+            return std::ptr::null();
+        }
+        let path_var = std::env::var_os("PATH");
+        let argv = resolve_argv(path, app, &files, &config, path_var.as_deref());
+
+        let json = serde_json::Value::from(argv).to_string();
+        let mut command = COMMAND.lock().unwrap_or_else(|e| e.into_inner());
+        command.insert(CString::new(json).unwrap_or_default()).as_ptr()
+    })
+}
+
+/// The command of the first launch attempt whose program is in `path_var`,
+/// or of the first attempt if none is.
+fn resolve_argv(
+    path: &str,
+    app: Option<&AppInfo>,
+    files: &[String],
+    config: &Config,
+    path_var: Option<&std::ffi::OsStr>,
+) -> Vec<String> {
+    let commands: Vec<Vec<String>> = launch_attempts(path, app, files, config, path_var)
+        .iter()
+        .map(|attempt| attempt.argv(path, app, files))
+        .filter(|argv| !argv.is_empty())
+        .collect();
+    let installed = commands.iter().position(|argv| find_executable(&argv[0], path_var));
+    commands.into_iter().nth(installed.unwrap_or(0)).unwrap_or_default()
+}
+
+#[cfg(test)]
+thread_local! {
+    // calls to `load_applications`, per test thread
+    static APP_LOADS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+#[cfg(test)]
+thread_local! {
+    // what the stand-in launcher below reports, per test thread
+    static LAUNCH_SUCCEEDS: std::cell::Cell<bool> = const { std::cell::Cell::new(true) };
+}
+
+#[cfg(test)]
+fn execute_gio_launch(_path: &str, _app: Option<&AppInfo>, _files: &[String], _config: &Config) -> bool {
+    LAUNCH_SUCCEEDS.with(|succeeds| succeeds.get())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_applications() {
+        let apps = load_applications(&Config::default()).unwrap();
+        assert!(!apps.is_empty());
+    }
+
+    #[test]
+    fn load_applications_uses_config() {
+        let dir = test_util::TestDir::new("config");
+        dir.write("applications/visible.desktop", "[Desktop Entry]\nExec=true\nName=Visible Fixture\n");
+        dir.write(
+            "applications/nodisplay.desktop",
+            "[Desktop Entry]\nExec=true\nName=NoDisplay Fixture\nNoDisplay=true\n",
+        );
+        dir.write(
+            "applications/hidden.desktop",
+            "[Desktop Entry]\nExec=true\nName=Hidden Fixture\nHidden=true\n",
+        );
+        let names = |config: &Config| -> Vec<String> {
+            load_applications(config).unwrap().into_iter().map(|app| app.name).collect()
+        };
+
+        let mut config = Config {
+            extra_search_paths: vec![dir.path().to_path_buf()],
+            ..Config::default()
+        };
+        let found = names(&config);
+        assert!(found.contains(&"Visible Fixture".to_string()));
+        assert!(!found.contains(&"NoDisplay Fixture".to_string()));
+
+        assert!(!found.contains(&"Hidden Fixture".to_string()));
+
+        config.include_nodisplay = true;
+        let found = names(&config);
+        assert!(found.contains(&"NoDisplay Fixture".to_string()));
+        // deleted, there's nothing to include
+        assert!(!found.contains(&"Hidden Fixture".to_string()));
+    }
+
+    #[test]
+    fn parallel_scan_matches_sequential_parse() {
+        let dir = test_util::TestDir::new("scan");
+        for i in 0..64 {
+            dir.write(&format!("app{}.desktop", i), &format!("[Desktop Entry]\nExec=true\nName=App {}\n", i));
+        }
+        dir.write("broken.desktop", "no group header\n");
+        dir.write("notes.txt", "[Desktop Entry]\nExec=true\nName=Not a desktop file\n");
+
+        let sequential: Vec<String> = read_desktop_files(dir.path())
+            .unwrap()
+            .iter()
+            .filter_map(|path| {
+                let content = fs::read_to_string(path).unwrap();
+                parse_desktop_file(&content, &path.to_string_lossy(), &[])
+            })
+            .map(|app| app.name)
+            .collect();
+        let scan = scan_dir(dir.path(), &ScanOptions::default()).unwrap();
+        let parallel: Vec<String> = scan.apps.into_iter().map(|app| app.name).collect();
+
+        assert_eq!(scan.files_read, 65);
+        assert_eq!(parallel.len(), 64);
+        assert_eq!(parallel, sequential);
+    }
+
+    #[test]
+    fn recursive_scan_of_nested_directories() {
+        let dir = test_util::TestDir::new("recursive");
+        dir.write("applications/top.desktop", "[Desktop Entry]\nExec=true\nName=Top\n");
+        dir.write("applications/kde4/konqueror.desktop", "[Desktop Entry]\nExec=true\nName=Konqueror\n");
+        dir.write("applications/a/b/c/d/deep.desktop", "[Desktop Entry]\nExec=true\nName=Deep\n");
+        dir.write("applications/a/b/c/d/e/deeper.desktop", "[Desktop Entry]\nExec=true\nName=Too Deep\n");
+        let apps_dir = dir.path().join("applications");
+        // a link back to the top must not loop
+        std::os::unix::fs::symlink(&apps_dir, apps_dir.join("kde4/loop")).unwrap();
+        let recursive = ScanOptions { recursive: true, ..ScanOptions::default() };
+        let ids = |options: &ScanOptions| {
+            let mut ids: Vec<String> =
+                scan_dir(&apps_dir, options).unwrap().apps.into_iter().map(|app| app.id).collect();
+            ids.sort();
+            ids
+        };
+
+        assert_eq!(
+            ids(&recursive),
+            vec!["a-b-c-d-deep.desktop", "kde4-konqueror.desktop", "top.desktop"]
+        );
+        assert_eq!(ids(&ScanOptions::default()), vec!["top.desktop"]);
+
+        // a file added to a subdirectory is picked up by the cache
+        let mut cache = cache::Cache::default();
+        assert_eq!(cache.dir_apps(&apps_dir, &recursive).unwrap().len(), 3);
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        dir.write("applications/kde4/kate.desktop", "[Desktop Entry]\nExec=true\nName=Kate\n");
+        assert_eq!(cache.dir_apps(&apps_dir, &recursive).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn validation_flags_malformed_files() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = test_util::TestDir::new("validate");
+        let validator = dir.write(
+            "validator",
+            "#!/bin/sh\ngrep -q Broken \"$1\" && { echo \"$1: error: broken\"; exit 1; }\nexit 0\n",
+        );
+        fs::set_permissions(&validator, fs::Permissions::from_mode(0o755)).unwrap();
+        dir.write("applications/good.desktop", "[Desktop Entry]\nExec=true\nName=Good\n");
+        dir.write("applications/broken.desktop", "[Desktop Entry]\nExec=true\nName=Broken\n");
+        let apps_dir = dir.path().join("applications");
+
+        let validated = |validator: Option<&str>| {
+            let options = ScanOptions {
+                validator: validator.map(str::to_string),
+                ..ScanOptions::default()
+            };
+            let mut apps = scan_dir(&apps_dir, &options).unwrap().apps;
+            apps.sort_by(|a, b| a.name.cmp(&b.name));
+            apps.into_iter().map(|app| (app.name, app.validation_error)).collect::<Vec<_>>()
+        };
+
+        let flagged = validated(Some(&validator.to_string_lossy()));
+        assert_eq!(flagged[0].0, "Broken");
+        assert!(flagged[0].1.as_deref().unwrap().ends_with("error: broken"));
+        assert_eq!(flagged[1], ("Good".to_string(), None));
+        // off, or with the validator missing, nothing is flagged
+        for validator in [None, Some("applist-missing-validator")] {
+            assert!(validated(validator).iter().all(|(_, error)| error.is_none()));
+        }
+    }
+
+    #[test]
+    fn hidden_ids_and_names_are_excluded() {
+        let user = test_util::TestDir::new("hidden-user");
+        let system = test_util::TestDir::new("hidden-system");
+        user.write("applications/applist-test-panel.desktop", "[Desktop Entry]\nExec=true\nName=Panel\n");
+        system.write("applications/applist-test-panel.desktop", "[Desktop Entry]\nExec=true\nName=Panel\n");
+        let other = "[Desktop Entry]\nExec=true\nName=Other Panel\n";
+        system.write("applications/applist-test-other.desktop", other);
+        system.write("applications/applist-test-kept.desktop", "[Desktop Entry]\nExec=true\nName=Kept\n");
+        let dirs = [user.path().join("applications"), system.path().join("applications")];
+        let ids = |config: &Config| -> Vec<String> {
+            let apps = load_applications_with(&mut cache::Cache::default(), &dirs, config).unwrap();
+            let mut ids: Vec<String> = apps.into_iter().map(|app| app.id).collect();
+            ids.sort();
+            ids
+        };
+
+        // hiding an ID hides its shadowed copies too
+        let config = Config {
+            hidden_ids: vec!["applist-test-panel.desktop".to_string()],
+            hidden_names: vec!["Other Panel".to_string()],
+            ..Config::default()
+        };
+        assert_eq!(ids(&config), vec!["applist-test-kept.desktop"]);
+        assert_eq!(ids(&Config::default()).len(), 3);
+    }
+
+    #[test]
+    fn blocklisted_prefixes_are_skipped() {
+        let dir = test_util::TestDir::new("blocklist");
+        let temp = dir.path().join("temp");
+        let app = "[Desktop Entry]\nExec=true\nName=App\n";
+        dir.write("temp/applications/applist-test-download.desktop", app);
+        dir.write("temp-like/applications/applist-test-near.desktop", app);
+        dir.write("data/applications/applist-test-kept.desktop", app);
+        dir.write("data/applications/temp/applist-test-nested.desktop", app);
+        let dirs = ["temp", "temp-like", "data"].map(|data| dir.path().join(data).join("applications"));
+        let ids = |config: &Config| -> Vec<String> {
+            let apps = load_applications_with(&mut cache::Cache::default(), &dirs, config).unwrap();
+            let mut ids: Vec<String> = apps.into_iter().map(|app| app.id).collect();
+            ids.sort();
+            ids
+        };
+
+        let config = Config {
+            blocklist_path_prefixes: vec![temp, dir.path().join("data/applications/temp")],
+            recursive_scan: true,
+            ..Config::default()
+        };
+        // whole path components only, `temp-like` isn't under `temp`
+        assert_eq!(ids(&config), vec!["applist-test-kept.desktop", "applist-test-near.desktop"]);
+        let unblocked = Config { blocklist_path_prefixes: Vec::new(), ..config };
+        assert_eq!(ids(&unblocked).len(), 4);
+        assert_eq!(Config::default().blocklist_path_prefixes, vec![std::env::temp_dir()]);
+    }
+
+    #[test]
+    fn excluded_and_included_categories() {
+        let dir = test_util::TestDir::new("categories");
+        let app = |categories: &str| {
+            format!("[Desktop Entry]\nExec=true\nName=App\nCategories={}\n", categories)
+        };
+        dir.write("applications/applist-test-settings.desktop", &app("GTK;Settings;"));
+        dir.write("applications/applist-test-monitor.desktop", &app("System;Monitor;"));
+        dir.write("applications/applist-test-game.desktop", &app("Game;ArcadeGame;"));
+        dir.write("applications/applist-test-none.desktop", "[Desktop Entry]\nExec=true\nName=App\n");
+        let dirs = [dir.path().join("applications")];
+        let ids = |config: &Config| -> Vec<String> {
+            let apps = load_applications_with(&mut cache::Cache::default(), &dirs, config).unwrap();
+            let mut ids: Vec<String> = apps.into_iter().map(|app| app.id).collect();
+            ids.sort();
+            ids
+        };
+        let categories = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+
+        let excluded = Config {
+            excluded_categories: categories(&["Settings", "System"]),
+            ..Config::default()
+        };
+        assert_eq!(ids(&excluded), vec!["applist-test-game.desktop", "applist-test-none.desktop"]);
+        // apps without Categories aren't in any whitelisted one
+        let included = Config {
+            included_categories: Some(categories(&["Game", "Monitor"])),
+            ..Config::default()
+        };
+        assert_eq!(ids(&included), vec!["applist-test-game.desktop", "applist-test-monitor.desktop"]);
+        // exclusion wins over the whitelist
+        let both = Config {
+            excluded_categories: categories(&["System"]),
+            ..included
+        };
+        assert_eq!(ids(&both), vec!["applist-test-game.desktop"]);
+        assert_eq!(ids(&Config::default()).len(), 4);
+    }
+
+    #[test]
+    fn iconless_apps_get_category_emoji() {
+        let dir = test_util::TestDir::new("emoji");
+        dir.write("applications/game.desktop", "[Desktop Entry]\nExec=true\nName=Game\nCategories=Game;\n");
+        dir.write(
+            "applications/icon.desktop",
+            "[Desktop Entry]\nExec=true\nName=Icon\nIcon=icon\nCategories=Game;\n",
+        );
+        let apps = load_applications_with(
+            &mut cache::Cache::default(),
+            &[dir.path().join("applications")],
+            &Config::default(),
+        )
+        .unwrap();
+        let emoji = |name: &str| apps.iter().find(|app| app.name == name).unwrap().emoji.clone();
+        assert_eq!(emoji("Game").as_deref(), Some("🎮"));
+        assert_eq!(emoji("Icon"), None);
+
+        let list = into_entry_list(apps.iter().map(|app| entry_text(app, &Config::default())).collect());
+        let entries = unsafe { std::slice::from_raw_parts(list.entries, list.length) };
+        let game = entries.iter().find(|entry| entry.icon.is_null()).unwrap();
+        assert_eq!(unsafe { CStr::from_ptr(game.emoji) }.to_str().unwrap(), "🎮");
+        free_entry_list(list);
+    }
+
+    #[test]
+    fn lists_configured_appimages() {
+        let dir = test_util::TestDir::new("appimage-dirs");
+        let path = dir.write("Applications/Fixture-2.0-x86_64.AppImage", "");
+        let config = Config {
+            appimage_dirs: vec![dir.path().join("Applications")],
+            ..Config::default()
+        };
+
+        let apps = load_applications_with(&mut cache::Cache::default(), &[], &config).unwrap();
+        assert_eq!(apps.len(), 1);
+        assert_eq!(apps[0].name, "Fixture");
+        assert_eq!(apps[0].path, path.to_string_lossy());
+        assert!(load_applications_with(&mut cache::Cache::default(), &[], &Config::default())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn user_files_shadow_system_ones() {
+        let home = test_util::TestDir::new("precedence-home");
+        let data_home = test_util::TestDir::new("precedence-data-home");
+        let first = test_util::TestDir::new("precedence-first");
+        let second = test_util::TestDir::new("precedence-second");
+        let app = |name: &str| format!("[Desktop Entry]\nExec=true\nName={}\n", name);
+        for (dir, name) in [(&first, "System"), (&second, "Second System")] {
+            dir.write("applications/applist-test-foo.desktop", &app(name));
+            dir.write("applications/applist-test-bar.desktop", &app(name));
+        }
+        second.write("applications/applist-test-baz.desktop", &app("Second System"));
+        home.write(".local/share/applications/applist-test-foo.desktop", &app("User"));
+        home.write(".local/share/applications/applist-test-baz.desktop", &app("User"));
+        data_home.write("applications/applist-test-baz.desktop", &app("Data Home"));
+
+        let data_dirs = vec![first.path().to_path_buf(), second.path().to_path_buf()];
+        let dirs = search_dirs_in(Some(home.path()), Some(data_home.path()), data_dirs, &Config::default());
+        let apps =
+            load_applications_with(&mut cache::Cache::default(), &dirs, &Config::default()).unwrap();
+        let name = |id: &str| apps.iter().find(|app| app.id == id).map(|app| app.name.as_str());
+        // the user's copy wins over every system one, not the other way around
+        assert_eq!(name("applist-test-foo.desktop"), Some("User"));
+        // among the data dirs, the first listed wins
+        assert_eq!(name("applist-test-bar.desktop"), Some("System"));
+        assert_eq!(name("applist-test-baz.desktop"), Some("Data Home"));
+        assert_eq!(apps.iter().filter(|app| app.id.starts_with("applist-test-")).count(), 3);
+    }
+
+    #[test]
+    fn unlisted_user_copies_hide_system_ones() {
+        let user = test_util::TestDir::new("override-user");
+        let system = test_util::TestDir::new("override-system");
+        let app = |name: &str| format!("[Desktop Entry]\nExec=true\nName={}\n", name);
+        for id in ["hidden", "nodisplay", "missing", "kept"] {
+            system.write(&format!("applications/applist-test-{}.desktop", id), &app("System"));
+        }
+        // the usual overrides, a bare `Hidden` file needs nothing else
+        user.write("applications/applist-test-hidden.desktop", "[Desktop Entry]\nHidden=true\n");
+        user.write("applications/applist-test-nodisplay.desktop", &(app("User") + "NoDisplay=true\n"));
+        let missing = app("User") + "TryExec=applist-test-missing-binary\n";
+        user.write("applications/applist-test-missing.desktop", &missing);
+
+        let dirs = [user.path().join("applications"), system.path().join("applications")];
+        let fixtures = |config: &Config| -> Vec<(String, String)> {
+            let apps = load_applications_with(&mut cache::Cache::default(), &dirs, config).unwrap();
+            apps.into_iter()
+                .filter(|app| app.id.starts_with("applist-test-"))
+                .map(|app| (app.id, app.name))
+                .collect()
+        };
+        let fixture = |id: &str, name: &str| (format!("applist-test-{}.desktop", id), name.to_string());
+        assert_eq!(fixtures(&Config::default()), [fixture("kept", "System")]);
+        // included, the NoDisplay copy is the one listed, the deleted one still isn't
+        let included = Config { include_nodisplay: true, ..Config::default() };
+        assert_eq!(fixtures(&included), [fixture("nodisplay", "User"), fixture("kept", "System")]);
+    }
+
+    #[test]
+    fn dedup_by_desktop_id() {
+        let user = test_util::TestDir::new("dedup-user");
+        let system = test_util::TestDir::new("dedup-system");
+        let terminal = |name: &str| format!("[Desktop Entry]\nExec=true\nName={}\n", name);
+        user.write("applications/applist-test-term.desktop", &terminal("My Terminal"));
+        system.write("applications/applist-test-term.desktop", &terminal("Terminal"));
+        // same display name, different ID: both are kept
+        system.write("applications/applist-test-other-term.desktop", &terminal("My Terminal"));
+
+        let dirs = [user.path().join("applications"), system.path().join("applications")];
+        let apps =
+            load_applications_with(&mut cache::Cache::default(), &dirs, &Config::default()).unwrap();
+        let fixtures: Vec<(&str, &str)> =
+            apps.iter().map(|app| (app.id.as_str(), app.name.as_str())).collect();
+        assert_eq!(
+            fixtures,
+            vec![
+                ("applist-test-term.desktop", "My Terminal"),
+                ("applist-test-other-term.desktop", "My Terminal"),
+            ]
+        );
+    }
+
+    #[test]
+    fn shadowed_copies_are_logged() {
+        let _lock = config::test_lock();
+        let user = test_util::TestDir::new("shadow-user");
+        let system = test_util::TestDir::new("shadow-system");
+        let user_path = user.write(
+            "applications/applist-test-shadow.desktop",
+            "[Desktop Entry]\nExec=true\nName=New\nX-AppImage-Version=2.0\nVersion=1.5\n",
+        );
+        let system_path = system.write(
+            "applications/applist-test-shadow.desktop",
+            "[Desktop Entry]\nExec=true\nName=Old\nVersion=1.0\n",
+        );
+
+        set_log_callback(Some(capture));
+        let dirs = [user.path().join("applications"), system.path().join("applications")];
+        let mut cache = cache::Cache::default();
+        let apps = load_applications_with(&mut cache, &dirs, &Config::default()).unwrap();
+        // precedence is still by directory
+        assert_eq!(apps.iter().find(|app| app.id == "applist-test-shadow.desktop").unwrap().name, "New");
+
+        let expected = format!(
+            "applist-test-shadow.desktop: {} (X-AppImage-Version=2.0) shadows {} (Version=1.0)",
+            user_path.display(),
+            system_path.display()
+        );
+        let logged = || {
+            let captured = CAPTURED.lock().unwrap();
+            let debug = log::Level::Debug as c_int;
+            captured.iter().filter(|(level, msg)| *level == debug && *msg == expected).count()
+        };
+        assert_eq!(logged(), 1);
+        // answered from the cache, nothing new to tell
+        load_applications_with(&mut cache, &dirs, &Config::default()).unwrap();
+        set_log_callback(None);
+        assert_eq!(logged(), 1);
+    }
+
+    #[test]
+    fn extra_and_override_search_paths() {
+        let home = test_util::TestDir::new("search-paths-home");
+        let app = |name: &str| format!("[Desktop Entry]\nExec=true\nName={}\n", name);
+        home.write(".local/share/applications/applist-test-home.desktop", &app("Home"));
+        let system = test_util::TestDir::new("search-paths-system");
+        system.write("applications/applist-test-system.desktop", &app("System"));
+        let custom = test_util::TestDir::new("search-paths-custom");
+        custom.write("applications/applist-test-custom.desktop", &app("Custom"));
+        let names = |config: &Config| {
+            let data_dirs = vec![system.path().to_path_buf()];
+            let dirs = search_dirs_in(Some(home.path()), None, data_dirs, config);
+            let apps = load_applications_with(&mut cache::Cache::default(), &dirs, config).unwrap();
+            let mut names: Vec<String> = apps.into_iter().map(|app| app.name).collect();
+            names.sort();
+            names
+        };
+
+        // both spellings of a directory mean its `applications` subdirectory
+        let extra = Config {
+            extra_search_paths: vec![custom.path().to_path_buf()],
+            ..Config::default()
+        };
+        assert_eq!(names(&extra), vec!["Custom", "Home", "System"]);
+        let only_custom = Config {
+            override_search_paths: Some(vec![custom.path().join("applications")]),
+            ..Config::default()
+        };
+        assert_eq!(names(&only_custom), vec!["Custom"]);
+        let overridden = Config {
+            override_search_paths: Some(vec![system.path().to_path_buf()]),
+            ..extra
+        };
+        assert_eq!(names(&overridden), vec!["Custom", "System"]);
+
+        // no home directory is needed when the defaults are replaced
+        let dirs = search_dirs_in(None, None, Vec::new(), &only_custom);
+        assert_eq!(dirs, vec![custom.path().join("applications")]);
+    }
+
+    #[test]
+    fn no_home_directory_still_lists_system_apps() {
+        let system = test_util::TestDir::new("homeless-system");
+        system.write("applications/applist-test-system.desktop", "[Desktop Entry]\nExec=true\nName=System\n");
+
+        let dirs = search_dirs_in(None, None, vec![system.path().to_path_buf()], &Config::default());
+        assert_eq!(
+            dirs,
+            vec![
+                system.path().join("applications"),
+                PathBuf::from("/var/lib/flatpak/exports/share/applications"),
+            ]
+        );
+        let apps =
+            load_applications_with(&mut cache::Cache::default(), &dirs, &Config::default()).unwrap();
+        assert!(apps.iter().any(|app| app.id == "applist-test-system.desktop"));
+
+        // an XDG_DATA_HOME set without HOME is still the user's directory
+        let data_home = test_util::TestDir::new("homeless-data-home");
+        let dirs = search_dirs_in(None, Some(data_home.path()), Vec::new(), &Config::default());
+        assert_eq!(dirs[0], data_home.path().join("applications"));
+    }
+
+    #[test]
+    fn scans_flatpak_exports() {
+        let home = test_util::TestDir::new("flatpak-home");
+        home.write(
+            ".local/share/flatpak/exports/share/applications/org.example.Flat.desktop",
+            "[Desktop Entry]\nName=Flat\nExec=/usr/bin/flatpak run org.example.Flat\n",
+        );
+        // the same app through a host shim later in the search path
+        let shim = test_util::TestDir::new("flatpak-shim");
+        shim.write("applications/org.example.Flat.desktop", "[Desktop Entry]\nExec=true\nName=Flat (shim)\n");
+
+        let data_dirs = vec![shim.path().to_path_buf()];
+        let dirs = search_dirs_in(Some(home.path()), None, data_dirs, &Config::default());
+        assert!(dirs.contains(&home.path().join(".local/share/flatpak/exports/share/applications")));
+        assert!(dirs.contains(&PathBuf::from("/var/lib/flatpak/exports/share/applications")));
+
+        let apps =
+            load_applications_with(&mut cache::Cache::default(), &dirs, &Config::default()).unwrap();
+        let flat: Vec<&str> = apps
+            .iter()
+            .filter(|app| app.id == "org.example.Flat.desktop")
+            .map(|app| app.name.as_str())
+            .collect();
+        assert_eq!(flat, vec!["Flat"]);
+    }
+
+    #[test]
+    fn try_exec_resolution() {
+        use std::os::unix::fs::PermissionsExt;
+        let bin = test_util::TestDir::new("try-exec");
+        let tool = bin.write("applist-test-tool", "#!/bin/sh\n");
+        fs::set_permissions(&tool, fs::Permissions::from_mode(0o755)).unwrap();
+        bin.write("not-executable", "");
+        let path_var = std::ffi::OsString::from(format!("/nonexistent:{}", bin.path().display()));
+
+        assert!(find_executable("applist-test-tool", Some(&path_var)));
+        assert!(find_executable(&tool.to_string_lossy(), None));
+        assert!(!find_executable("/nonexistent/bin/applist-test-tool", Some(&path_var)));
+        assert!(!find_executable("not-executable", Some(&path_var)));
+        assert!(!find_executable("applist-test-tool", None));
+
+        let content = "[Desktop Entry]\nName=Tool\nExec=applist-test-tool\nTryExec=applist-test-tool\n";
+        let app = parse_desktop_file(content, "tool.desktop", &[]).unwrap();
+        assert_eq!(app.try_exec.as_deref(), Some("applist-test-tool"));
+    }
+
+    #[test]
+    fn desktop_id_of_nested_file() {
+        let id = desktop_id(
+            Path::new("/usr/share/applications"),
+            Path::new("/usr/share/applications/kde4/kate.desktop"),
+        );
+        assert_eq!(id, "kde4-kate.desktop");
+    }
+
+    #[test]
+    fn init_config_stores_valid_config() {
+        let _lock = config::test_lock();
+        assert!(init_config(literal_as_c_char!(r#"{"max_results": 3, "show_hidden": true}"#)));
+        let stored = config::current();
+        assert_eq!(stored.max_results, 3);
+        // under its old name
+        assert!(stored.include_nodisplay);
+
+        // an invalid config is rejected and the previous one kept
+        assert!(!init_config(literal_as_c_char!("{max_results")));
+        assert_eq!(config::current().max_results, 3);
+
+        config::set(Config::default());
+    }
+
+    static CAPTURED: std::sync::Mutex<Vec<(c_int, String)>> = std::sync::Mutex::new(Vec::new());
+
+    extern "C" fn capture(level: c_int, msg: *const c_char) {
+        let msg = unsafe { CStr::from_ptr(msg) }.to_string_lossy().into_owned();
+        CAPTURED.lock().unwrap().push((level, msg));
+    }
+
+    #[test]
+    fn log_callback_receives_errors() {
+        let _lock = config::test_lock();
+        set_log_callback(Some(capture));
+        assert!(!init_config(std::ptr::null()));
+        set_log_callback(None);
+
+        let captured = CAPTURED.lock().unwrap();
+        assert!(captured.contains(&(log::Level::Error as c_int, "Config is null".to_string())));
+    }
+
+    #[test]
+    fn plugin_cleanup_resets_state() {
+        let _lock = config::test_lock();
+        assert!(init_config(literal_as_c_char!(r#"{"max_results": 1}"#)));
+        set_log_callback(Some(capture));
+        free_entry_list(get_entries(literal_as_c_char!("")));
+        assert!(cache::global().lock().unwrap().files_read > 0);
+
+        plugin_cleanup();
+        assert_eq!(config::current().max_results, Config::default().max_results);
+        assert_eq!(cache::global().lock().unwrap().files_read, 0);
+        let before = CAPTURED.lock().unwrap().len();
+        assert!(!init_config(std::ptr::null()));
+        assert_eq!(CAPTURED.lock().unwrap().len(), before);
+
+        // a fresh init works as on first load
+        assert!(init_config(literal_as_c_char!(r#"{"max_results": 2}"#)));
+        let list = get_entries(literal_as_c_char!(""));
+        assert!(list.length > 0 && list.length <= 2);
+        free_entry_list(list);
+        config::set(Config::default());
+    }
+
+    #[test]
+    fn get_entries_respects_max_results() {
+        let _lock = config::test_lock();
+        let dir = test_util::TestDir::new("max-results");
+        dir.write_apps("capped", "Capped Fixture", 10);
+        let _dir = dir.configure(Config {
+            max_results: 3,
+            ..Config::default()
+        });
+
+        // an empty query lists everything, still within the cap
+        for query in [literal_as_c_char!(""), literal_as_c_char!("capped fixture")] {
+            let list = get_entries(query);
+            assert_eq!(list.length, 3);
+            free_entry_list(list);
+        }
+        let list = get_entries(literal_as_c_char!("capped fixture 7"));
+        assert_eq!(entry_names(&list)[0], "Capped Fixture 7");
+        assert!(list.length <= 3);
+        free_entry_list(list);
+    }
+
+    #[test]
+    fn concurrent_queries_agree() {
+        let _lock = config::test_lock();
+        let dir = test_util::TestDir::new("concurrent");
+        dir.write_apps("concurrent", "Concurrent Fixture", 20);
+        let config = Config { extra_search_paths: vec![dir.path().to_path_buf()], ..Config::default() };
+        config::set(config.clone());
+        // a `&CStr` can be shared with the threads, its pointer can't
+        let query = c"concurrent fixture";
+        let list = get_entries(query.as_ptr());
+        let expected = entry_names(&list);
+        free_entry_list(list);
+        assert_eq!(expected.len(), 20);
+
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..25 {
+                        let list = get_entries(query.as_ptr());
+                        assert_eq!(entry_names(&list), expected);
+                        free_entry_list(list);
+                        assert_eq!(get_entries_into(query.as_ptr(), std::ptr::null_mut(), 0), 20);
+                    }
+                });
+            }
+            // the shared state changing under the readers
+            scope.spawn(|| {
+                for _ in 0..25 {
+                    config::set(config.clone());
+                    cache::clear();
+                    icon::clear_cache();
+                }
+            });
+        });
+        clear_scratch();
+        config::set(Config::default());
+    }
+
+    #[test]
+    fn get_entries_into_truncates_and_counts() {
+        let _lock = config::test_lock();
+        let dir = test_util::TestDir::new("entries-into");
+        dir.write_apps("into", "Buffered Fixture", 4);
+        let _dir = dir.configure(Config::default());
+        let query = literal_as_c_char!("buffered fixture");
+        let name = |entry: &Entry| unsafe { CStr::from_ptr(entry.name) }.to_string_lossy().into_owned();
+
+        // counting only
+        assert_eq!(get_entries_into(query, std::ptr::null_mut(), 0), 4);
+        assert_eq!(get_entries_into(query, std::ptr::null_mut(), 10), 4);
+
+        // same results in the same order as get_entries
+        let list = get_entries(query);
+        let expected = entry_names(&list);
+        free_entry_list(list);
+
+        let mut out = [Entry::null(); 3];
+        assert_eq!(get_entries_into(query, out.as_mut_ptr(), 2), 4);
+        assert_eq!([name(&out[0]), name(&out[1])], expected[..2]);
+        // past the written ones the buffer is untouched
+        assert!(out[2].name.is_null());
+
+        assert_eq!(get_entries_into(query, out.as_mut_ptr(), 3), 4);
+        assert_eq!(name(&out[2]), expected[2]);
+        let mut big = [out[0]; 8];
+        assert_eq!(get_entries_into(literal_as_c_char!("buffered fixture 3"), big.as_mut_ptr(), 8), 1);
+        assert_eq!(name(&big[0]), "Buffered Fixture 3");
+
+        clear_scratch();
+    }
+
+    #[test]
+    fn explain_match_breaks_down_scores() {
+        let _lock = config::test_lock();
+        let dir = test_util::TestDir::new("explain");
+        dir.write(
+            "applications/applist-test-explain.desktop",
+            "[Desktop Entry]\nName=Explained Fixture\nComment=Shows its scores\nExec=explainer\n",
+        );
+        let _dir = dir.configure(Config::default());
+        let explain = |query: &CStr| {
+            let json = explain_match(query.as_ptr(), c"applist-test-explain.desktop".as_ptr());
+            let json = unsafe { CStr::from_ptr(json) }.to_str().unwrap();
+            serde_json::from_str::<serde_json::Value>(json).unwrap()
+        };
+
+        let explained = explain(c"explain");
+        assert!(explained["name"].as_i64().unwrap() > 0);
+        assert!(explained["comment"].is_null());
+        assert!(explained["exec"].as_i64().unwrap() < explained["name"].as_i64().unwrap());
+        // the total adds the launch bonus to the best field
+        assert_eq!(
+            explained["total"].as_i64().unwrap(),
+            explained["name"].as_i64().unwrap() + explained["frecency"].as_i64().unwrap()
+        );
+        let explained = explain(c"scores");
+        assert!(explained["name"].is_null());
+        assert!(explained["comment"].as_i64().unwrap() > 0);
+        assert!(explain(c"zzzz")["total"].is_null());
+
+        assert!(explain_match(c"explained".as_ptr(), c"applist-missing.desktop".as_ptr()).is_null());
+        assert!(explain_match(c"explained".as_ptr(), std::ptr::null()).is_null());
+    }
+
+    #[test]
+    fn warm_index_fills_the_cache() {
+        let _lock = config::test_lock();
+        let dir = test_util::TestDir::new("warm-index");
+        dir.write_apps("warm", "Warm Fixture", 5);
+        let _dir = dir.configure(Config::default());
+        cache::clear();
+
+        spawn_warm_up().unwrap().join().unwrap();
+        let files_read = || cache::global().lock().unwrap().files_read;
+        let warmed = files_read();
+        assert!(warmed >= 5);
+        let list = get_entries(literal_as_c_char!("warm fixture"));
+        assert_eq!(list.length, 5);
+        free_entry_list(list);
+        // the query was answered from what the warm-up read
+        assert_eq!(files_read(), warmed);
+    }
+
+    #[test]
+    fn panicking_exports_return_safe_defaults() {
+        let _lock = config::test_lock();
+        set_log_callback(Some(capture));
+        let panicked = |export: &str| {
+            let message = format!("Panic in {}: injected panic", export);
+            CAPTURED.lock().unwrap().iter().any(|(level, msg)| *level == 0 && *msg == message)
+        };
+
+        guard::inject_panic(Some("get_entries"));
+        let list = get_entries(literal_as_c_char!(""));
+        assert!(list.entries.is_null());
+        assert_eq!(list.length, 0);
+        assert!(panicked("get_entries"));
+        guard::inject_panic(Some("handle_selection"));
+        assert!(!handle_selection(literal_as_c_char!("firefox")));
+        guard::inject_panic(Some("handle_selection_ex"));
+        assert_eq!(handle_selection_ex(literal_as_c_char!("firefox")), 3);
+        guard::inject_panic(Some("init_config"));
+        assert!(!init_config(literal_as_c_char!("{}")));
+        assert!(panicked("init_config"));
+        guard::inject_panic(Some("app_count"));
+        assert_eq!(app_count(), 0);
+        guard::inject_panic(Some("free_entry_list"));
+        free_entry_list(EntryList::empty());
+
+        // nothing is left broken
+        guard::inject_panic(None);
+        assert!(init_config(literal_as_c_char!("{}")));
+        let list = get_entries(literal_as_c_char!(""));
+        assert!(list.length > 0);
+        free_entry_list(list);
+        set_log_callback(None);
+        config::set(Config::default());
+    }
+
+    #[test]
+    fn load_failure_skips_the_directory() {
+        let _lock = config::test_lock();
+        // a file where a directory is expected can't be listed
+        let dir = test_util::TestDir::new("load-failure");
+        dir.write("applications", "not a directory");
+        let dir = dir.configure(Config::default());
+        set_log_callback(Some(capture));
+
+        let list = get_entries(literal_as_c_char!(""));
+        assert!(!list.entries.is_null());
+        free_entry_list(list);
+        set_log_callback(None);
+        let failed = format!("Failed to read {}", dir.path().join("applications").display());
+        assert!(CAPTURED
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(level, msg)| *level == log::Level::Warn as c_int && msg.starts_with(&failed)));
+    }
+
+    #[test]
+    fn unreadable_directory_keeps_the_others() {
+        use std::os::unix::fs::PermissionsExt;
+        let locked = test_util::TestDir::new("unreadable-locked");
+        let open = test_util::TestDir::new("unreadable-open");
+        let app = |name: &str| format!("[Desktop Entry]\nExec=true\nName={}\n", name);
+        locked.write("applications/applist-test-locked.desktop", &app("Locked"));
+        open.write("applications/applist-test-open.desktop", &app("Open"));
+        let locked_dir = locked.path().join("applications");
+        fs::set_permissions(&locked_dir, fs::Permissions::from_mode(0o000)).unwrap();
+
+        let dirs = [locked_dir.clone(), open.path().join("applications")];
+        let apps = load_applications_with(&mut cache::Cache::default(), &dirs, &Config::default());
+        // root reads it anyway, the directory after it must load either way
+        let unreadable = fs::read_dir(&locked_dir).is_err();
+        fs::set_permissions(&locked_dir, fs::Permissions::from_mode(0o755)).unwrap();
+        let names: Vec<String> = apps.unwrap().into_iter().map(|app| app.name).collect();
+        assert!(names.contains(&"Open".to_string()));
+        assert_eq!(names.contains(&"Locked".to_string()), !unreadable);
+    }
+
+    #[test]
+    fn invalid_utf8_selection_is_rejected() {
+        assert!(!handle_selection(c"/apps/\xff\xfe.desktop".as_ptr()));
+    }
+
+    #[test]
+    fn app_count_counts_fixtures() {
+        let _lock = config::test_lock();
+        config::set(Config::default());
+        let system = app_count();
+
+        let dir = test_util::TestDir::new("app-count");
+        dir.write_apps("count", "Counted", 3);
+        dir.write("applications/applist-test-broken.desktop", "no group header\n");
+        let _dir = dir.configure(Config::default());
+        assert_eq!(app_count(), system + 3);
+    }
+
+    #[test]
+    fn get_entries_highlighted_ranges() {
+        let _lock = config::test_lock();
+        let dir = test_util::TestDir::new("highlighted");
+        dir.write(
+            "applications/applist-test-highlight.desktop",
+            "[Desktop Entry]\nExec=true\nName=Firefox Highlight Fixture\nComment=ff always matches\n",
+        );
+        let _dir = dir.configure(Config::default());
+        let ranges = |list: &HighlightedEntryList, i: usize| {
+            let ranges = unsafe { &*list.ranges.add(i) };
+            unsafe { std::slice::from_raw_parts(ranges.ranges, ranges.length) }.to_vec()
+        };
+
+        let list = get_entries_highlighted(literal_as_c_char!("highlight fixture"));
+        assert_eq!(entry_names(&list.list), vec!["Firefox Highlight Fixture"]);
+        let range = |start, end| MatchRange { start, end };
+        assert_eq!(ranges(&list, 0), vec![range(8, 17), range(18, 25)]);
+        free_highlighted_entry_list(list);
+
+        let list = get_entries_highlighted(literal_as_c_char!("ff highlight"));
+        let index = entry_names(&list.list).iter().position(|name| name == "Firefox Highlight Fixture");
+        assert_eq!(ranges(&list, index.unwrap()), vec![range(0, 1), range(4, 5), range(8, 17)]);
+        free_highlighted_entry_list(list);
+
+        // nothing to highlight for an empty query, but the list is still whole
+        let list = get_entries_highlighted(std::ptr::null());
+        assert!(list.list.length > 0);
+        assert!((0..list.list.length).all(|i| ranges(&list, i).is_empty()));
+        free_highlighted_entry_list(list);
+        let empty = HighlightedEntryList { list: EntryList::empty(), ranges: std::ptr::null() };
+        free_highlighted_entry_list(empty);
+    }
+
+    #[test]
+    fn entry_iteration() {
+        let _lock = config::test_lock();
+        let dir = test_util::TestDir::new("entry-iter");
+        dir.write_apps("iter", "Iterated Fixture", 3);
+        let _dir = dir.configure(Config::default());
+        let query = literal_as_c_char!("iterated fixture");
+        let list = get_entries(query);
+        let expected = entry_names(&list);
+        free_entry_list(list);
+        let live = || LIVE_C_STRINGS.with(|live| live.get());
+        let before = live();
+        let mut entry = Entry::null();
+
+        // to the end, in the order of get_entries
+        let iter = get_entries_begin(query);
+        let mut names = Vec::new();
+        while get_entries_next(iter, &mut entry) {
+            names.push(unsafe { CStr::from_ptr(entry.name) }.to_string_lossy().into_owned());
+        }
+        assert_eq!(names, expected);
+        assert!(!get_entries_next(iter, &mut entry));
+        get_entries_end(iter);
+        assert_eq!(live(), before);
+
+        // stopping after the first one frees it too
+        let iter = get_entries_begin(query);
+        assert!(get_entries_next(iter, &mut entry));
+        assert_eq!(unsafe { CStr::from_ptr(entry.name) }.to_string_lossy(), expected[0]);
+        assert!(live() > before);
+        get_entries_end(iter);
+        assert_eq!(live(), before);
+
+        assert!(!get_entries_next(std::ptr::null_mut(), &mut entry));
+        get_entries_end(std::ptr::null_mut());
+    }
+
+    #[test]
+    fn name_replacements_change_only_the_display() {
+        let _lock = config::test_lock();
+        let dir = test_util::TestDir::new("name-replacements");
+        dir.write(
+            "applications/applist-test-writer.desktop",
+            "[Desktop Entry]\nExec=true\nName=Writer — ApplistFixtureOffice\n",
+        );
+        let _dir = dir.configure(Config {
+            name_replacements: vec![("re: — .*$".to_string(), String::new())],
+            ..Config::default()
+        });
+
+        // found by the part of the name no longer shown
+        let list = get_entries(literal_as_c_char!("applistfixtureoffice"));
+        assert_eq!(entry_names(&list), vec!["Writer"]);
+        free_entry_list(list);
+
+        let list = get_entries_highlighted(literal_as_c_char!("applistfixtureoffice writ"));
+        assert_eq!(entry_names(&list.list), vec!["Writer"]);
+        let ranges = unsafe { &*list.ranges };
+        let ranges = unsafe { std::slice::from_raw_parts(ranges.ranges, ranges.length) };
+        assert_eq!(ranges, [MatchRange { start: 0, end: 4 }]);
+        free_highlighted_entry_list(list);
+    }
+
+    #[test]
+    fn broken_name_replacements_are_warned_about() {
+        let _lock = config::test_lock();
+        set_log_callback(Some(capture));
+        assert!(reload_config(cr#"{"name_replacements": [["re:(unclosed", ""]]}"#.as_ptr()));
+        set_log_callback(None);
+        let warning = "Ignoring the name replacement \"(unclosed\"";
+        let warned =
+            |(level, msg): &(c_int, String)| *level == log::Level::Warn as c_int && msg.starts_with(warning);
+        assert!(CAPTURED.lock().unwrap().iter().any(warned));
+        config::set(Config::default());
+    }
+
+    #[test]
+    fn running_apps_found_in_proc() {
+        let _lock = config::test_lock();
+        let dir = test_util::TestDir::new("is-running");
+        // this test binary is running for sure
+        let exe = std::env::current_exe().unwrap();
+        dir.write(
+            "applications/applist-test-running.desktop",
+            &format!("[Desktop Entry]\nName=Running\nExec=env A=1 {} --flag\n", exe.display()),
+        );
+        dir.write(
+            "applications/applist-test-stopped.desktop",
+            "[Desktop Entry]\nName=Stopped\nExec=applist-test-never-started\n",
+        );
+        let _dir = dir.configure(Config::default());
+
+        assert!(is_running(literal_as_c_char!("applist-test-running.desktop")));
+        assert!(!is_running(literal_as_c_char!("applist-test-stopped.desktop")));
+        assert!(!is_running(literal_as_c_char!("applist-test-missing.desktop")));
+        assert!(!is_running(std::ptr::null()));
+    }
+
+    #[test]
+    fn entries_implementing_an_interface() {
+        let _lock = config::test_lock();
+        let dir = test_util::TestDir::new("implements");
+        dir.write(
+            "applications/applist-test-files.desktop",
+            "[Desktop Entry]\nExec=true\nName=Implements Files\n\
+             Implements=org.freedesktop.FileManager1;org.freedesktop.Application;\n",
+        );
+        dir.write(
+            "applications/applist-test-plain.desktop",
+            "[Desktop Entry]\nExec=true\nName=Implements Plain\n",
+        );
+        let _dir = dir.configure(Config::default());
+
+        let names = |iface: *const c_char| -> Vec<String> {
+            let list = get_entries_implementing(iface);
+            let names = entry_names(&list);
+            free_entry_list(list);
+            names.into_iter().filter(|name| name.starts_with("Implements")).collect()
+        };
+        assert_eq!(names(literal_as_c_char!("org.freedesktop.FileManager1")), vec!["Implements Files"]);
+        // interface names are case sensitive
+        assert!(names(literal_as_c_char!("org.freedesktop.filemanager1")).is_empty());
+        assert_eq!(get_entries_implementing(std::ptr::null()).length, 0);
+    }
+
+    #[test]
+    fn entries_for_mime_type() {
+        let _lock = config::test_lock();
+        let dir = test_util::TestDir::new("for-mime");
+        let fixtures = [
+            ("viewer", "MimeType=image/png;image/jpeg;\n"),
+            ("editor", "MimeType=text/plain;Image/PNG;\n"),
+            ("player", "MimeType=audio/ogg;\n"),
+            ("plain", ""),
+        ];
+        for (name, mime_types) in fixtures {
+            dir.write(
+                &format!("applications/applist-test-mime-{}.desktop", name),
+                &format!("[Desktop Entry]\nExec=true\nName=Mime Fixture {}\n{}", name, mime_types),
+            );
+        }
+        let _dir = dir.configure(Config::default());
+
+        let list = get_entries_for_mime(literal_as_c_char!("image/png"));
+        let mut names: Vec<String> =
+            entry_names(&list).into_iter().filter(|name| name.starts_with("Mime Fixture")).collect();
+        names.sort();
+        assert_eq!(names, vec!["Mime Fixture editor", "Mime Fixture viewer"]);
+        free_entry_list(list);
+        assert_eq!(get_entries_for_mime(std::ptr::null()).length, 0);
+
+        // ordered and filtered by mimeapps.list
+        let apps = load_applications(&config::current()).unwrap();
+        let ids = |list: &str| -> Vec<String> {
+            let associations = mimeapps::Associations::merge(&[list], "image/png");
+            apps_for_mime(&apps, "image/png", &associations)
+                .iter()
+                .filter_map(|app| app.id.strip_prefix("applist-test-mime-"))
+                .map(|id| id.trim_end_matches(".desktop").to_string())
+                .collect()
+        };
+        let mut unordered = ids("");
+        unordered.sort();
+        assert_eq!(unordered, vec!["editor", "viewer"]);
+        assert_eq!(
+            ids("[Default Applications]\nimage/png=applist-test-mime-editor.desktop\n"),
+            vec!["editor", "viewer"]
+        );
+        assert_eq!(
+            ids("[Added Associations]\nimage/png=applist-test-mime-player.desktop;\n\
+                 [Removed Associations]\nimage/png=applist-test-mime-viewer.desktop;\n"),
+            vec!["player", "editor"]
+        );
+    }
+
+    #[test]
+    fn get_all_entries_lists_every_app() {
+        let _lock = config::test_lock();
+        let dir = test_util::TestDir::new("all-entries");
+        dir.write_apps("all", "Listed", 3);
+        let _dir = dir.configure(Config {
+            max_results: 1,
+            hidden_ids: vec!["applist-test-all-0.desktop".to_string()],
+            ..Config::default()
+        });
+
+        let list = get_all_entries();
+        // max_results doesn't apply, hidden apps stay hidden
+        assert_eq!(list.length, app_count());
+        let names = entry_names(&list);
+        assert!(names.contains(&"Listed 1".to_string()) && names.contains(&"Listed 2".to_string()));
+        assert!(!names.contains(&"Listed 0".to_string()));
+        free_entry_list(list);
+    }
+
+    #[test]
+    fn reload_config_applies_to_next_query() {
+        let _lock = config::test_lock();
+        let dir = test_util::TestDir::new("reload");
+        dir.write_apps("reload", "Reloaded", 3);
+        let config = |max_results: usize| {
+            let json = format!(
+                r#"{{"max_results": {}, "extra_search_paths": ["{}"]}}"#,
+                max_results,
+                dir.path().display()
+            );
+            CString::new(json).unwrap()
+        };
+        let count = || {
+            let list = get_entries(literal_as_c_char!("reloaded"));
+            let length = list.length;
+            free_entry_list(list);
+            length
+        };
+
+        assert!(init_config(config(3).as_ptr()));
+        assert_eq!(count(), 3);
+        assert!(reload_config(config(1).as_ptr()));
+        assert_eq!(count(), 1);
+        // a bad config keeps the current one
+        assert!(!reload_config(literal_as_c_char!("{max_results")));
+        assert!(!reload_config(std::ptr::null()));
+        assert_eq!(count(), 1);
+        config::set(Config::default());
+    }
+
+    #[test]
+    fn reload_config_rereads_edited_files() {
+        let _lock = config::test_lock();
+        let dir = test_util::TestDir::new("reload-edited");
+        let app_path = "applications/applist-test-edited.desktop";
+        let app = dir.write(app_path, "[Desktop Entry]\nExec=true\nName=Unedited\n");
+        // not watched, only the reload can tell
+        let json =
+            format!(r#"{{"watch_directories": false, "extra_search_paths": ["{}"]}}"#, dir.path().display());
+        let config = CString::new(json).unwrap();
+        // with an index on disk, which has the file as it was
+        *cache::TEST_INDEX.lock().unwrap() = Some(dir.path().join("cache/index.bin"));
+        cache::clear();
+        let names = || {
+            let list = get_entries(literal_as_c_char!("applist-test-edited"));
+            let names = entry_names(&list);
+            free_entry_list(list);
+            names
+        };
+
+        assert!(init_config(config.as_ptr()));
+        assert_eq!(names(), vec!["Unedited"]);
+        // rewritten in place, which leaves the directory mtime alone
+        fs::write(&app, "[Desktop Entry]\nExec=true\nName=Edited\n").unwrap();
+        assert!(reload_config(config.as_ptr()));
+        assert_eq!(names(), vec!["Edited"]);
+        *cache::TEST_INDEX.lock().unwrap() = None;
+        cache::clear();
+        config::set(Config::default());
+    }
+
+    #[test]
+    fn get_entries_test() {
+        let _lock = config::test_lock();
+        let entries = get_entries(literal_as_c_char!(""));
+        assert!(!entries.entries.is_null());
+        assert!(entries.length > 0);
+    }
+
+    fn entry_names(list: &EntryList) -> Vec<String> {
+        let entries = unsafe { std::slice::from_raw_parts(list.entries, list.length) };
+        entries
+            .iter()
+            .map(|entry| unsafe { CStr::from_ptr(entry.name) }.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn launches_boost_ranking() {
+        let _lock = config::test_lock();
+        let dir = test_util::TestDir::new("frecency-ranking");
+        dir.write("applications/applist-test-one.desktop", "[Desktop Entry]\nExec=true\nName=Fixture One\n");
+        let three = dir.write(
+            "applications/applist-test-three.desktop",
+            "[Desktop Entry]\nExec=true\nName=Fixture Three\n",
+        );
+        let _dir = dir.configure(Config::default());
+        reset_frecency();
+
+        let list = get_entries(literal_as_c_char!("fixture"));
+        assert_eq!(entry_names(&list), vec!["Fixture One", "Fixture Three"]);
+        free_entry_list(list);
+
+        let three = CString::new(three.to_string_lossy().into_owned()).unwrap();
+        assert!(handle_selection(three.as_ptr()));
+        let list = get_entries(literal_as_c_char!("fixture"));
+        assert_eq!(entry_names(&list), vec!["Fixture Three", "Fixture One"]);
+        free_entry_list(list);
+
+        reset_frecency();
+    }
+
+    #[test]
+    fn empty_query_sort_modes() {
+        let _lock = config::test_lock();
+        let dir = test_util::TestDir::new("sort-mode");
+        for (file, name) in [
+            ("zulu", "Zulu Sortfix"),
+            ("alpha", "alpha Sortfix"),
+            ("echo", "Écho Sortfix"),
+            ("bravo", "Bravo Sortfix"),
+        ] {
+            dir.write(
+                &format!("applications/applist-test-{}.desktop", file),
+                &format!("[Desktop Entry]\nExec=true\nName={}\n", name),
+            );
+        }
+        let fixtures = |sort_mode| {
+            config::set(Config {
+                sort_mode,
+                max_results: usize::MAX,
+                extra_search_paths: vec![dir.path().to_path_buf()],
+                ..Config::default()
+            });
+            let list = get_entries(literal_as_c_char!(""));
+            let names: Vec<String> =
+                entry_names(&list).into_iter().filter(|name| name.ends_with("Sortfix")).collect();
+            free_entry_list(list);
+            names
+        };
+        reset_frecency();
+
+        let alphabetical = vec!["alpha Sortfix", "Bravo Sortfix", "Écho Sortfix", "Zulu Sortfix"];
+        assert_eq!(fixtures(SortMode::Alphabetical), alphabetical);
+        assert_eq!(fixtures(SortMode::Frecency), alphabetical);
+        frecency::record_launch("applist-test-zulu.desktop");
+        assert_eq!(fixtures(SortMode::Frecency)[0], "Zulu Sortfix");
+        assert_eq!(fixtures(SortMode::Alphabetical), alphabetical);
+
+        reset_frecency();
+        config::set(Config::default());
+    }
+
+    #[test]
+    fn ignored_prefixes_skip_loading() {
+        let _lock = config::test_lock();
+        config::set(Config {
+            ignore_query_prefixes: vec!["=".to_string(), "!g ".to_string(), String::new()],
+            ..Config::default()
+        });
+        let loads = || APP_LOADS.with(|loads| loads.get());
+        let before = loads();
+        let mut entry = Entry::null();
+        for query in [c"=2+2", c"=", c" !g rust", c"!g "] {
+            assert_eq!(get_entries(query.as_ptr()).length, 0, "{:?}", query);
+            assert_eq!(get_entries_into(query.as_ptr(), std::ptr::null_mut(), 0), 0);
+            let highlighted = get_entries_highlighted(query.as_ptr());
+            assert_eq!(highlighted.list.length, 0);
+            free_highlighted_entry_list(highlighted);
+            let iter = get_entries_begin(query.as_ptr());
+            assert!(!iter.is_null());
+            assert!(!get_entries_next(iter, &mut entry));
+            get_entries_end(iter);
+        }
+        assert_eq!(loads(), before);
+
+        // an empty prefix ignores nothing, the prefix has to start the query
+        let list = get_entries(c"!gimp".as_ptr());
+        free_entry_list(list);
+        assert_eq!(loads(), before + 1);
+        config::set(Config::default());
+    }
+
+    #[test]
+    fn short_queries_skip_loading() {
+        let _lock = config::test_lock();
+        let dir = test_util::TestDir::new("min-query");
+        dir.write("applications/applist-test-abacus.desktop", "[Desktop Entry]\nExec=true\nName=Abacus\n");
+        config::set(Config {
+            override_search_paths: Some(vec![dir.path().to_path_buf()]),
+            min_query_length: 2,
+            ..Config::default()
+        });
+        let loads = || APP_LOADS.with(|loads| loads.get());
+        let before = loads();
+        for query in [c"a", c" a ", c"é"] {
+            assert_eq!(get_entries(query.as_ptr()).length, 0, "{:?}", query);
+        }
+        assert_eq!(loads(), before);
+
+        let list = get_entries(c"ab".as_ptr());
+        assert_eq!(entry_names(&list), ["Abacus"]);
+        free_entry_list(list);
+        // an empty query still lists everything
+        let list = get_entries(c"".as_ptr());
+        assert_eq!(entry_names(&list), ["Abacus"]);
+        free_entry_list(list);
+        config::set(Config::default());
+    }
+
+    #[test]
+    fn query_pointers() {
+        assert_eq!(query_from_ptr(std::ptr::null()), "");
+        assert_eq!(query_from_ptr(EMPTY_QUERY_SENTINEL as *const c_char), "");
+        assert_eq!(query_from_ptr(16 as *const c_char), "");
+        assert_eq!(query_from_ptr(literal_as_c_char!("")), "");
+        assert_eq!(query_from_ptr(literal_as_c_char!("fire")), "fire");
+    }
+
+    #[test]
+    fn selection_with_files() {
+        assert_eq!(split_selection("/apps/a.desktop"), ("/apps/a.desktop", vec![]));
+        assert_eq!(
+            split_selection("/apps/a.desktop\x1f/home/me/a b.txt\x1f\x1fhttps://example.com"),
+            ("/apps/a.desktop", vec!["/home/me/a b.txt".to_string(), "https://example.com".to_string()])
+        );
+
+        let content = "[Desktop Entry]\nName=Editor\nExec=editor --new %f\n";
+        let app = parse_desktop_file(content, "e.desktop", &[]).unwrap();
+        assert_eq!(exec_argv(&app, &[]), vec!["editor", "--new"]);
+        assert_eq!(
+            exec_argv(&app, &["/home/me/a b.txt".to_string()]),
+            vec!["editor", "--new", "/home/me/a b.txt"]
+        );
+    }
+
+    #[test]
+    fn terminal_command_keeps_arguments() {
+        let content = "[Desktop Entry]\nName=Éditeur\nIcon=editor\nTerminal=true\n\
+                       Exec=sensible-editor --title %c %i %F\n";
+        let app = parse_desktop_file(content, "/apps/editor.desktop", &[]).unwrap();
+        let files = ["/home/me/a.txt".to_string(), "/home/me/b.txt".to_string()];
+        let terminal = terminal::candidates(Some("foot"), None);
+        assert_eq!(
+            terminal::build_argv(&terminal[0], &exec_argv(&app, &files)),
+            vec![
+                "foot",
+                "sensible-editor",
+                "--title",
+                "Éditeur",
+                "--icon",
+                "editor",
+                "/home/me/a.txt",
+                "/home/me/b.txt",
+            ]
+        );
+    }
+
+    #[test]
+    fn dbus_activatable_is_parsed() {
+        let content = "[Desktop Entry]\nName=Files\nExec=nautilus --new-window\nDBusActivatable=true\n";
+        let app = parse_desktop_file(content, "/usr/share/applications/org.gnome.Nautilus.desktop", &[]);
+        let app = app.unwrap();
+        assert!(app.dbus_activatable);
+        assert_eq!(dbus::bus_name(&app.id), Some("org.gnome.Nautilus"));
+
+        let app = parse_desktop_file("[Desktop Entry]\nExec=true\nName=App\n", "app.desktop", &[]).unwrap();
+        assert!(!app.dbus_activatable);
+    }
+
+    #[test]
+    fn work_dir_is_applied() {
+        let content = "[Desktop Entry]\nName=Game\nExec=./game\nPath=/tmp/foo\n";
+        let app = parse_desktop_file(content, "game.desktop", &[]).unwrap();
+        assert_eq!(app.work_dir.as_deref(), Some("/tmp/foo"));
+
+        let dir = test_util::TestDir::new("work-dir");
+        let argv = exec_argv(&app, &[]);
+        let command = app_command(&argv, Some(&dir.path().to_string_lossy()));
+        assert_eq!(command.get_program(), "./game");
+        assert_eq!(command.get_current_dir(), Some(dir.path()));
+        // a directory that doesn't exist is ignored rather than failing the launch
+        assert_eq!(app_command(&argv, Some("/nonexistent/applist")).get_current_dir(), None);
+        assert_eq!(app_command(&argv, None).get_current_dir(), None);
+    }
+
+    #[test]
+    fn env_prefix_and_variables_at_launch() {
+        let content = "[Desktop Entry]\nName=Chat\nExec=env QT_SCALE_FACTOR=2 chat --profile=$HOME/p %f\n";
+        let app = parse_desktop_file(content, "chat.desktop", &[]).unwrap();
+        let home = std::env::var("HOME").unwrap();
+        let argv = exec_argv(&app, &["a b.txt".to_string()]);
+        let profile = format!("--profile={}/p", home);
+        assert_eq!(argv, vec!["env", "QT_SCALE_FACTOR=2", "chat", &profile, "a b.txt"]);
+
+        let command = app_command(&argv, Some("${HOME}"));
+        assert_eq!(command.get_program(), "chat");
+        assert_eq!(command.get_args().count(), 2);
+        let envs: Vec<_> = command.get_envs().collect();
+        assert!(envs.contains(&(std::ffi::OsStr::new("QT_SCALE_FACTOR"), Some(std::ffi::OsStr::new("2")))));
+        assert_eq!(command.get_current_dir(), Some(Path::new(&home)));
+    }
+
+    #[test]
+    fn resolved_launch_commands() {
+        use std::os::unix::fs::PermissionsExt;
+        let bin = test_util::TestDir::new("resolve-launch");
+        for program in ["konsole", "gtk-launch", "applist-test-viewer"] {
+            let stub = bin.write(program, "#!/bin/sh\n");
+            fs::set_permissions(&stub, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let path_var = std::ffi::OsString::from(bin.path());
+        let config = Config::default();
+        let resolve = |path: &str, content: &str, files: &[&str]| {
+            let app = parse_desktop_file(content, path, &[]).unwrap();
+            let files: Vec<String> = files.iter().map(|file| file.to_string()).collect();
+            resolve_argv(path, Some(&app), &files, &config, Some(&path_var))
+        };
+
+        // the first known terminal that is installed
+        let htop = "[Desktop Entry]\nName=htop\nExec=htop -d 5\nTerminal=true\n";
+        assert_eq!(resolve("/apps/htop.desktop", htop, &[]), ["konsole", "-e", "htop", "-d", "5"]);
+        // GUI apps go through gtk-launch, unless there are files to substitute
+        let viewer = "[Desktop Entry]\nName=Viewer\nExec=applist-test-viewer --open %f\n";
+        assert_eq!(resolve("/apps/viewer.desktop", viewer, &[]), ["gtk-launch", "/apps/viewer.desktop"]);
+        assert_eq!(
+            resolve("/apps/viewer.desktop", viewer, &["/home/me/a b.png"]),
+            ["applist-test-viewer", "--open", "/home/me/a b.png"]
+        );
+        // gdbus stands for the D-Bus activation
+        let files = "[Desktop Entry]\nName=Files\nExec=nautilus\nDBusActivatable=true\n";
+        let app = parse_desktop_file(files, "/apps/org.gnome.Nautilus.desktop", &[]).unwrap();
+        let attempts = launch_attempts(&app.path, Some(&app), &[], &config, Some(&path_var));
+        assert_eq!(attempts[0], LaunchAttempt::DBus);
+        let gdbus = attempts[0].argv(&app.path, Some(&app), &[]);
+        assert_eq!(gdbus[..4], ["gdbus", "call", "--session", "--dest"]);
+        // with nothing installed, the first one
+        let nothing = std::ffi::OsString::from("/nonexistent");
+        let attempts_without = resolve_argv("/apps/viewer.desktop", None, &[], &config, Some(&nothing));
+        assert_eq!(attempts_without, ["gtk-launch", "/apps/viewer.desktop"]);
+    }
+
+    #[test]
+    fn resolve_launch_command_export() {
+        let _lock = config::test_lock();
+        let dir = test_util::TestDir::new("resolve-export");
+        let path = dir.write(
+            "applications/applist-test-resolve.desktop",
+            "[Desktop Entry]\nExec=true %f\nName=Resolve Fixture\n",
+        );
+        let _dir = dir.configure(Config::default());
+
+        let selection = CString::new(format!("{}{}/tmp/a.txt", path.display(), ARG_SEPARATOR)).unwrap();
+        let resolved = resolve_launch_command(selection.as_ptr());
+        let resolved = unsafe { CStr::from_ptr(resolved) }.to_str().unwrap();
+        assert_eq!(serde_json::from_str::<Vec<String>>(resolved).unwrap(), ["true", "/tmp/a.txt"]);
+        assert!(resolve_launch_command(std::ptr::null()).is_null());
+    }
+
+    #[test]
+    fn systemd_scope_wraps_the_command() {
+        use std::os::unix::fs::PermissionsExt;
+        let bin = test_util::TestDir::new("systemd-run");
+        let systemd_run = bin.write("systemd-run", "#!/bin/sh\n");
+        fs::set_permissions(&systemd_run, fs::Permissions::from_mode(0o755)).unwrap();
+        let path_var = std::ffi::OsString::from(bin.path());
+        let argv = vec!["firefox".to_string(), "--new-window".to_string()];
+        let enabled = Config { use_systemd_scope: true, ..Config::default() };
+
+        assert_eq!(
+            scoped_argv(argv.clone(), &enabled, Some(&path_var)),
+            ["systemd-run", "--user", "--scope", "--quiet", "--", "firefox", "--new-window"]
+        );
+        // off by default, and launched directly without systemd-run
+        assert_eq!(scoped_argv(argv.clone(), &Config::default(), Some(&path_var)), argv);
+        assert_eq!(scoped_argv(argv.clone(), &enabled, Some("/nonexistent".as_ref())), argv);
+    }
+
+    #[test]
+    fn launch_wrappers_prefix_matching_apps() {
+        use std::os::unix::fs::PermissionsExt;
+        let bin = test_util::TestDir::new("wrappers");
+        for wrapper in ["gamemoderun", "mangohud", "game"] {
+            let path = bin.write(wrapper, "#!/bin/sh\n");
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let path_var = std::ffi::OsString::from(bin.path());
+        let content = "[Desktop Entry]\nName=Game\nExec=game --fullscreen\nCategories=Game;ActionGame;\n";
+        let game = parse_desktop_file(content, "/tmp/game.desktop", &[]).unwrap();
+        let config = config::parse(r#"{"launch_wrappers": [
+            {"match": "Game", "command": "gamemoderun"},
+            {"match": "Video", "command": "nice"}
+        ]}"#)
+        .unwrap();
+        let resolve = |config: &Config| resolve_argv(&game.path, Some(&game), &[], config, Some(&path_var));
+
+        assert_eq!(resolve(&config), ["gamemoderun", "game", "--fullscreen"]);
+        // stacked in order, and matched by ID too
+        let mut stacked = config.clone();
+        stacked.launch_wrappers.push(config::LaunchWrapper {
+            target: "game.desktop".to_string(),
+            command: "mangohud --dlsym".to_string(),
+        });
+        assert_eq!(resolve(&stacked), ["gamemoderun", "mangohud", "--dlsym", "game", "--fullscreen"]);
+        // unwrapped apps still go through gtk-launch
+        assert_eq!(resolve(&Config::default())[0], "gtk-launch");
+    }
+
+    #[test]
+    fn startup_notify_gets_the_token() {
+        let content = "[Desktop Entry]\nName=Files\nExec=nautilus\nStartupNotify=true\n";
+        let notifying = parse_desktop_file(content, "nautilus.desktop", &[]).unwrap();
+        assert!(notifying.startup_notify);
+        // like the other booleans, whatever the case
+        let content = "[Desktop Entry]\nName=Files\nExec=nautilus\nStartupNotify=True\n";
+        assert!(parse_desktop_file(content, "nautilus.desktop", &[]).unwrap().startup_notify);
+        let content = "[Desktop Entry]\nName=Xterm\nExec=xterm\nStartupNotify=false\n";
+        let silent = parse_desktop_file(content, "xterm.desktop", &[]).unwrap();
+        assert!(!silent.startup_notify);
+
+        // launchers get it too, for the app they start
+        let launcher = ["gtk-launch".to_string(), "nautilus.desktop".to_string()];
+        let envs = |token: Option<&str>| {
+            attempt_command(&launcher, None, token)
+                .get_envs()
+                .map(|(var, value)| (var.to_string_lossy().into_owned(), value.map(|v| v.to_owned())))
+                .collect::<Vec<_>>()
+        };
+        let token = Some(std::ffi::OsString::from("token-1"));
+        assert_eq!(
+            envs(Some("token-1")),
+            vec![
+                ("DESKTOP_STARTUP_ID".to_string(), token.clone()),
+                ("XDG_ACTIVATION_TOKEN".to_string(), token),
+            ]
+        );
+        assert!(envs(None).iter().all(|(_, value)| value.is_none()));
+
+        // apps not notifying leave the token for the next launch
+        set_activation_token(literal_as_c_char!("token-1"));
+        assert_eq!(take_activation_token(&silent), None);
+        assert_eq!(take_activation_token(&notifying).as_deref(), Some("token-1"));
+        assert_eq!(take_activation_token(&notifying), None);
+        set_activation_token(literal_as_c_char!("token-2"));
+        set_activation_token(std::ptr::null());
+        assert_eq!(take_activation_token(&notifying), None);
+    }
+
+    #[test]
+    fn handle_selection_with_file_records_launch() {
+        let _lock = config::test_lock();
+        let dir = test_util::TestDir::new("selection-files");
+        let path = dir.write(
+            "applications/applist-test-editor.desktop",
+            "[Desktop Entry]\nName=Editor\nExec=editor %f\n",
+        );
+        let _dir = dir.configure(Config::default());
+        reset_frecency();
+
+        let selection = CString::new(format!("{}\x1f/home/me/notes.txt", path.display())).unwrap();
+        assert!(handle_selection(selection.as_ptr()));
+        let now = frecency::now();
+        assert!(frecency::current().value("applist-test-editor.desktop", now) > 0.0);
+
+        reset_frecency();
+    }
+
+    #[test]
+    fn batch_selections_record_each_launch() {
+        let _lock = config::test_lock();
+        let dir = test_util::TestDir::new("selections");
+        let paths: Vec<PathBuf> = ["one", "two"]
+            .iter()
+            .map(|name| {
+                dir.write(
+                    &format!("applications/applist-test-batch-{}.desktop", name),
+                    &format!("[Desktop Entry]\nName=Batch {}\nExec=true\n", name),
+                )
+            })
+            .collect();
+        let _dir = dir.configure(Config::default());
+        reset_frecency();
+
+        let selections: Vec<CString> =
+            paths.iter().map(|path| CString::new(path.to_string_lossy().as_bytes()).unwrap()).collect();
+        let mut values: Vec<*const c_char> = selections.iter().map(|s| s.as_ptr()).collect();
+        assert_eq!(handle_selections(values.as_ptr(), values.len()), 2);
+        let now = frecency::now();
+        for id in ["applist-test-batch-one.desktop", "applist-test-batch-two.desktop"] {
+            assert!(frecency::current().value(id, now) > 0.0, "{}", id);
+        }
+
+        // a missing selection doesn't stop the others
+        values.insert(1, std::ptr::null());
+        assert_eq!(handle_selections(values.as_ptr(), values.len()), 2);
+        assert_eq!(handle_selections(std::ptr::null(), 3), 0);
+        assert_eq!(handle_selections(values.as_ptr(), 0), 0);
+
+        reset_frecency();
+    }
+
+    #[test]
+    fn selection_status_codes() {
+        let app = parse_desktop_file("[Desktop Entry]\nName=App\nExec=app\n", "/apps/app.desktop", &[]);
+        let mut app = app.unwrap();
+        let status = |app: Option<&AppInfo>| launch("/apps/app.desktop", app, &[], &Config::default());
+
+        assert_eq!(status(Some(&app)), LaunchStatus::Launched);
+        assert_eq!(status(None), LaunchStatus::Launched);
+        LAUNCH_SUCCEEDS.with(|succeeds| succeeds.set(false));
+        assert_eq!(status(Some(&app)), LaunchStatus::SpawnFailed);
+        assert_eq!(status(None), LaunchStatus::NotFound);
+        LAUNCH_SUCCEEDS.with(|succeeds| succeeds.set(true));
+        app.validation_error = Some("app.desktop: error: broken".to_string());
+        assert_eq!(status(Some(&app)), LaunchStatus::ValidationFailed);
+
+        assert_eq!(handle_selection_ex(literal_as_c_char!("firefox")), 0);
+        assert_eq!(handle_selection_ex(std::ptr::null()), 1);
+        assert_eq!(handle_selection_ex(c"/apps/\xff.desktop".as_ptr()), 1);
+        LAUNCH_SUCCEEDS.with(|succeeds| succeeds.set(false));
+        assert_eq!(handle_selection_ex(literal_as_c_char!("/nonexistent/app.desktop")), 1);
+        assert!(!handle_selection(literal_as_c_char!("/nonexistent/app.desktop")));
+        LAUNCH_SUCCEEDS.with(|succeeds| succeeds.set(true));
+    }
+
+    #[test]
+    fn handle_selection_test() {
+        let selection = literal_as_c_char!("firefox");
+        let result = handle_selection(selection);
+        assert!(result);
+    }
+
+    #[test]
+    fn free_entry_list_releases_every_string() {
+        let before = LIVE_C_STRINGS.with(|live| live.get());
+        let apps: Vec<AppInfo> = [
+            "[Desktop Entry]\nExec=true\nName=One\nComment=First\nIcon=one\nStartupWMClass=one\n",
+            "[Desktop Entry]\nExec=true\nName=Two\n",
+        ]
+        .iter()
+        .map(|content| parse_desktop_file(content, "app.desktop", &[]).unwrap())
+        .collect();
+
+        let arenas = LIVE_ARENAS.with(|live| live.get());
+        let list = into_entry_list(apps.iter().map(|app| entry_text(app, &Config::default())).collect());
+        assert_eq!(list.length, 2);
+        let first = unsafe { &*list.entries };
+        assert_eq!(unsafe { CStr::from_ptr(first.name) }.to_str().unwrap(), "One");
+        // the 7 strings share one arena
+        assert_eq!(LIVE_C_STRINGS.with(|live| live.get()), before);
+        assert_eq!(LIVE_ARENAS.with(|live| live.get()) - arenas, 1);
+
+        free_entry_list(list);
+        assert_eq!(LIVE_ARENAS.with(|live| live.get()), arenas);
+    }
+
+    // no file system, so it also runs under `cargo +nightly miri test arena`
+    #[test]
+    fn entry_list_arena_round_trip() {
+        let text = |name: &str, description: Option<&str>| EntryText {
+            name: name.to_string(),
+            description: description.map(str::to_string),
+            value: format!("/apps/{}.desktop", name),
+            icon: None,
+            emoji: Some("🎮".to_string()),
+            startup_wm_class: None,
+        };
+        let read = |s: *const c_char| unsafe { CStr::from_ptr(s) }.to_str().unwrap().to_string();
+        let first = into_entry_list(vec![text("Één", Some("nul\0byte")), text("", Some(""))]);
+        // another list coming and going leaves the first one alone
+        let second = into_entry_list(vec![text("Other", None)]);
+        assert_eq!(read(unsafe { &*second.entries }.name), "Other");
+        free_entry_list(second);
+
+        let entries = unsafe { std::slice::from_raw_parts(first.entries, first.length) };
+        assert_eq!(read(entries[0].name), "Één");
+        assert_eq!(read(entries[0].description), "nulbyte");
+        assert_eq!(read(entries[0].value), "/apps/Één.desktop");
+        assert_eq!(read(entries[0].emoji), "🎮");
+        assert!(entries[0].icon.is_null() && entries[0].startup_wm_class.is_null());
+        assert_eq!(read(entries[1].name), "");
+        assert_eq!(read(entries[1].description), "");
+        free_entry_list(first);
+        free_entry_list(into_entry_list(Vec::new()));
+    }
+
+    #[test]
+    fn generic_name_is_localized_and_described() {
+        let content =
+            "[Desktop Entry]\nName=Firefox\nExec=firefox\nGenericName=Web Browser\n\
+             GenericName[fr]=Navigateur Web\n";
+        let app = parse_desktop_file(content, "firefox.desktop", &locale_candidates("fr_FR")).unwrap();
+        assert_eq!(app.generic_name.as_deref(), Some("Navigateur Web"));
+
+        // without a Comment the generic name is the description
+        let list = into_entry_list(vec![entry_text(&app, &Config::default())]);
+        let entry = unsafe { &*list.entries };
+        assert_eq!(unsafe { CStr::from_ptr(entry.description) }.to_str().unwrap(), "Navigateur Web");
+        free_entry_list(list);
+    }
+
+    #[test]
+    fn source_in_description() {
+        let content = "[Desktop Entry]\nName=Files\nComment=Browse files\nExec=nautilus\n";
+        let path = "/usr/share/applications/org.gnome.Nautilus.desktop";
+        let with_comment = parse_desktop_file(content, path, &[]).unwrap();
+        let without = parse_desktop_file("[Desktop Entry]\nName=Files\nExec=nautilus\n", path, &[]).unwrap();
+        let description = |app: &AppInfo, show_source_in_description: bool| {
+            let config = Config { show_source_in_description, ..Config::default() };
+            let list = into_entry_list(vec![entry_text(app, &config)]);
+            let description = unsafe { &*list.entries }.description;
+            let description = (!description.is_null())
+                .then(|| unsafe { CStr::from_ptr(description) }.to_str().unwrap().to_string());
+            free_entry_list(list);
+            description
+        };
+
+        assert_eq!(
+            description(&with_comment, true).as_deref(),
+            Some("Browse files (nautilus from /usr/share/applications/org.gnome.Nautilus.desktop)")
+        );
+        assert_eq!(description(&without, true), Some(format!("nautilus from {}", path)));
+        assert_eq!(description(&with_comment, false).as_deref(), Some("Browse files"));
+        assert_eq!(description(&without, false), None);
+    }
+
+    #[test]
+    fn displayed_command_keeps_launch_exec() {
+        let content = "[Desktop Entry]\nName=App\nExec=app %U --foo\n";
+        let app = parse_desktop_file(content, "/usr/share/applications/app.desktop", &[]).unwrap();
+        let config = Config { show_source_in_description: true, ..Config::default() };
+        let list = into_entry_list(vec![entry_text(&app, &config)]);
+        let description = unsafe { CStr::from_ptr((*list.entries).description) };
+        assert_eq!(description.to_str().unwrap(), "app --foo from /usr/share/applications/app.desktop");
+        free_entry_list(list);
+        assert_eq!(exec_argv(&app, &["/tmp/a.txt".to_string()]), vec!["app", "/tmp/a.txt", "--foo"]);
+    }
+
+    #[test]
+    fn typos_found_by_fallback() {
+        let app = |name: &str| {
+            let content = format!("[Desktop Entry]\nName={}\nExec=true\n", name);
+            parse_desktop_file(&content, &format!("/apps/{}.desktop", name), &[]).unwrap()
+        };
+        let apps = vec![app("Files"), app("Firefox"), app("Firefix")];
+        let history = frecency::Frecency::default();
+        let names = |query: &str, config: &Config| -> Vec<String> {
+            let found = query_apps(&apps, query, config, &history, 0);
+            found.iter().map(|found| found.app.name.clone()).collect()
+        };
+        let config = Config::default();
+        assert_eq!(names("fierfox", &config), vec!["Firefox"]);
+        assert_eq!(names("firefux", &config), vec!["Firefox", "Firefix"]);
+        // a real match leaves out the typos
+        assert_eq!(names("firefox", &config), vec!["Firefox"]);
+        // unless fewer apps match than asked for, the near names then follow
+        // the matches, which aren't listed twice
+        let below_two = Config { typo_fallback_below: 2, ..Config::default() };
+        assert_eq!(names("firefox", &below_two), vec!["Firefox", "Firefix"]);
+        assert!(names("fierfox", &Config { max_typos: 0, ..Config::default() }).is_empty());
+    }
+
+    #[test]
+    fn typos_follow_a_weak_match() {
+        let apps = vec![
+            parse_desktop_file("[Desktop Entry]\nName=Firefox\nExec=firefox\n", "/apps/firefox.desktop", &[]),
+            parse_desktop_file(
+                "[Desktop Entry]\nName=Notes\nComment=Sort fiery fox photos\nExec=notes\n",
+                "/apps/notes.desktop",
+                &[],
+            ),
+        ];
+        let apps: Vec<AppInfo> = apps.into_iter().map(Option::unwrap).collect();
+        let config = Config { typo_fallback_below: 2, ..Config::default() };
+        let found = query_apps(&apps, "fierfox", &config, &frecency::Frecency::default(), 0);
+        let names: Vec<&str> = found.iter().map(|found| found.app.name.as_str()).collect();
+        // the comment matches, the typo of the name still shows, ranked below
+        assert_eq!(names, ["Notes", "Firefox"]);
+        assert!(found[0].score.is_some() && found[1].score.is_none());
+    }
+
+    #[test]
+    fn query_apps_scores_best_first() {
+        let app = |name: &str, comment: &str| {
+            let content = format!("[Desktop Entry]\nName={}\nComment={}\nExec=true\n", name, comment);
+            parse_desktop_file(&content, &format!("/apps/{}.desktop", name), &[]).unwrap()
+        };
+        let apps = vec![app("Terminal", "Use the command line"), app("Text Editor", "Edit text files")];
+        let config = Config::default();
+        let history = frecency::Frecency::default();
+
+        let found = query_apps(&apps, "te", &config, &history, 0);
+        assert_eq!(found.len(), 2);
+        assert!(found.windows(2).all(|pair| pair[0].score >= pair[1].score));
+        assert!(found.iter().all(|found| found.score.is_some()));
+        // launches count from the history passed in, as of `now`
+        let mut launched = frecency::Frecency::default();
+        launched.record("Text Editor.desktop", 100);
+        let editor =
+            |found: &[ScoredApp]| found.iter().find(|found| found.app.name == "Text Editor").unwrap().score;
+        assert!(editor(&query_apps(&apps, "te", &config, &launched, 100)) > editor(&found));
+        // the comment matches too, below the name
+        let found = query_apps(&apps, "command", &config, &history, 0);
+        assert_eq!(found.iter().map(|found| found.app.name.as_str()).collect::<Vec<_>>(), vec!["Terminal"]);
+        // typos have no score of their own
+        let found = query_apps(&apps, "termnial", &config, &history, 0);
+        assert_eq!(found[0].app.name, "Terminal");
+        assert_eq!(found[0].score, None);
+        let one = Config { max_results: 1, ..Config::default() };
+        assert_eq!(query_apps(&apps, "", &one, &history, 0).len(), 1);
+        assert!(query_apps(&[], "te", &config, &history, 0).is_empty());
+    }
+
+    /// Times `query_apps` over a few thousand apps, without the FFI around it.
+    #[test]
+    #[ignore = "benchmark, run with `cargo test --release -- --ignored --nocapture bench_`"]
+    fn bench_query_apps() {
+        let words =
+            ["Files", "Text", "Editor", "Web", "Browser", "Music", "Player", "System", "Monitor", "Office"];
+        let apps: Vec<AppInfo> = (0..5000)
+            .map(|i| {
+                let name = format!("{} {} {}", words[i % 10], words[i / 10 % 10], i);
+                let content = format!(
+                    "[Desktop Entry]\nName={}\nComment={} for {}\nKeywords={};{};\nExec=app{}\n",
+                    name, words[i / 100 % 10], words[i % 7], words[i % 3], words[i % 9], i
+                );
+                parse_desktop_file(&content, &format!("/apps/app{}.desktop", i), &[]).unwrap()
+            })
+            .collect();
+        let config = Config::default();
+        let history = frecency::Frecency::default();
+        for query in ["", "f", "text ed", "brwoser", "monitor system 42"] {
+            let start = std::time::Instant::now();
+            let rounds = 20;
+            let mut found = 0;
+            for _ in 0..rounds {
+                found = query_apps(&apps, query, &config, &history, 0).len();
+            }
+            println!("{:?}: {:?} per query, {} results", query, start.elapsed() / rounds, found);
+        }
+    }
+
+    #[test]
+    fn demoted_categories_rank_last() {
+        let app = |name: &str, categories: &str| {
+            let content = format!("[Desktop Entry]\nName={}\nExec=true\nCategories={}\n", name, categories);
+            parse_desktop_file(&content, &format!("/apps/{}.desktop", name), &[]).unwrap()
+        };
+        let apps = vec![
+            app("Appearance", "Settings;DesktopSettings;"),
+            app("Sound", "Settings;HardwareSettings;"),
+            app("Zoom", "Network;"),
+        ];
+        let history = frecency::Frecency::default();
+        let names = |query: &str, config: &Config| -> Vec<String> {
+            let found = query_apps(&apps, query, config, &history, 0);
+            found.iter().map(|found| found.app.name.clone()).collect()
+        };
+        let demoted = Config { demoted_categories: vec!["Settings".to_string()], ..Config::default() };
+        assert_eq!(names("", &Config::default()), vec!["Appearance", "Sound", "Zoom"]);
+        assert_eq!(names("", &demoted), vec!["Zoom", "Appearance", "Sound"]);
+        // a better match still ranks below the apps that aren't demoted
+        assert_eq!(names("o", &demoted)[0], "Zoom");
+        assert_eq!(names("sound", &demoted), vec!["Sound"]);
+    }
+
+    #[test]
+    fn recently_added_apps_are_flagged() {
+        let day = 24 * 3600;
+        let now = 1_000 * day;
+        let mut history = frecency::Frecency::default();
+        // tracked since the first run
+        history.see(&["applist-test-old.desktop"], now - 30 * day);
+        history.see(&["applist-test-old.desktop", "applist-test-new.desktop"], now - 2 * day);
+        let app = |id: &str| AppInfo { id: id.to_string(), name: id.to_string(), ..AppInfo::default() };
+        let mut apps = vec![app("applist-test-old.desktop"), app("applist-test-new.desktop"), app("other")];
+
+        mark_recently_added(&mut apps, &history, now, 7);
+        let flagged: Vec<bool> = apps.iter().map(|app| app.recently_added).collect();
+        assert_eq!(flagged, vec![false, true, false]);
+        mark_recently_added(&mut apps, &history, now, 1);
+        assert!(!apps[1].recently_added);
+
+        let mut new = app("applist-test-new.desktop");
+        new.recently_added = true;
+        new.description = Some("Draw".to_string());
+        let list = into_entry_list(vec![entry_text(&new, &Config::default())]);
+        let description = unsafe { CStr::from_ptr((*list.entries).description) };
+        assert_eq!(description.to_str().unwrap(), "New · Draw");
+        free_entry_list(list);
+    }
+
+    #[test]
+    fn feature_detection() {
+        assert_eq!(abi_version(), 5);
+        let known = [
+            c"free_entry_list",
+            c"get_entries_for_mime",
+            c"get_entries_highlighted",
+            c"get_entries_implementing",
+            c"entry_startup_wm_class",
+        ];
+        for feature in known {
+            assert!(supports(feature.as_ptr()), "{:?}", feature);
+        }
+        assert!(!supports(c"get_entries_for_mime2".as_ptr()));
+        assert!(!supports(c"".as_ptr()));
+        assert!(!supports(std::ptr::null()));
+    }
+
+    #[test]
+    fn startup_wm_class_is_exposed() {
+        let content = "[Desktop Entry]\nName=Firefox\nExec=firefox %u\nStartupWMClass=firefox\n";
+        let app = parse_desktop_file(content, "/usr/share/applications/firefox.desktop", &[]).unwrap();
+        assert_eq!(app.startup_wm_class.as_deref(), Some("firefox"));
+
+        let list = into_entry_list(vec![entry_text(&app, &Config::default())]);
+        let entry = unsafe { &*list.entries };
+        assert_eq!(unsafe { CStr::from_ptr(entry.startup_wm_class) }.to_str().unwrap(), "firefox");
+        free_entry_list(list);
+
+        let app = parse_desktop_file("[Desktop Entry]\nExec=true\nName=App\n", "app.desktop", &[]).unwrap();
+        let list = into_entry_list(vec![entry_text(&app, &Config::default())]);
+        assert!(unsafe { &*list.entries }.startup_wm_class.is_null());
+        free_entry_list(list);
+    }
+
+    #[test]
+    fn entry_list_round_trip() {
+        let apps: Vec<AppInfo> = (0..3)
+            .map(|i| {
+                let content = format!("[Desktop Entry]\nExec=true\nName=App {}\nComment=Number {}\n", i, i);
+                parse_desktop_file(&content, &format!("/apps/app{}.desktop", i), &[]).unwrap()
+            })
+            .collect();
+        let list = into_entry_list(apps.iter().map(|app| entry_text(app, &Config::default())).collect());
+
+        let entries = unsafe { std::slice::from_raw_parts(list.entries, list.length) };
+        for (i, entry) in entries.iter().enumerate() {
+            let read = |s: *const c_char| unsafe { CStr::from_ptr(s) }.to_str().unwrap().to_string();
+            assert_eq!(read(entry.name), format!("App {}", i));
+            assert_eq!(read(entry.value), format!("/apps/app{}.desktop", i));
+            assert_eq!(read(entry.description), format!("Number {}", i));
+            assert!(entry.icon.is_null());
+        }
+        free_entry_list(list);
+
+        // an empty list is a valid, freeable list too
+        let empty = into_entry_list(Vec::new());
+        assert_eq!(empty.length, 0);
+        free_entry_list(empty);
+    }
+
+    #[test]
+    fn parse_desktop_file_keeps_raw_exec() {
+        let content = "[Desktop Entry]\nName=App\nIcon=app\nExec=\"/opt/My App/app\" %U\n";
+        let app = parse_desktop_file(content, "/usr/share/applications/app.desktop", &[]).unwrap();
+        assert_eq!(app.exec.as_deref(), Some("\"/opt/My App/app\" %U"));
+        assert_eq!(exec::expand_exec(app.exec.as_deref().unwrap(), &[]), vec!["/opt/My App/app"]);
+    }
+
+    const LOCALIZED: &str = "[Desktop Entry]
+Name=Text Editor
+Name[fr]=Éditeur de texte
+Name[fr_CA]=Éditeur de texte (Canada)
+Comment=Edit files
+Comment[fr]=Modifier des fichiers
+Keywords=text;editor;
+Keywords[fr]=texte;éditeur;
+Icon=editor
+Exec=gnome-text-editor
+";
+
+    #[test]
+    fn localized_name_prefers_most_specific_locale() {
+        let app = parse_desktop_file(LOCALIZED, "e.desktop", &locale_candidates("fr_CA.UTF-8")).unwrap();
+        assert_eq!(app.name, "Éditeur de texte (Canada)");
+        assert_eq!(app.description.as_deref(), Some("Modifier des fichiers"));
+
+        // only the region-less localization exists for France
+        let app = parse_desktop_file(LOCALIZED, "e.desktop", &locale_candidates("fr_FR")).unwrap();
+        assert_eq!(app.name, "Éditeur de texte");
+
+        let app = parse_desktop_file(LOCALIZED, "e.desktop", &locale_candidates("fr_FR")).unwrap();
+        assert_eq!(app.keywords, vec!["texte", "éditeur"]);
+
+        let app = parse_desktop_file(LOCALIZED, "e.desktop", &locale_candidates("de_DE")).unwrap();
+        assert_eq!(app.keywords, vec!["text", "editor"]);
+        assert_eq!(app.name, "Text Editor");
+        assert_eq!(app.description.as_deref(), Some("Edit files"));
+    }
+
+    #[test]
+    fn locale_candidates_with_modifier() {
+        assert_eq!(
+            locale_candidates("ca_ES.UTF-8@valencia"),
+            vec!["ca_ES@valencia", "ca_ES", "ca@valencia", "ca"]
+        );
+        assert_eq!(locale_candidates("fr"), vec!["fr"]);
+        assert!(locale_candidates("C.UTF-8").is_empty());
+    }
+
+    #[test]
+    fn desktop_file_without_icon_is_kept() {
+        let content = "[Desktop Entry]\nName=htop\nExec=htop\nTerminal=true\n";
+        let app = parse_desktop_file(content, "htop.desktop", &[]).unwrap();
+        assert_eq!(app.name, "htop");
+        assert!(app.icon.is_none());
+
+        assert!(parse_desktop_file("[Desktop Entry]\nIcon=nameless\n", "x.desktop", &[]).is_none());
+    }
+
+    #[test]
+    fn only_application_type_is_kept() {
+        let link = "[Desktop Entry]\nType=Link\nName=Docs\nIcon=help\nURL=https://example.com\n";
+        assert!(parse_desktop_file(link, "docs.desktop", &[]).is_none());
+        let directory = "[Desktop Entry]\nType=Directory\nName=Games\nIcon=games\n";
+        assert!(parse_desktop_file(directory, "games.directory", &[]).is_none());
+
+        let app = "[Desktop Entry]\nType=Application\nName=App\nExec=app\n";
+        assert!(parse_desktop_file(app, "app.desktop", &[]).is_some());
+        let untyped = "[Desktop Entry]\nName=App\nExec=app\n";
+        assert!(parse_desktop_file(untyped, "app.desktop", &[]).is_some());
+    }
+
+    #[test]
+    fn bom_and_crlf_are_accepted() {
+        let content = "\u{feff}[Desktop Entry]\r\nName=Notepad\r\nComment=Edit text\r\nExec=notepad %f\r\n\
+                       Icon=notepad\r\n\r\n[Desktop Action New]\r\nName=New\r\n";
+        let app = parse_desktop_file(content, "notepad.desktop", &[]).unwrap();
+        assert_eq!(app.name, "Notepad");
+        assert_eq!(app.description.as_deref(), Some("Edit text"));
+        assert_eq!(app.exec.as_deref(), Some("notepad %f"));
+        assert_eq!(app.icon.as_deref(), Some("notepad"));
+    }
+
+    #[test]
+    fn headerless_files_in_lenient_mode() {
+        let headerless = "# by hand\nName=Scratchpad\nExec=scratchpad\n\n[Desktop Action New]\nName=New\n";
+        assert!(parse_desktop_file(headerless, "pad.desktop", &[]).is_none());
+        let app = parse_desktop_file_with(headerless, "pad.desktop", &[], true).unwrap();
+        assert_eq!(app.name, "Scratchpad");
+        assert_eq!(app.exec.as_deref(), Some("scratchpad"));
+
+        // with a header the keys before it are still stray
+        let stray = "Name=Stray\n[Desktop Entry]\nName=Real\nExec=real\n";
+        assert_eq!(parse_desktop_file_with(stray, "real.desktop", &[], true).unwrap().name, "Real");
+
+        let dir = test_util::TestDir::new("lenient");
+        dir.write("applications/applist-test-pad.desktop", headerless);
+        let dirs = [dir.path().join("applications")];
+        let names = |lenient_parsing| -> Vec<String> {
+            let config = Config { lenient_parsing, ..Config::default() };
+            let apps = load_applications_with(&mut cache::Cache::default(), &dirs, &config).unwrap();
+            apps.into_iter().map(|app| app.name).collect()
+        };
+        assert!(names(false).is_empty());
+        assert_eq!(names(true), vec!["Scratchpad"]);
+    }
+
+    #[test]
+    fn nameless_files_named_after_the_file() {
+        let nameless = "[Desktop Entry]\nExec=true\nComment=Quick notes\n";
+        let path = "/apps/org.example.Notes.desktop";
+        assert!(parse_desktop_file(nameless, path, &[]).is_none());
+        let app = parse_desktop_file_with(nameless, path, &[], true).unwrap();
+        assert_eq!(app.name, "org.example.Notes");
+        // searchable by it too
+        let query = matcher::Query::new("notes");
+        assert!(matcher::rank_app(&query, &app, config::MatchMode::Fuzzy, &Default::default()).is_some());
+        // a Name still wins
+        let named = "[Desktop Entry]\nExec=true\nName=Notes\n";
+        assert_eq!(parse_desktop_file_with(named, path, &[], true).unwrap().name, "Notes");
+    }
+
+    #[test]
+    fn apps_without_exec_are_skipped() {
+        let no_exec = "[Desktop Entry]\nType=Application\nName=Broken\nIcon=broken\n";
+        assert!(parse_desktop_file(no_exec, "broken.desktop", &[]).is_none());
+        let empty = "[Desktop Entry]\nName=Broken\nIcon=broken\nExec= \n";
+        assert!(parse_desktop_file(empty, "broken.desktop", &[]).is_none());
+
+        // launched over D-Bus, it needs no command
+        let activatable = "[Desktop Entry]\nName=Files\nIcon=files\nDBusActivatable=true\n";
+        let app = parse_desktop_file(activatable, "org.gnome.Nautilus.desktop", &[]).unwrap();
+        assert!(app.exec.is_none());
+    }
+
+    #[cfg(feature = "pinyin")]
+    #[test]
+    fn chinese_names_found_by_pinyin() {
+        let dir = test_util::TestDir::new("pinyin");
+        dir.write("browser.desktop", "[Desktop Entry]\nName=网页浏览器\nExec=true\n");
+        let names = |pinyin: bool, query: &str| -> Vec<String> {
+            let options = ScanOptions { pinyin, ..ScanOptions::default() };
+            let apps = scan_dir(dir.path(), &options).unwrap().apps;
+            let found = query_apps(&apps, query, &Config::default(), &frecency::Frecency::default(), 0);
+            found.iter().map(|found| found.app.name.clone()).collect()
+        };
+        assert_eq!(names(true, "liulanqi"), vec!["网页浏览器"]);
+        assert_eq!(names(true, "浏览"), vec!["网页浏览器"]);
+        assert!(names(false, "liulanqi").is_empty());
+    }
+
+    #[test]
+    fn vendor_keywords_are_searched() {
+        let dir = test_util::TestDir::new("vendor-keywords");
+        dir.write(
+            "control-center.desktop",
+            "[Desktop Entry]\nName=Control Center\nExec=true\nKeywords=panel;\n\
+             X-GNOME-Keywords=settings;preferences;panel;\nX-KDE-Keywords=kcm;\n\
+             X-Purism-FormFactor=Workstation;Mobile;\n",
+        );
+        let preferences = matcher::Query::new("preferences");
+        let options = ScanOptions {
+            keyword_prefixes: vec!["X-GNOME".to_string()],
+            ..ScanOptions::default()
+        };
+
+        let apps = scan_dir(dir.path(), &options).unwrap().apps;
+        assert_eq!(apps[0].keywords, vec!["panel", "settings", "preferences"]);
+        let rank = |app| matcher::rank_app(&preferences, app, config::MatchMode::Fuzzy, &Default::default());
+        assert!(rank(&apps[0]).is_some());
+        // only the honored prefixes count
+        let apps = scan_dir(dir.path(), &ScanOptions::default()).unwrap().apps;
+        assert_eq!(apps[0].keywords, vec!["panel"]);
+        assert!(rank(&apps[0]).is_none());
+        assert_eq!(Config::default().keyword_prefixes, vec!["X-GNOME", "X-KDE"]);
+
+        // localized like Keywords
+        let content = "[Desktop Entry]\nName=Dolphin\nExec=dolphin\n\
+                       X-KDE-Keywords=files\nX-KDE-Keywords[fr]=fichiers\n";
+        let app = parse_desktop_file(content, "dolphin.desktop", &["fr".to_string()]).unwrap();
+        assert_eq!(app.vendor_keywords, vec![("X-KDE".to_string(), vec!["fichiers".to_string()])]);
+    }
+
+    #[test]
+    fn list_values_with_escaped_semicolons() {
+        assert_eq!(split_desktop_list("a\\;b;c"), vec!["a;b", "c"]);
+        assert_eq!(split_desktop_list("GNOME;GTK;"), vec!["GNOME", "GTK"]);
+        assert_eq!(split_desktop_list(";; one ;two\\;;"), vec!["one", "two;"]);
+        // an escaped backslash doesn't escape the separator after it
+        assert_eq!(split_desktop_list("a\\\\;b"), vec!["a\\", "b"]);
+        assert_eq!(split_desktop_list("new\\stab"), vec!["new tab"]);
+        assert!(split_desktop_list("").is_empty());
+
+        let content = "[Desktop Entry]\nName=App\nExec=app\nKeywords=a\\;b;c\nCategories=Utility\\;Tool;\n";
+        let app = parse_desktop_file(content, "app.desktop", &[]).unwrap();
+        assert_eq!(app.keywords, vec!["a;b", "c"]);
+        assert_eq!(app.categories, vec!["Utility;Tool"]);
+    }
+
+    #[test]
+    fn only_show_in_with_multiple_desktops() {
+        let content = "[Desktop Entry]\nExec=true\nName=Settings\nIcon=s\nOnlyShowIn=GNOME;Unity;\n";
+        let app = parse_desktop_file(content, "settings.desktop", &[]).unwrap();
+        assert_eq!(app.only_show_in, vec!["GNOME", "Unity"]);
+
+        assert!(is_shown_in(&app, &parse_current_desktops(Some("ubuntu:GNOME"))));
+        assert!(!is_shown_in(&app, &parse_current_desktops(Some("sway:wlroots"))));
+        assert!(is_shown_in(&app, &parse_current_desktops(None)));
+    }
+
+    #[test]
+    fn not_show_in_with_multiple_desktops() {
+        let content = "[Desktop Entry]\nExec=true\nName=Panel\nIcon=p\nNotShowIn=KDE;XFCE;\n";
+        let app = parse_desktop_file(content, "panel.desktop", &[]).unwrap();
+        assert_eq!(app.not_show_in, vec!["KDE", "XFCE"]);
+
+        assert!(!is_shown_in(&app, &parse_current_desktops(Some("X-Generic:XFCE"))));
+        assert!(is_shown_in(&app, &parse_current_desktops(Some("Hyprland"))));
+        assert!(is_shown_in(&app, &parse_current_desktops(Some(""))));
+    }
+
+    #[test]
+    fn first_occurrence_of_a_key_wins() {
+        let content = "[Desktop Entry]\nName=First\nExec=first\nName=Second\nName[fr]=Premier\n\
+                       Name[fr]=Deuxième\nExec=second\nNoDisplay=false\nNoDisplay=true\n";
+        let app = parse_desktop_file(content, "dup.desktop", &["fr".to_string()]).unwrap();
+        assert_eq!(app.name, "Premier");
+        assert_eq!(app.exec.as_deref(), Some("first"));
+        assert!(!app.no_display);
+        let app = parse_desktop_file(content, "dup.desktop", &[]).unwrap();
+        assert_eq!(app.name, "First");
+        // keys of other groups don't count
+        let content = "[Desktop Action new]\nName=New Window\n[Desktop Entry]\nName=Files\nExec=nautilus\n";
+        assert_eq!(parse_desktop_file(content, "dup.desktop", &[]).unwrap().name, "Files");
+    }
+
+    #[test]
+    fn show_in_quirks() {
+        let app = |keys: &str| {
+            let content = format!("[Desktop Entry]\nExec=true\nName=App\n{}\n", keys);
+            parse_desktop_file(&content, "app.desktop", &[]).unwrap()
+        };
+        let gnome = parse_current_desktops(Some("ubuntu:GNOME"));
+        let kde = parse_current_desktops(Some("KDE"));
+
+        let trailing = app("OnlyShowIn=GNOME;");
+        assert_eq!(trailing.only_show_in, vec!["GNOME"]);
+        assert!(is_shown_in(&trailing, &gnome) && !is_shown_in(&trailing, &kde));
+        let spaced = app("OnlyShowIn= gnome ;;XFCE;");
+        assert_eq!(spaced.only_show_in, vec!["gnome", "XFCE"]);
+        assert!(is_shown_in(&spaced, &gnome) && !is_shown_in(&spaced, &kde));
+        let lowercase = app("NotShowIn=kde;");
+        assert!(!is_shown_in(&lowercase, &kde) && is_shown_in(&lowercase, &gnome));
+        // no desktop left in the list, shown everywhere
+        let empty = app("OnlyShowIn=;;");
+        assert!(empty.only_show_in.is_empty());
+        assert!(is_shown_in(&empty, &kde));
     }
-    
 }
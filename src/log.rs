@@ -0,0 +1,69 @@
+//! Routing of the plugin's log messages to the host.
+//!
+//! The host can register a callback with `set_log_callback`; until it does,
+//! messages go to stderr so they don't end up in the host's stdout.
+
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+use std::sync::RwLock;
+
+/// Log levels passed to the callback, lower is more severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub enum Level {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+    Debug = 3,
+}
+
+impl Level {
+    fn label(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+}
+
+pub type LogCallback = extern "C" fn(level: c_int, msg: *const c_char);
+
+// read for every message, from any thread, written by `set_log_callback`
+static CALLBACK: RwLock<Option<LogCallback>> = RwLock::new(None);
+
+pub(crate) fn set_callback(callback: Option<LogCallback>) {
+    *CALLBACK.write().unwrap_or_else(|e| e.into_inner()) = callback;
+}
+
+/// Sends a message to the registered callback, or stderr without one.
+///
+/// The message pointer is only valid for the duration of the callback.
+pub(crate) fn log(level: Level, message: &str) {
+    // copied out, a callback logging again doesn't wait for itself
+    let callback = *CALLBACK.read().unwrap_or_else(|e| e.into_inner());
+    match callback {
+        Some(callback) => {
+            let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+            callback(level as c_int, message.as_ptr());
+        }
+        None => eprintln!("[applist] {}: {}", level.label(), message),
+    }
+}
+
+macro_rules! log_error {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Error, &format!($($arg)*)) };
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Warn, &format!($($arg)*)) };
+}
+
+macro_rules! log_info {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Info, &format!($($arg)*)) };
+}
+
+macro_rules! log_debug {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Debug, &format!($($arg)*)) };
+}
@@ -0,0 +1,981 @@
+//! Fuzzy matching of queries against entry names and other searchable fields.
+//!
+//! The scorer is a small fzf-style aligner: every query character has to be
+//! found in the candidate in order, matches on word boundaries and runs of
+//! consecutive matches are rewarded, and gaps between matches cost points.
+//! A dynamic programming pass picks the best alignment instead of the first.
+
+use crate::AppInfo;
+use crate::config::{MatchMode, Weights};
+use serde::Serialize;
+use unicode_normalization::char::{decompose_canonical, is_combining_mark};
+
+const SCORE_MATCH: i64 = 16;
+const GAP_START: i64 = -3;
+const GAP_EXTENSION: i64 = -1;
+const BONUS_BOUNDARY: i64 = 8;
+const BONUS_CAMEL: i64 = 7;
+const BONUS_CONSECUTIVE: i64 = 4;
+// the first query character says the most about what the user meant
+const FIRST_CHAR_MULTIPLIER: i64 = 2;
+
+// anything below this is "no alignment", low enough to never win a max
+const NONE: i64 = i64::MIN / 2;
+
+// `rank` orders by tier first, then fuzzy score, then length; the scales keep
+// each criterion from overflowing into the one above it
+const TIER_SCALE: i64 = 1 << 40;
+const SCORE_SCALE: i64 = 1 << 10;
+const MAX_LENGTH_PENALTY: usize = (1 << 10) - 1;
+
+/// Lowercases `c` and strips its diacritics, so "É" and "e" compare equal.
+///
+/// Always one char for one, the scorer relies on folded text lining up with
+/// the original. Lowercasings producing several chars keep their base letter
+/// (`İ` gives `i`, its dot is a combining mark).
+fn fold(c: char) -> char {
+    let lower = c.to_lowercase().next().unwrap_or(c);
+    // the first char of a canonical decomposition is the base letter
+    let mut base = None;
+    decompose_canonical(lower, |d| {
+        base.get_or_insert(d);
+    });
+    match base.unwrap_or(lower) {
+        // the final form depends on the position in the word, not on the letter
+        'ς' => 'σ',
+        base => base,
+    }
+}
+
+/// The chars of `s` that take part in matching. Combining marks are dropped
+/// so text that is already decomposed folds like the precomposed form.
+fn chars(s: &str) -> impl Iterator<Item = char> + '_ {
+    s.chars().filter(|&c| !is_combining_mark(c))
+}
+
+/// The form text is compared in when searching: lowercased, accents and
+/// other combining marks removed.
+///
+/// This doesn't depend on the locale: `I` always gives `i` and Turkish
+/// dotless `ı` stays distinct from `i`. Nothing expands either, so `ẞ` and
+/// `ß` give `ß`, which "ss" doesn't match.
+pub(crate) fn normalize_for_search(s: &str) -> String {
+    chars(s).map(fold).collect()
+}
+
+/// A searchable string, folded once so every query doesn't redo it.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Key {
+    // the matching chars as written, for the case-based bonuses
+    original: Vec<char>,
+    folded: Vec<char>,
+    normalized: String,
+}
+
+impl Key {
+    pub(crate) fn new(s: &str) -> Self {
+        let original = chars(s).collect();
+        let normalized = normalize_for_search(s);
+        let folded = normalized.chars().collect();
+        Key { original, folded, normalized }
+    }
+}
+
+/// One term of a query, folded once per search.
+#[derive(Debug, Clone)]
+struct Term {
+    // without whitespace, spaces in a phrase don't have to match
+    folded: Vec<char>,
+    // trimmed, for the whole-name tiers
+    normalized: String,
+    // quoted in the query, it only matches text containing it as written
+    phrase: bool,
+}
+
+impl Term {
+    fn new(term: &str, phrase: bool) -> Self {
+        let normalized = normalize_for_search(term.trim());
+        let folded = normalized.chars().filter(|c| !c.is_whitespace()).collect();
+        Term { folded, normalized, phrase }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.folded.is_empty()
+    }
+}
+
+/// A folded query, built once per search.
+///
+/// Whitespace separates terms that all have to match, each in any field, so
+/// "fire dev" finds "Firefox Developer Edition". Double quotes keep a phrase
+/// like `"web browser"` together as a single term.
+#[derive(Debug, Clone)]
+pub(crate) struct Query {
+    terms: Vec<Term>,
+}
+
+impl Query {
+    pub(crate) fn new(query: &str) -> Self {
+        let terms = split_terms(query)
+            .into_iter()
+            .map(|(term, phrase)| Term::new(&term, phrase))
+            .filter(|term| !term.is_empty())
+            .collect();
+        Query { terms }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+}
+
+/// Splits a query on whitespace outside of double quotes, flagging the
+/// quoted parts. A quote left open runs to the end of the query.
+fn split_terms(query: &str) -> Vec<(String, bool)> {
+    let mut terms = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in query.chars() {
+        if c == '"' || (!quoted && c.is_whitespace()) {
+            if !current.is_empty() {
+                terms.push((std::mem::take(&mut current), quoted));
+            }
+            if c == '"' {
+                quoted = !quoted;
+            }
+        } else {
+            current.push(c);
+        }
+    }
+    if !current.is_empty() {
+        terms.push((current, quoted));
+    }
+    terms
+}
+
+/// The keys of every searchable field of an app.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct SearchKeys {
+    name: Key,
+    generic_name: Option<Key>,
+    keywords: Vec<Key>,
+    comment: Option<Key>,
+    // the program Exec runs, e.g. `chromium`
+    command: Option<Key>,
+    // the desktop ID without `.desktop`, e.g. `org.mozilla.firefox`
+    id: Key,
+}
+
+impl SearchKeys {
+    pub(crate) fn new(app: &AppInfo) -> Self {
+        SearchKeys {
+            name: Key::new(&app.name),
+            generic_name: app.generic_name.as_deref().map(Key::new),
+            keywords: app.keywords.iter().map(|keyword| Key::new(keyword)).collect(),
+            comment: app.description.as_deref().map(Key::new),
+            command: app
+                .exec
+                .as_deref()
+                .and_then(crate::exec::program_name)
+                .map(|program| Key::new(&program)),
+            id: Key::new(app.id.strip_suffix(".desktop").unwrap_or(&app.id)),
+        }
+    }
+}
+
+/// Bonus for matching the character at `index`, based on its neighbour.
+fn position_bonus(chars: &[char], index: usize) -> i64 {
+    let Some(prev) = index.checked_sub(1).map(|i| chars[i]) else {
+        return BONUS_BOUNDARY;
+    };
+    let current = chars[index];
+    if !prev.is_alphanumeric() && current.is_alphanumeric() {
+        BONUS_BOUNDARY
+    } else if prev.is_lowercase() && current.is_uppercase() {
+        BONUS_CAMEL
+    } else {
+        0
+    }
+}
+
+/// Scores `candidate` against `query`, ignoring case and diacritics.
+///
+/// Returns `None` when the query isn't a subsequence of the candidate. Higher
+/// scores are better matches, an empty query matches everything with 0.
+fn score_key(query: &Term, candidate: &Key) -> Option<i64> {
+    let query = &query.folded;
+    if query.is_empty() {
+        return Some(0);
+    }
+    if query.len() > candidate.folded.len() {
+        return None;
+    }
+
+    // prev[j]: best score with the previous query char matched at j
+    let mut prev = vec![NONE; candidate.folded.len()];
+    let mut current = vec![NONE; candidate.folded.len()];
+
+    for (i, &q) in query.iter().enumerate() {
+        align_row(i, q, candidate, &prev, &mut current);
+        std::mem::swap(&mut prev, &mut current);
+    }
+
+    prev.into_iter().filter(|&s| s > NONE).max()
+}
+
+/// One step of the alignment: fills `current[j]` with the best score of
+/// query char `i`, `q`, matched at `j`, given the scores of char `i - 1` in
+/// `prev`.
+fn align_row(i: usize, q: char, candidate: &Key, prev: &[i64], current: &mut [i64]) {
+    let original = &candidate.original;
+    let folded = &candidate.folded;
+    // best alignment ending with a gap of at least one char before j
+    let mut gap = NONE;
+    for j in 0..folded.len() {
+        if j >= 2 {
+            gap = (prev[j - 2] + GAP_START).max(gap + GAP_EXTENSION).max(NONE);
+        }
+        current[j] = if folded[j] != q {
+            NONE
+        } else if i == 0 {
+            SCORE_MATCH + position_bonus(original, j) * FIRST_CHAR_MULTIPLIER
+        } else {
+            let consecutive = match j.checked_sub(1).map(|k| prev[k]) {
+                Some(score) if score > NONE => score + BONUS_CONSECUTIVE,
+                _ => NONE,
+            };
+            let best = consecutive.max(gap);
+            if best <= NONE {
+                NONE
+            } else {
+                best + SCORE_MATCH + position_bonus(original, j)
+            }
+        };
+    }
+}
+
+/// The positions in `candidate` of the chars of the best alignment found by
+/// `score_key`, as indexes into its matching chars.
+fn align_positions(query: &Term, candidate: &Key) -> Option<Vec<usize>> {
+    let query = &query.folded;
+    let length = candidate.folded.len();
+    if query.is_empty() || query.len() > length {
+        return None;
+    }
+    // every row is kept to trace the alignment back
+    let mut rows = vec![vec![NONE; length]];
+    for (i, &q) in query.iter().enumerate() {
+        let mut row = vec![NONE; length];
+        align_row(i, q, candidate, &rows[i], &mut row);
+        rows.push(row);
+    }
+
+    let last = &rows[query.len()];
+    let mut j = (0..length).filter(|&j| last[j] > NONE).max_by_key(|&j| last[j])?;
+    let mut positions = vec![j];
+    for i in (1..query.len()).rev() {
+        // what char i added on top of where char i - 1 was matched
+        let before = rows[i + 1][j] - SCORE_MATCH - position_bonus(&candidate.original, j);
+        let prev = &rows[i];
+        let consecutive = j >= 1 && prev[j - 1] > NONE && prev[j - 1] + BONUS_CONSECUTIVE == before;
+        j = if consecutive {
+            j - 1
+        } else {
+            (0..j.saturating_sub(1)).rev().find(|&k| {
+                prev[k] > NONE && prev[k] + GAP_START + (j - 2 - k) as i64 * GAP_EXTENSION == before
+            })?
+        };
+        positions.push(j);
+    }
+    positions.reverse();
+    Some(positions)
+}
+
+/// How the query lines up with the whole name, best first.
+fn tier_key(query: &Term, name: &Key) -> i64 {
+    let query = &query.normalized;
+    let name = &name.normalized;
+    if name == query {
+        3
+    } else if name.starts_with(query.as_str()) {
+        2
+    } else if name
+        .split(|c: char| !c.is_alphanumeric())
+        .any(|word| word.starts_with(query.as_str()))
+    {
+        1
+    } else {
+        0
+    }
+}
+
+/// Ranks a name for sorting results, higher first.
+///
+/// Exact matches come first, then names starting with the query, then names
+/// with a word starting with it, then any other fuzzy match. Within a tier the
+/// fuzzy score decides, and shorter names win remaining ties. An empty query
+/// ranks everything equally so the caller's order is kept.
+fn rank_key(query: &Term, name: &Key) -> Option<i64> {
+    let score = score_key(query, name)?;
+    if query.is_empty() {
+        return Some(0);
+    }
+    let length = name.original.len().min(MAX_LENGTH_PENALTY) as i64;
+    Some(tier_key(query, name) * TIER_SCALE + score * SCORE_SCALE - length)
+}
+
+// string versions for the tests
+#[cfg(test)]
+fn score(query: &str, candidate: &str) -> Option<i64> {
+    score_key(&Term::new(query, false), &Key::new(candidate))
+}
+
+#[cfg(test)]
+fn tier(query: &str, name: &str) -> i64 {
+    tier_key(&Term::new(query, false), &Key::new(name))
+}
+
+#[cfg(test)]
+fn rank(query: &str, name: &str) -> Option<i64> {
+    rank_key(&Term::new(query, false), &Key::new(name))
+}
+
+// at most half a tier, so launches reorder matches of similar quality without
+// lifting a poor match above a much better one
+const MAX_FRECENCY: f64 = 20.0;
+const FRECENCY_SCALE: f64 = (TIER_SCALE / 2) as f64 / MAX_FRECENCY;
+
+/// Converts a decayed launch count into a bonus added to an app's rank.
+pub(crate) fn frecency_bonus(frecency: f64, weights: &Weights) -> i64 {
+    weighted((frecency.clamp(0.0, MAX_FRECENCY) * FRECENCY_SCALE) as i64, weights.frecency)
+}
+
+/// Whether `mode` lets `candidate` match `query` at all, fuzzy ranking then
+/// orders the candidates it lets through. Phrases match the same way in
+/// every mode.
+fn accepts(mode: MatchMode, query: &Term, candidate: &Key) -> bool {
+    if query.phrase {
+        return candidate.normalized.contains(query.normalized.as_str());
+    }
+    match mode {
+        MatchMode::Fuzzy => true,
+        // the name or one of its words starts with the query
+        MatchMode::Prefix => tier_key(query, candidate) >= 1,
+        MatchMode::Substring => candidate.normalized.contains(query.normalized.as_str()),
+    }
+}
+
+/// `rank_key`, restricted to the candidates `mode` accepts.
+fn rank_with(mode: MatchMode, query: &Term, candidate: &Key) -> Option<i64> {
+    if !query.is_empty() && !accepts(mode, query, candidate) {
+        return None;
+    }
+    rank_key(query, candidate)
+}
+
+/// `rank` scaled by `weight` percent, clamped to the range `Weights` allows.
+fn weighted(rank: i64, weight: i64) -> i64 {
+    rank * weight.clamp(0, Weights::MAX) / 100
+}
+
+/// The weighted rank of each searchable field of an app, `None` for fields
+/// that don't match or that the app doesn't have. With several terms, a
+/// field adds up the ranks of the terms it matches.
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct Breakdown {
+    pub name: Option<i64>,
+    pub generic_name: Option<i64>,
+    /// The best of the keywords.
+    pub keywords: Option<i64>,
+    pub comment: Option<i64>,
+    /// The program `Exec` runs.
+    pub exec: Option<i64>,
+    /// The desktop ID.
+    pub id: Option<i64>,
+    // the rank of the app, `None` unless every term matched
+    #[serde(skip)]
+    total: Option<i64>,
+}
+
+impl Breakdown {
+    /// The rank of the app: for each term its best field, added up.
+    pub(crate) fn best(&self) -> Option<i64> {
+        self.total
+    }
+
+    fn best_field(&self) -> Option<i64> {
+        [self.name, self.generic_name, self.keywords, self.comment, self.exec, self.id]
+            .into_iter()
+            .flatten()
+            .max()
+    }
+
+    /// Adds the ranks of another term.
+    fn add(&mut self, term: Breakdown) {
+        let add = |total: &mut Option<i64>, rank: Option<i64>| {
+            *total = match (*total, rank) {
+                (Some(total), Some(rank)) => Some(total + rank),
+                (total, rank) => total.or(rank),
+            };
+        };
+        add(&mut self.name, term.name);
+        add(&mut self.generic_name, term.generic_name);
+        add(&mut self.keywords, term.keywords);
+        add(&mut self.comment, term.comment);
+        add(&mut self.exec, term.exec);
+        add(&mut self.id, term.id);
+        self.total = self.total.zip(term.total).map(|(total, rank)| total + rank);
+    }
+}
+
+/// Ranks each searchable field of an application, scaled by how much a match
+/// in that field is worth compared to the name, see `Weights`. Every field is
+/// matched according to `mode`.
+pub(crate) fn explain(query: &Query, app: &AppInfo, mode: MatchMode, weights: &Weights) -> Breakdown {
+    let Some((first, rest)) = query.terms.split_first() else {
+        return explain_term(&Term::new("", false), app, mode, weights);
+    };
+    let mut breakdown = explain_term(first, app, mode, weights);
+    for term in rest {
+        breakdown.add(explain_term(term, app, mode, weights));
+    }
+    breakdown
+}
+
+fn explain_term(term: &Term, app: &AppInfo, mode: MatchMode, weights: &Weights) -> Breakdown {
+    let keys = &app.search;
+    // fields weighing nothing aren't searched, but the empty query still
+    // lists every app whatever the weights
+    let rank = |candidate: &Key, weight: i64| {
+        (weight > 0 || term.folded.is_empty())
+            .then(|| rank_with(mode, term, candidate))
+            .flatten()
+            .map(|r| weighted(r, weight))
+    };
+    let mut breakdown = Breakdown {
+        name: rank(&keys.name, weights.name),
+        generic_name: keys.generic_name.as_ref().and_then(|key| rank(key, weights.generic_name)),
+        keywords: keys.keywords.iter().filter_map(|key| rank(key, weights.keywords)).max(),
+        comment: keys.comment.as_ref().and_then(|key| rank(key, weights.comment)),
+        exec: keys.command.as_ref().and_then(|key| rank(key, weights.exec)),
+        id: rank(&keys.id, weights.id),
+        total: None,
+    };
+    breakdown.total = breakdown.best_field();
+    breakdown
+}
+
+/// The byte ranges of `app`'s name matched by `query`, for hosts bolding
+/// them. Adjacent chars are merged into one range, and the ranges always fall
+/// on char boundaries of the name as written.
+///
+/// Only the name is highlighted, terms matching other fields add nothing.
+pub(crate) fn highlight(query: &Query, app: &AppInfo, mode: MatchMode) -> Vec<(usize, usize)> {
+    let name = &app.search.name;
+    let mut positions: Vec<usize> = Vec::new();
+    for term in &query.terms {
+        if !accepts(mode, term, name) {
+            continue;
+        }
+        if term.phrase || mode == MatchMode::Substring {
+            // the occurrence itself rather than a fuzzy alignment
+            if let Some(start) = name.normalized.find(term.normalized.as_str()) {
+                let start = name.normalized[..start].chars().count();
+                positions.extend(start..start + term.normalized.chars().count());
+            }
+        } else if let Some(aligned) = align_positions(term, name) {
+            positions.extend(aligned);
+        }
+    }
+    positions.sort_unstable();
+    positions.dedup();
+    byte_ranges(&app.name, &positions)
+}
+
+/// Converts indexes into the matching chars of `text` (see `chars`) into
+/// byte ranges of `text`. Combining marks stay with the char before them.
+fn byte_ranges(text: &str, positions: &[usize]) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    let mut index = 0;
+    let mut highlighted = false;
+    for (start, c) in text.char_indices() {
+        let end = start + c.len_utf8();
+        if !is_combining_mark(c) {
+            highlighted = positions.binary_search(&index).is_ok();
+            index += 1;
+            if !highlighted {
+                continue;
+            }
+        } else if !highlighted {
+            continue;
+        }
+        match ranges.last_mut() {
+            Some(last) if last.1 == start => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+    ranges
+}
+
+/// Queries shorter than this are never taken for typos, one edit away from
+/// them is too much of the alphabet.
+const MIN_TYPO_QUERY_LENGTH: usize = 4;
+
+/// The fewest edits turning `query` into `app`'s name or one of its words,
+/// when that's at most `max`. Inserting, deleting or replacing a char counts
+/// as one edit, and so does swapping two neighbours, "fierfox" is one edit
+/// away from "Firefox".
+pub(crate) fn typo_distance(query: &Query, app: &AppInfo, max: usize) -> Option<usize> {
+    let terms: Vec<&str> = query.terms.iter().map(|term| term.normalized.as_str()).collect();
+    let query: Vec<char> = terms.join(" ").chars().collect();
+    if query.len() < MIN_TYPO_QUERY_LENGTH {
+        return None;
+    }
+    let name = &app.search.name.normalized;
+    std::iter::once(name.as_str())
+        .chain(name.split_whitespace())
+        .filter_map(|candidate| edit_distance(&query, &candidate.chars().collect::<Vec<_>>(), max))
+        .min()
+}
+
+/// The optimal string alignment distance between `a` and `b`, `None` once
+/// it's over `max`.
+fn edit_distance(a: &[char], b: &[char], max: usize) -> Option<usize> {
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+    // the rows for the prefixes of `a` one and two chars shorter
+    let mut before: Vec<usize> = Vec::new();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (previous[j] + 1).min(row[j - 1] + 1).min(previous[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(before[j - 2] + 1);
+            }
+        }
+        // every alignment goes through this row
+        if row.iter().min().is_some_and(|&min| min > max) {
+            return None;
+        }
+        before = std::mem::replace(&mut previous, row);
+    }
+    Some(previous[b.len()]).filter(|&distance| distance <= max)
+}
+
+/// Ranks an application by its searchable fields, see `explain`.
+pub(crate) fn rank_app(query: &Query, app: &AppInfo, mode: MatchMode, weights: &Weights) -> Option<i64> {
+    explain(query, app, mode, weights).best()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_desktop_file;
+
+    #[test]
+    fn matches_with_gaps() {
+        assert!(score("frfx", "Firefox").is_some());
+        assert!(score("gt", "GNOME Terminal").is_some());
+        assert!(score("fz", "Firefox").is_none());
+    }
+
+    #[test]
+    fn typos_within_the_distance() {
+        let firefox = app("[Desktop Entry]\nName=Firefox Web Browser\nExec=firefox\n");
+        let distance = |query: &str, max| typo_distance(&Query::new(query), &firefox, max);
+        assert_eq!(distance("fierfox", 1), Some(1));
+        assert_eq!(distance("firefx", 1), Some(1));
+        assert_eq!(distance("fireofx browser", 2), None);
+        assert_eq!(distance("firefox web browsr", 1), Some(1));
+        assert_eq!(distance("frefxo", 1), None);
+        assert_eq!(distance("frefxo", 2), Some(2));
+        // too short to tell a typo from another word
+        assert_eq!(distance("web", 1), None);
+    }
+
+    #[test]
+    fn transpositions_do_not_match() {
+        // the order of the query characters matters
+        assert!(score("ifrefox", "Firefox").is_none());
+        assert!(score("xof", "Firefox").is_none());
+    }
+
+    #[test]
+    fn case_insensitive() {
+        assert_eq!(score("firefox", "Firefox"), score("FIREFOX", "firefox"));
+        assert!(score("GIMP", "gimp").is_some());
+    }
+
+    #[test]
+    fn tighter_matches_score_higher() {
+        let contiguous = score("term", "Terminal").unwrap();
+        let scattered = score("term", "The Remote Manager").unwrap();
+        assert!(contiguous > scattered);
+
+        let boundary = score("t", "GNOME Terminal").unwrap();
+        let inner = score("t", "Kate").unwrap();
+        assert!(boundary > inner);
+    }
+
+    #[test]
+    fn empty_query_matches_everything() {
+        assert_eq!(score("", "Anything"), Some(0));
+        assert_eq!(rank("", "Anything"), rank("", "Else"));
+    }
+
+    fn ranked<'a>(query: &str, names: &[&'a str]) -> Vec<&'a str> {
+        let mut names: Vec<_> = names
+            .iter()
+            .filter_map(|&name| rank(query, name).map(|rank| (rank, name)))
+            .collect();
+        names.sort_by_key(|(rank, _)| std::cmp::Reverse(*rank));
+        names.into_iter().map(|(_, name)| name).collect()
+    }
+
+    #[test]
+    fn prefix_ranks_above_mid_word() {
+        assert_eq!(
+            ranked("term", &["X Terminal Emulator Settings", "Terminal"]),
+            vec!["Terminal", "X Terminal Emulator Settings"]
+        );
+        assert_eq!(
+            ranked("term", &["Determine", "GNOME Terminal"]),
+            vec!["GNOME Terminal", "Determine"]
+        );
+    }
+
+    #[test]
+    fn exact_match_ranks_first() {
+        assert_eq!(ranked("files", &["Files Manager", "Files"]), vec!["Files", "Files Manager"]);
+    }
+
+    fn app(content: &str) -> AppInfo {
+        parse_desktop_file(content, "app.desktop", &[]).unwrap()
+    }
+
+    fn fuzzy(query: &str, app: &AppInfo) -> Option<i64> {
+        rank_app(&Query::new(query), app, MatchMode::Fuzzy, &Weights::default())
+    }
+
+    #[test]
+    fn configured_weights() {
+        let drawing = app("[Desktop Entry]\nExec=true\nName=Drawing\n");
+        let sketch = app("[Desktop Entry]\nExec=true\nName=Sketch\nKeywords=drawing;paint;\n");
+        let query = Query::new("drawing");
+        let rank = |app, weights: &Weights| rank_app(&query, app, MatchMode::Fuzzy, weights).unwrap();
+
+        let defaults = Weights::default();
+        assert!(rank(&drawing, &defaults) > rank(&sketch, &defaults));
+        let keywords_first = Weights { keywords: 150, ..defaults };
+        assert!(rank(&sketch, &keywords_first) > rank(&drawing, &keywords_first));
+
+        // out of range weights are clamped, a field weighing nothing isn't searched
+        let negative = Weights { name: -40, ..defaults };
+        assert_eq!(rank_app(&query, &drawing, MatchMode::Fuzzy, &negative), None);
+        let unnamed = Weights { name: 0, ..defaults };
+        assert_eq!(rank_app(&query, &drawing, MatchMode::Fuzzy, &unnamed), None);
+        assert!(rank(&sketch, &unnamed) > 0);
+        assert_eq!(rank_app(&Query::new(""), &drawing, MatchMode::Fuzzy, &unnamed), Some(0));
+        let huge = Weights { name: i64::MAX, ..defaults };
+        assert_eq!(rank(&drawing, &huge), rank(&drawing, &Weights { name: Weights::MAX, ..defaults }));
+        assert_eq!(frecency_bonus(5.0, &Weights { frecency: -1, ..defaults }), 0);
+        let doubled = Weights { frecency: 200, ..defaults };
+        assert_eq!(frecency_bonus(5.0, &doubled), 2 * frecency_bonus(5.0, &defaults));
+    }
+
+    #[test]
+    fn matches_the_desktop_id() {
+        let content = "[Desktop Entry]\nExec=firefox\nName=Firefox\n";
+        let firefox = parse_desktop_file(content, "/opt/org.mozilla.firefox.desktop", &[]).unwrap();
+        assert!(fuzzy("mozilla", &firefox).is_some());
+        assert!(fuzzy("org.mozilla", &firefox).is_some());
+        assert!(fuzzy("desktop", &firefox).is_none());
+        // below any name match
+        assert!(fuzzy("mozilla", &firefox) < fuzzy("firefox", &firefox));
+        let breakdown = explain(&Query::new("mozilla"), &firefox, MatchMode::Fuzzy, &Weights::default());
+        assert!(breakdown.name.is_none() && breakdown.id.is_some());
+        let off = Weights { id: 0, ..Weights::default() };
+        assert_eq!(rank_app(&Query::new("mozilla"), &firefox, MatchMode::Fuzzy, &off), None);
+    }
+
+    #[test]
+    fn matches_comment_when_name_does_not() {
+        let epiphany = app("[Desktop Entry]\nExec=true\nName=Epiphany\nComment=Web browser\n");
+        let webcam = app("[Desktop Entry]\nExec=true\nName=Webcam\nComment=Take pictures\n");
+        let no_comment = app("[Desktop Entry]\nExec=true\nName=Calculator\n");
+
+        assert!(fuzzy("web", &epiphany).is_some());
+        assert!(fuzzy("web", &no_comment).is_none());
+        // a name match is still worth more than the same match in a comment
+        assert!(fuzzy("web", &webcam) > fuzzy("web", &epiphany));
+        let no_comments = Weights { comment: 0, ..Weights::default() };
+        assert_eq!(rank_app(&Query::new("web"), &epiphany, MatchMode::Fuzzy, &no_comments), None);
+    }
+
+    #[test]
+    fn highlighted_ranges() {
+        let ranges = |query: &str, name: &str, mode: MatchMode| {
+            let app = app(&format!("[Desktop Entry]\nExec=true\nName={}\n", name));
+            highlight(&Query::new(query), &app, mode)
+        };
+        let fuzzy = |query: &str, name: &str| ranges(query, name, MatchMode::Fuzzy);
+
+        assert_eq!(fuzzy("ff", "Firefox"), vec![(0, 1), (4, 5)]);
+        assert_eq!(fuzzy("fire", "Firefox"), vec![(0, 4)]);
+        // the best alignment, on the word boundary, not the first one found
+        assert_eq!(fuzzy("t", "Kate Terminal"), vec![(5, 6)]);
+        assert_eq!(fuzzy("gt", "GNOME Terminal"), vec![(0, 1), (6, 7)]);
+        // byte offsets in the name as written, accents included
+        assert_eq!(fuzzy("tele", "Téléphone"), vec![(0, 6)]);
+        let decomposed = "Te\u{301}le\u{301}phone";
+        assert_eq!(fuzzy("tele", decomposed), vec![(0, 8)]);
+        assert!(decomposed.is_char_boundary(8));
+        // every term, and a phrase where it appears
+        assert_eq!(fuzzy("gnome term", "GNOME Terminal"), vec![(0, 5), (6, 10)]);
+        assert_eq!(fuzzy(r#""e t""#, "GNOME Terminal"), vec![(4, 7)]);
+        assert_eq!(ranges("min", "Terminal", MatchMode::Substring), vec![(3, 6)]);
+        assert!(fuzzy("zz", "Firefox").is_empty());
+        assert!(fuzzy("", "Firefox").is_empty());
+
+        // the traced positions score like the alignment score_key found, by
+        // the rules of `align_row`
+        let alignment_score = |key: &Key, positions: &[usize]| -> i64 {
+            let mut previous: Option<usize> = None;
+            let mut total = 0;
+            for &j in positions {
+                let bonus = position_bonus(&key.original, j);
+                total += SCORE_MATCH
+                    + match previous {
+                        None => bonus * FIRST_CHAR_MULTIPLIER,
+                        Some(k) if k + 1 == j => BONUS_CONSECUTIVE + bonus,
+                        Some(k) => GAP_START + (j - k - 2) as i64 * GAP_EXTENSION + bonus,
+                    };
+                previous = Some(j);
+            }
+            total
+        };
+        for (query, name) in [("trml", "The Terminal"), ("aaa", "banana bar"), ("ox", "Firefox Box")] {
+            let term = Term::new(query, false);
+            let key = Key::new(name);
+            let positions = align_positions(&term, &key).unwrap();
+            assert_eq!(positions.len(), query.len());
+            assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+            assert!(positions.iter().zip(query.chars()).all(|(&j, q)| key.folded[j] == q));
+            assert_eq!(Some(alignment_score(&key, &positions)), score_key(&term, &key), "{}", query);
+        }
+    }
+
+    #[test]
+    fn query_terms_and_phrases() {
+        let terms = |query: &str| split_terms(query);
+        assert_eq!(terms("  fire   dev "), vec![("fire".to_string(), false), ("dev".to_string(), false)]);
+        assert_eq!(
+            terms(r#"open "web browser"x"#),
+            vec![("open".to_string(), false), ("web browser".to_string(), true), ("x".to_string(), false)]
+        );
+        assert_eq!(terms(r#""unterminated phrase"#), vec![("unterminated phrase".to_string(), true)]);
+        assert!(Query::new(r#" "" "  " "#).is_empty());
+
+        let apps = [
+            app("[Desktop Entry]\nExec=true\nName=Firefox\nGenericName=Web Browser\n"),
+            app("[Desktop Entry]\nExec=true\nName=Web\nKeywords=browser;\n"),
+            app("[Desktop Entry]\nExec=true\nName=Webcam\nComment=Take pictures\n"),
+            app("[Desktop Entry]\nExec=true\nName=Firefox Developer Edition\n"),
+        ];
+        let matching = |query: &str| -> Vec<&str> {
+            let mut ranked: Vec<(i64, &str)> = apps
+                .iter()
+                .filter_map(|app| fuzzy(query, app).map(|rank| (rank, app.name.as_str())))
+                .collect();
+            ranked.sort_by_key(|(rank, _)| std::cmp::Reverse(*rank));
+            ranked.into_iter().map(|(_, name)| name).collect()
+        };
+
+        // each term on its own, in any field
+        assert_eq!(matching("web browser"), vec!["Web", "Firefox"]);
+        assert_eq!(matching("browser web"), vec!["Web", "Firefox"]);
+        // the phrase has to appear as written
+        assert_eq!(matching(r#""web browser""#), vec!["Firefox"]);
+        assert_eq!(matching("fire dev"), vec!["Firefox Developer Edition"]);
+        assert!(matching("web zz").is_empty());
+
+        // a term matching in several fields counts its best one
+        let both = fuzzy("web browser", &apps[1]).unwrap();
+        let web = fuzzy("web", &apps[1]).unwrap();
+        let browser = fuzzy("browser", &apps[1]).unwrap();
+        assert_eq!(both, web + browser);
+        let breakdown = explain(&Query::new("web browser"), &apps[1], MatchMode::Fuzzy, &Weights::default());
+        assert_eq!(breakdown.best(), Some(both));
+        assert!(breakdown.keywords.is_some() && breakdown.name.is_some());
+    }
+
+    #[test]
+    fn matches_generic_name() {
+        let firefox = app(
+            "[Desktop Entry]\nExec=true\nName=Firefox\nGenericName=Web Browser\n\
+             Comment=Browse the Web\n",
+        );
+        let webcam = app("[Desktop Entry]\nExec=true\nName=Webcam\nComment=Web browser for cameras\n");
+
+        assert!(fuzzy("web browser", &firefox).is_some());
+        // worth less than the name, more than the comment
+        assert!(fuzzy("web browser", &firefox) > fuzzy("web browser", &webcam));
+        let browser = app("[Desktop Entry]\nExec=true\nName=Web Browser\n");
+        assert!(fuzzy("web browser", &browser) > fuzzy("web browser", &firefox));
+    }
+
+    #[test]
+    fn matches_exec_program() {
+        let web = app("[Desktop Entry]\nName=Web\nExec=chromium %U\n");
+        assert!(fuzzy("chromium", &web).is_some());
+        let chrome = app("[Desktop Entry]\nName=Google Chrome\nExec=/usr/bin/google-chrome-stable %U\n");
+        let other = app("[Desktop Entry]\nName=Browser\nExec=google-chrome-stable %U\n");
+        assert!(fuzzy("chrome", &other).is_some());
+        // the display name still counts more
+        assert!(fuzzy("chrome", &chrome) > fuzzy("chrome", &other));
+        assert!(fuzzy("chromium", &app("[Desktop Entry]\nExec=true\nName=Web\n")).is_none());
+    }
+
+    #[test]
+    fn matches_keywords() {
+        let gimp = app("[Desktop Entry]\nExec=true\nName=GIMP\nKeywords=photo;paint;editor;\n");
+        assert!(fuzzy("paint", &gimp).is_some());
+        assert!(fuzzy("zzz", &gimp).is_none());
+
+        let paint = app("[Desktop Entry]\nExec=true\nName=Paint\n");
+        assert!(fuzzy("paint", &paint) > fuzzy("paint", &gimp));
+    }
+
+    #[test]
+    fn normalization_of_tricky_text() {
+        assert_eq!(normalize_for_search("Éditeur"), "editeur");
+        assert_eq!(normalize_for_search("E\u{301}diteur"), "editeur");
+        // Turkish: dotted capital I folds to plain i, dotless ı stays apart
+        assert_eq!(normalize_for_search("İSTANBUL"), "istanbul");
+        assert_eq!(normalize_for_search("Istanbul"), "istanbul");
+        assert_eq!(normalize_for_search("ılık"), "ılık");
+        // German: capital sharp s lowercases, nothing expands to "ss"
+        assert_eq!(normalize_for_search("STRAẞE"), "straße");
+        assert_eq!(normalize_for_search("Straße"), "straße");
+        // Greek final sigma folds like the medial one
+        assert_eq!(normalize_for_search("ΟΔΟΣ"), normalize_for_search("οδος"));
+        assert_eq!(normalize_for_search("Ἀθῆναι"), "αθηναι");
+        // one char for one, always
+        for text in ["İSTANBUL", "STRAẞE", "ǅemal", "ﬁle", "Ａpp"] {
+            assert_eq!(normalize_for_search(text).chars().count(), chars(text).count());
+        }
+
+        assert!(score("istanbul", "İstanbul").is_some());
+        assert!(score("strasse", "Straße").is_none());
+        assert_eq!(tier("STRAẞE", "Straße"), 3);
+    }
+
+    #[test]
+    fn ignores_diacritics() {
+        // French, Spanish and Vietnamese names, precomposed and decomposed
+        assert!(score("editeur", "Éditeur de texte").is_some());
+        assert_eq!(ranked("telefono", &["Teléfonos", "Teléfono"]), vec!["Teléfono", "Teléfonos"]);
+        assert_eq!(rank("trinh duyet", "Trình duyệt"), rank("trinh duyet", "Trinh duyet"));
+        assert_eq!(tier("tele", "Te\u{301}le\u{301}fono"), 2);
+        // accents in the query are ignored too
+        assert!(score("café", "Cafe").is_some());
+
+        let app = app(
+            "[Desktop Entry]\nExec=true\nName=Lecteur\nComment=Écouter de la musique\n\
+             Keywords=música;\n",
+        );
+        assert!(fuzzy("ecouter", &app).is_some());
+        assert!(fuzzy("musica", &app).is_some());
+        assert_eq!(app.name, "Lecteur");
+    }
+
+    #[test]
+    fn match_modes() {
+        let names = ["GNOME Terminal", "Terminal", "Determine", "The Remote Manager"];
+        let matching = |mode, query| -> Vec<&str> {
+            names
+                .iter()
+                .copied()
+                .filter(|name| {
+                    let app = app(&format!("[Desktop Entry]\nExec=true\nName={}\n", name));
+                    rank_app(&Query::new(query), &app, mode, &Weights::default()).is_some()
+                })
+                .collect()
+        };
+
+        assert_eq!(matching(MatchMode::Fuzzy, "term"), names);
+        assert_eq!(matching(MatchMode::Substring, "term"), vec!["GNOME Terminal", "Terminal", "Determine"]);
+        assert_eq!(matching(MatchMode::Prefix, "term"), vec!["GNOME Terminal", "Terminal"]);
+        assert_eq!(matching(MatchMode::Prefix, "gnome t"), vec!["GNOME Terminal"]);
+        // an empty query still lists everything
+        assert_eq!(matching(MatchMode::Prefix, ""), names);
+    }
+
+    #[test]
+    fn precomputed_keys_rank_to_fixed_scores() {
+        let apps = [
+            app(
+                "[Desktop Entry]\nExec=true\nName=Firefox\nGenericName=Web Browser\n\
+                 Keywords=internet;www;\n",
+            ),
+            app("[Desktop Entry]\nExec=true\nName=Téléphone\nComment=Appeler vos contacts\n"),
+            app("[Desktop Entry]\nExec=true\nName=GNOME Terminal\nKeywords=shell;prompt;command;\n"),
+        ];
+        // per app, in the order above
+        let expected: [(&str, [Option<i64>; 3]); 10] = [
+            ("", [Some(0); 3]),
+            ("  ", [Some(0); 3]),
+            ("fire", [Some(2_199_023_349_753), None, None]),
+            ("web", [Some(1_869_169_829_878), None, None]),
+            ("tele", [None, Some(2_199_023_349_751), None]),
+            ("TÉLÉ", [None, Some(2_199_023_349_751), None]),
+            ("gt", [None, None, Some(50_162)]),
+            ("shell", [None, None, Some(2_308_974_498_607)]),
+            ("contacts", [None, Some(439_804_721_553), None]),
+            ("zz", [None; 3]),
+        ];
+        for (query, scores) in expected {
+            let found: Vec<Option<i64>> = apps.iter().map(|app| fuzzy(query, app)).collect();
+            assert_eq!(found, scores, "{:?}", query);
+        }
+        // the same prefix match is worth less in a lower weighted field
+        assert!(fuzzy("fire", &apps[0]) > fuzzy("web", &apps[0]));
+        assert!(fuzzy("web", &apps[0]) > fuzzy("contacts", &apps[1]));
+        // a whole keyword beats a name prefix, a scattered match anything else
+        assert!(fuzzy("shell", &apps[2]) > fuzzy("fire", &apps[0]));
+        assert!(fuzzy("gt", &apps[2]) < fuzzy("contacts", &apps[1]));
+    }
+
+    // cargo test --release bench_rank_apps -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_rank_apps() {
+        let apps: Vec<AppInfo> = (0..2000)
+            .map(|i| {
+                app(&format!(
+                    "[Desktop Entry]\nExec=true\nName=Application Number {i}\n\
+                     Comment=Does thing {i} well\nKeywords=tool;utility{i};\n"
+                ))
+            })
+            .collect();
+        let queries = ["a", "ap", "app", "appn", "number 1", "thing", "util", "xyz"];
+        let start = std::time::Instant::now();
+        for _ in 0..10 {
+            for query in queries {
+                let query = Query::new(query);
+                let weights = Weights::default();
+                let matches = apps.iter().filter_map(|app| rank_app(&query, app, MatchMode::Fuzzy, &weights));
+                std::hint::black_box(matches.count());
+            }
+        }
+        println!("{} queries over {} apps: {:?}", 10 * queries.len(), apps.len(), start.elapsed());
+    }
+
+    #[test]
+    fn shorter_name_wins_ties() {
+        assert_eq!(ranked("kat", &["Kate Editor", "Kate"]), vec!["Kate", "Kate Editor"]);
+    }
+}
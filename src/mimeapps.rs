@@ -0,0 +1,221 @@
+//! `mimeapps.list` files, where the user and the system pick the apps opening
+//! each MIME type.
+//!
+//! The files are looked up in the order of the MIME Applications spec, the
+//! desktop specific `<desktop>-mimeapps.list` before the plain one in each
+//! directory, the user's configuration before the system's and before the
+//! lists shipped next to the desktop files.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What the `mimeapps.list` files say about one MIME type.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Associations {
+    /// `[Default Applications]`, most preferred first.
+    pub defaults: Vec<String>,
+    /// `[Added Associations]`, apps opening the type even if their desktop
+    /// file doesn't declare it, most preferred first.
+    pub added: Vec<String>,
+    /// `[Removed Associations]`, apps not offered even though their desktop
+    /// file declares the type.
+    pub removed: Vec<String>,
+}
+
+impl Associations {
+    /// Merges the contents of `lists`, most important first, for `mime`.
+    ///
+    /// A removal hides the app from the lists after the one removing it and
+    /// from its `MimeType`, not from the more important lists.
+    pub(crate) fn merge<S: AsRef<str>>(lists: &[S], mime: &str) -> Self {
+        let mut merged = Associations::default();
+        for list in lists {
+            let list = parse(list.as_ref(), mime);
+            for id in list.defaults {
+                if !merged.defaults.contains(&id) {
+                    merged.defaults.push(id);
+                }
+            }
+            for id in list.added {
+                if !merged.removed.contains(&id) && !merged.added.contains(&id) {
+                    merged.added.push(id);
+                }
+            }
+            for id in list.removed {
+                if !merged.added.contains(&id) && !merged.removed.contains(&id) {
+                    merged.removed.push(id);
+                }
+            }
+        }
+        merged
+    }
+
+    /// The associations of `mime` for the current user and desktop.
+    pub(crate) fn load(mime: &str, desktops: &[String]) -> Self {
+        let xdg_dirs = xdg::BaseDirectories::new();
+        let paths = list_paths(
+            xdg_dirs.get_config_home().as_deref(),
+            &xdg_dirs.get_config_dirs(),
+            xdg_dirs.get_data_home().as_deref(),
+            &xdg_dirs.get_data_dirs(),
+            desktops,
+        );
+        // most don't exist
+        let lists: Vec<String> = paths.iter().filter_map(|path| fs::read_to_string(path).ok()).collect();
+        Associations::merge(&lists, mime)
+    }
+
+    /// Where `id` ranks in the "open with" list: defaults first, then the
+    /// added associations, then everything else.
+    pub(crate) fn rank(&self, id: &str) -> (usize, usize) {
+        if let Some(index) = self.defaults.iter().position(|d| d == id) {
+            (0, index)
+        } else if let Some(index) = self.added.iter().position(|a| a == id) {
+            (1, index)
+        } else {
+            (2, 0)
+        }
+    }
+
+    /// Whether `id` is offered for the type, `declared` telling whether its
+    /// desktop file lists it in `MimeType`.
+    pub(crate) fn offers(&self, id: &str, declared: bool) -> bool {
+        let listed = |ids: &[String]| ids.iter().any(|listed| listed == id);
+        listed(&self.defaults) || listed(&self.added) || (declared && !listed(&self.removed))
+    }
+}
+
+/// The three groups of one `mimeapps.list` for `mime`. MIME types are
+/// compared ignoring case.
+fn parse(content: &str, mime: &str) -> Associations {
+    let mut associations = Associations::default();
+    let mut group = "";
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            group = line;
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        if !key.trim().eq_ignore_ascii_case(mime) {
+            continue;
+        }
+        let ids = match group {
+            "[Default Applications]" => &mut associations.defaults,
+            "[Added Associations]" => &mut associations.added,
+            "[Removed Associations]" => &mut associations.removed,
+            _ => continue,
+        };
+        ids.extend(crate::split_desktop_list(value));
+    }
+    associations
+}
+
+/// The `mimeapps.list` files to read, most important first.
+fn list_paths(
+    config_home: Option<&Path>,
+    config_dirs: &[PathBuf],
+    data_home: Option<&Path>,
+    data_dirs: &[PathBuf],
+    desktops: &[String],
+) -> Vec<PathBuf> {
+    let config = config_home.into_iter().chain(config_dirs.iter().map(PathBuf::as_path));
+    let data: Vec<PathBuf> = data_home
+        .into_iter()
+        .chain(data_dirs.iter().map(PathBuf::as_path))
+        .map(|dir| dir.join("applications"))
+        .collect();
+    let mut paths = Vec::new();
+    for dir in config.chain(data.iter().map(PathBuf::as_path)) {
+        for desktop in desktops {
+            paths.push(dir.join(format!("{}-mimeapps.list", desktop.to_lowercase())));
+        }
+        paths.push(dir.join("mimeapps.list"));
+    }
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER: &str = "\
+[Default Applications]
+image/png=org.gnome.Loupe.desktop;gimp.desktop;
+
+[Added Associations]
+image/png=org.inkscape.Inkscape.desktop;
+Text/Plain=org.gnome.TextEditor.desktop
+
+[Removed Associations]
+image/png=org.gnome.Evince.desktop;
+";
+
+    const SYSTEM: &str = "\
+[Default Applications]
+image/png=eog.desktop
+[Added Associations]
+image/png=org.gnome.Evince.desktop;krita.desktop;
+";
+
+    #[test]
+    fn one_list() {
+        let associations = Associations::merge(&[USER], "image/png");
+        assert_eq!(associations.defaults, vec!["org.gnome.Loupe.desktop", "gimp.desktop"]);
+        assert_eq!(associations.added, vec!["org.inkscape.Inkscape.desktop"]);
+        assert_eq!(associations.removed, vec!["org.gnome.Evince.desktop"]);
+        assert_eq!(
+            Associations::merge(&[USER], "text/plain").added,
+            vec!["org.gnome.TextEditor.desktop"]
+        );
+        assert_eq!(Associations::merge(&[USER], "image/jpeg"), Associations::default());
+    }
+
+    #[test]
+    fn lists_merged_by_precedence() {
+        let associations = Associations::merge(&[USER, SYSTEM], "image/png");
+        // the system default comes after the user's
+        assert_eq!(associations.rank("org.gnome.Loupe.desktop"), (0, 0));
+        assert_eq!(associations.rank("eog.desktop"), (0, 2));
+        assert_eq!(associations.rank("org.inkscape.Inkscape.desktop"), (1, 0));
+        assert_eq!(associations.rank("krita.desktop"), (1, 1));
+        assert_eq!(associations.rank("shotwell.desktop"), (2, 0));
+
+        // removed by the user, the system can't add it back
+        assert!(!associations.offers("org.gnome.Evince.desktop", true));
+        assert!(associations.offers("krita.desktop", false));
+        assert!(associations.offers("shotwell.desktop", true));
+        assert!(!associations.offers("shotwell.desktop", false));
+
+        // the other way around the user's addition wins over the system's removal
+        let reversed = Associations::merge(&[SYSTEM, USER], "image/png");
+        assert!(reversed.offers("org.gnome.Evince.desktop", false));
+        assert!(reversed.removed.is_empty());
+    }
+
+    #[test]
+    fn files_in_spec_order() {
+        let paths = list_paths(
+            Some(Path::new("/home/me/.config")),
+            &[PathBuf::from("/etc/xdg")],
+            Some(Path::new("/home/me/.local/share")),
+            &[PathBuf::from("/usr/share")],
+            &["ubuntu".to_string(), "GNOME".to_string()],
+        );
+        let paths: Vec<String> = paths.iter().map(|path| path.display().to_string()).collect();
+        assert_eq!(
+            paths[..4],
+            [
+                "/home/me/.config/ubuntu-mimeapps.list",
+                "/home/me/.config/gnome-mimeapps.list",
+                "/home/me/.config/mimeapps.list",
+                "/etc/xdg/ubuntu-mimeapps.list",
+            ]
+        );
+        assert_eq!(paths.len(), 12);
+        assert_eq!(paths[8], "/home/me/.local/share/applications/mimeapps.list");
+        assert_eq!(paths[11], "/usr/share/applications/mimeapps.list");
+    }
+}
@@ -0,0 +1,41 @@
+//! Pinyin readings of Chinese app names, so `liulanqi` finds 浏览器.
+//!
+//! Behind the `pinyin` cargo feature and the `pinyin_search` config flag. The
+//! readings come from the `pinyin` crate's dictionary, one per character:
+//! characters with several readings get the most common one, e.g. `le` for
+//! 乐, whatever the word they are in.
+
+use ::pinyin::ToPinyin;
+
+/// The pinyin of `name`, lowercase toneless syllables run together like they
+/// are typed, `ü` spelled `v` as input methods do, other characters kept as
+/// they are. `None` without any character to read.
+pub(crate) fn reading(name: &str) -> Option<String> {
+    let mut read = false;
+    let mut reading = String::new();
+    for (c, pinyin) in name.chars().zip(name.to_pinyin()) {
+        match pinyin {
+            Some(pinyin) => {
+                reading.extend(pinyin.plain().chars().map(|c| if c == 'ü' { 'v' } else { c }));
+                read = true;
+            }
+            None if c.is_whitespace() => {}
+            None => reading.extend(c.to_lowercase()),
+        }
+    }
+    read.then_some(reading)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_read_as_pinyin() {
+        assert_eq!(reading("网页浏览器").as_deref(), Some("wangyeliulanqi"));
+        assert_eq!(reading("QQ邮箱").as_deref(), Some("qqyouxiang"));
+        assert_eq!(reading("文本 编辑器").as_deref(), Some("wenbenbianjiqi"));
+        assert_eq!(reading("绿色").as_deref(), Some("lvse"));
+        assert_eq!(reading("Firefox"), None);
+    }
+}
@@ -0,0 +1,98 @@
+//! `name_replacements`, rules rewriting the names shown to the user.
+//!
+//! A rule's pattern is replaced literally, unless it starts with `re:`: the
+//! rest is then a regular expression in the syntax of the `regex` crate, and
+//! the replacement can refer to its groups as `$1` or `${name}`. A rule whose
+//! expression doesn't compile is left out, with a warning when the config is
+//! applied.
+
+use regex::Regex;
+
+enum Rule {
+    Literal(String, String),
+    Pattern(Regex, String),
+}
+
+/// The rules of a config, their expressions compiled once for every name.
+pub(crate) struct Rules(Vec<Rule>);
+
+impl Rules {
+    pub(crate) fn new(rules: &[(String, String)]) -> Self {
+        let compiled = rules.iter().filter_map(|(pattern, replacement)| {
+            let rule = match pattern.strip_prefix("re:") {
+                Some(pattern) => Rule::Pattern(Regex::new(pattern).ok()?, replacement.clone()),
+                None if pattern.is_empty() => return None,
+                None => Rule::Literal(pattern.clone(), replacement.clone()),
+            };
+            Some(rule)
+        });
+        Rules(compiled.collect())
+    }
+
+    /// `name` after the rules, each applied in turn to the result of the
+    /// previous one. `None` when nothing changed, or when the rules leave an
+    /// empty name.
+    pub(crate) fn apply(&self, name: &str) -> Option<String> {
+        let mut renamed = name.to_string();
+        for rule in &self.0 {
+            renamed = match rule {
+                Rule::Literal(pattern, replacement) => renamed.replace(pattern.as_str(), replacement),
+                Rule::Pattern(pattern, replacement) => {
+                    pattern.replace_all(&renamed, replacement.as_str()).into_owned()
+                }
+            };
+        }
+        let renamed = renamed.trim();
+        (!renamed.is_empty() && renamed != name).then(|| renamed.to_string())
+    }
+}
+
+/// Warns about the `re:` patterns of `rules` that don't compile.
+pub(crate) fn check(rules: &[(String, String)]) {
+    for pattern in rules.iter().filter_map(|(pattern, _)| pattern.strip_prefix("re:")) {
+        if let Err(e) = Regex::new(pattern) {
+            log_warn!("Ignoring the name replacement {:?}: {}", pattern, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(name: &str, rules: &[(String, String)]) -> Option<String> {
+        Rules::new(rules).apply(name)
+    }
+
+    fn rule(pattern: &str, replacement: &str) -> (String, String) {
+        (pattern.to_string(), replacement.to_string())
+    }
+
+    #[test]
+    fn literal_and_pattern_rules() {
+        assert_eq!(apply("Files (GTK)", &[rule(" (GTK)", "")]).as_deref(), Some("Files"));
+        assert_eq!(apply("Files", &[rule(" (GTK)", "")]), None);
+        let suffix = [rule("re: — .*$", "")];
+        assert_eq!(apply("Writer — LibreOffice", &suffix).as_deref(), Some("Writer"));
+        assert_eq!(apply("Writer", &suffix), None);
+        // anchored, so only the leading one goes
+        let prefix = [rule("re:^GNOME ", "")];
+        assert_eq!(apply("GNOME Maps GNOME ", &prefix).as_deref(), Some("Maps GNOME"));
+        let spaces = [rule(r"re: +", " "), rule(r"re: v\.?.*$", "")];
+        assert_eq!(apply("App   v.2", &spaces).as_deref(), Some("App"));
+        // a rule leaving nothing keeps the name
+        assert_eq!(apply("Files", &[rule("re:.*", "")]), None);
+        assert_eq!(apply("Files", &[rule("", "x")]), None);
+    }
+
+    #[test]
+    fn pattern_syntax() {
+        let groups = [rule(r"re:^(\w+) \((\w+)\)$", "$2 $1")];
+        assert_eq!(apply("Office (Libre)", &groups).as_deref(), Some("Libre Office"));
+        let alternatives = [rule("re: (Preview|Beta)$", "")];
+        assert_eq!(apply("Browser Beta", &alternatives).as_deref(), Some("Browser"));
+        // left out, the rules after it still apply
+        let broken = [rule("re:(unclosed", ""), rule(" Beta", "")];
+        assert_eq!(apply("Browser Beta", &broken).as_deref(), Some("Browser"));
+    }
+}
@@ -0,0 +1,89 @@
+//! Whether an app has a running process, for "focus if running" hosts.
+//!
+//! Best effort: the process table only tells what's running, not which
+//! desktop file started it, so a process counts for an app when its name is
+//! the app's binary or its `StartupWMClass`.
+
+use std::fs;
+use std::path::Path;
+
+/// Binaries launching other apps, a process of theirs says nothing about
+/// which app it is.
+const LAUNCHERS: &[&str] = &["flatpak", "snap", "sh", "bash", "gtk-launch", "gio", "systemd-run"];
+
+/// The longest process name the kernel keeps in `comm`.
+const COMM_LENGTH: usize = 15;
+
+/// The names a process of an app with `binary` (basename of its Exec
+/// program) and `wm_class` would go by.
+fn candidates(binary: Option<&str>, wm_class: Option<&str>) -> Vec<String> {
+    let binary = binary.filter(|binary| !LAUNCHERS.contains(binary));
+    binary.into_iter().chain(wm_class).filter(|name| !name.is_empty()).map(str::to_lowercase).collect()
+}
+
+/// Whether one of the processes under `proc`, laid out like `/proc`, goes by
+/// one of `names`: its `comm` (cut to `COMM_LENGTH`), or the basename of the
+/// first argument of its `cmdline`. Names compare ignoring case.
+fn running_in(proc: &Path, names: &[String]) -> bool {
+    if names.is_empty() {
+        return false;
+    }
+    let Ok(entries) = fs::read_dir(proc) else {
+        return false;
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().bytes().all(|b| b.is_ascii_digit()))
+        .any(|entry| {
+            // processes exit while being looked at, a missing file is no match
+            let comm = fs::read_to_string(entry.path().join("comm")).unwrap_or_default();
+            let comm = comm.trim_end().to_lowercase();
+            let cmdline = fs::read(entry.path().join("cmdline")).unwrap_or_default();
+            let argv0 = cmdline.split(|&b| b == 0).next().unwrap_or_default();
+            let argv0 = String::from_utf8_lossy(argv0);
+            let argv0 = argv0.rsplit('/').next().unwrap_or_default().to_lowercase();
+            names.iter().any(|name| {
+                *name == argv0
+                    || (!comm.is_empty() && *name.chars().take(COMM_LENGTH).collect::<String>() == comm)
+            })
+        })
+}
+
+/// Whether a process of the app with `binary` and `wm_class` is running.
+pub(crate) fn is_running(binary: Option<&str>, wm_class: Option<&str>) -> bool {
+    running_in(Path::new("/proc"), &candidates(binary, wm_class))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDir;
+
+    fn process(proc: &TestDir, pid: u32, comm: &str, cmdline: &[&str]) {
+        proc.write(&format!("{}/comm", pid), &format!("{}\n", comm));
+        proc.write(&format!("{}/cmdline", pid), &(cmdline.join("\0") + "\0"));
+    }
+
+    #[test]
+    fn processes_matched_by_binary_or_wm_class() {
+        let proc = TestDir::new("proc");
+        process(&proc, 1, "systemd", &["/sbin/init"]);
+        process(&proc, 42, "Isolated Web Co", &["/usr/lib/firefox/firefox", "-contentproc"]);
+        process(&proc, 77, "gnome-text-edit", &["gnome-text-editor"]);
+        process(&proc, 99, "bash", &["/bin/bash"]);
+        proc.write("self/comm", "nautilus\n");
+
+        let running = |binary: Option<&str>, wm_class: Option<&str>| {
+            running_in(proc.path(), &candidates(binary, wm_class))
+        };
+        assert!(running(Some("firefox"), None));
+        // cut to 15 chars in comm, whole in cmdline
+        assert!(running(Some("gnome-text-editor"), None));
+        assert!(running(None, Some("Gnome-Text-Editor")));
+        assert!(!running(Some("nautilus"), Some("org.gnome.Nautilus")));
+        // a shell running says nothing about a `sh -c` app
+        assert!(!running(Some("bash"), None));
+        assert!(!running(None, None));
+        assert!(!running_in(&proc.path().join("missing"), &["firefox".to_string()]));
+    }
+}
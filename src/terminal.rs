@@ -0,0 +1,96 @@
+//! Picking a terminal emulator for `Terminal=true` apps.
+
+use crate::exec;
+
+/// The argument each known terminal expects before the command to run.
+///
+/// An empty flag means the command is simply appended.
+const EXEC_FLAGS: &[(&str, &str)] = &[
+    ("gnome-terminal", "--"),
+    ("kgx", "--"),
+    ("konsole", "-e"),
+    ("xfce4-terminal", "-x"),
+    ("terminator", "-x"),
+    ("alacritty", "-e"),
+    ("kitty", ""),
+    ("foot", ""),
+    ("wezterm", "start --"),
+    ("ghostty", "-e"),
+    ("xterm", "-e"),
+    ("urxvt", "-e"),
+    ("st", "-e"),
+];
+
+/// Builds the argv prefix for a terminal given by name, using its exec flag
+/// from the table and `-e` for terminals we don't know.
+fn known_terminal(name: &str) -> Vec<String> {
+    let basename = name.rsplit('/').next().unwrap_or(name);
+    let flag = EXEC_FLAGS
+        .iter()
+        .find(|(terminal, _)| *terminal == basename)
+        .map_or("-e", |(_, flag)| *flag);
+    std::iter::once(name.to_string())
+        .chain(flag.split_whitespace().map(str::to_string))
+        .collect()
+}
+
+/// The terminal commands to try, in order.
+///
+/// A configured `terminal_command` is used verbatim and is the only candidate.
+/// Otherwise `$TERMINAL` is tried before the known terminals.
+pub(crate) fn candidates(configured: Option<&str>, env_terminal: Option<&str>) -> Vec<Vec<String>> {
+    if let Some(configured) = configured.filter(|c| !c.trim().is_empty()) {
+        return vec![exec::expand_exec(configured, &[])];
+    }
+
+    let mut candidates = Vec::new();
+    if let Some(terminal) = env_terminal.filter(|t| !t.trim().is_empty()) {
+        candidates.push(known_terminal(terminal.trim()));
+    }
+    candidates.extend(EXEC_FLAGS.iter().map(|(terminal, _)| known_terminal(terminal)));
+    candidates
+}
+
+/// Appends the app's argv to a terminal prefix from `candidates`.
+pub(crate) fn build_argv(terminal: &[String], command: &[String]) -> Vec<String> {
+    terminal.iter().chain(command).cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn configured_terminal_is_used_verbatim() {
+        let candidates = candidates(Some("wezterm start --cwd /tmp --"), Some("xterm"));
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(
+            build_argv(&candidates[0], &strings(&["htop", "-d", "5"])),
+            strings(&["wezterm", "start", "--cwd", "/tmp", "--", "htop", "-d", "5"])
+        );
+    }
+
+    #[test]
+    fn env_terminal_comes_first_with_its_flag() {
+        let candidates = candidates(None, Some("/usr/bin/gnome-terminal"));
+        assert_eq!(candidates[0], strings(&["/usr/bin/gnome-terminal", "--"]));
+        assert_eq!(
+            build_argv(&candidates[0], &strings(&["vim"])),
+            strings(&["/usr/bin/gnome-terminal", "--", "vim"])
+        );
+        // the fallback list is still tried afterwards
+        assert!(candidates.contains(&strings(&["xterm", "-e"])));
+    }
+
+    #[test]
+    fn per_terminal_flags() {
+        let candidates = candidates(None, None);
+        assert!(candidates.contains(&strings(&["kitty"])));
+        assert!(candidates.contains(&strings(&["xfce4-terminal", "-x"])));
+        assert_eq!(known_terminal("some-new-term"), strings(&["some-new-term", "-e"]));
+    }
+}
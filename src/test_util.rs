@@ -0,0 +1,80 @@
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::config::{self, Config};
+
+/// A scratch directory for fixtures, removed again on drop.
+///
+/// It lives under `target/` rather than the system temp dir because desktop
+/// files under the temp dir are skipped by default.
+pub(crate) struct TestDir {
+    path: PathBuf,
+}
+
+impl TestDir {
+    pub(crate) fn new(name: &str) -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("target/test-fixtures").join(format!(
+            "{}-{}-{}",
+            name,
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&path).unwrap();
+        TestDir { path }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes `content` to `relative`, creating parent directories.
+    pub(crate) fn write(&self, relative: &str, content: &str) -> PathBuf {
+        let path = self.path.join(relative);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    /// Writes `count` applications `applist-test-<id>-<i>.desktop`, named `<name> <i>`.
+    pub(crate) fn write_apps(&self, id: &str, name: &str, count: usize) {
+        for i in 0..count {
+            self.write(
+                &format!("applications/applist-test-{}-{}.desktop", id, i),
+                &format!("[Desktop Entry]\nExec=true\nName={} {}\n", name, i),
+            );
+        }
+    }
+
+    /// Sets `config` globally with this directory added to its search paths, until the
+    /// returned guard drops. Only while holding [`config::test_lock`].
+    pub(crate) fn configure(self, mut config: Config) -> ConfiguredDir {
+        config.extra_search_paths.push(self.path.clone());
+        config::set(config);
+        ConfiguredDir(self)
+    }
+}
+
+/// A [`TestDir`] searched by the global config, which is back to its default on drop.
+pub(crate) struct ConfiguredDir(TestDir);
+
+impl Deref for ConfiguredDir {
+    type Target = TestDir;
+
+    fn deref(&self) -> &TestDir {
+        &self.0
+    }
+}
+
+impl Drop for ConfiguredDir {
+    fn drop(&mut self) {
+        config::set(Config::default());
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,276 @@
+//! Background watching of the scanned directories with inotify, so apps
+//! installed, removed or edited mid-session show up on the next query.
+//!
+//! The mtime check of the cache already notices files being added or removed,
+//! but not a desktop file rewritten in place. The watcher covers both by
+//! invalidating a directory on any change to it, or to the subdirectories a
+//! recursive scan read in it. Directories that don't exist yet are retried
+//! periodically and invalidated once they appear, and the subdirectories are
+//! looked up again on every pass, so the ones a rescan finds get watched too.
+
+use anyhow::{Result, bail};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+
+// how long the thread waits for events before checking the stop flag and
+// retrying missing directories
+const POLL_TIMEOUT_MS: libc::c_int = 250;
+
+const WATCH_MASK: u32 = libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MODIFY
+    | libc::IN_CLOSE_WRITE
+    | libc::IN_ATTRIB
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_DELETE_SELF
+    | libc::IN_MOVE_SELF;
+
+/// A running watcher thread, stopped and joined on drop.
+pub(crate) struct Watcher {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watcher {
+    /// Starts watching `dirs` and the `subdirs` of each, calling `on_change`
+    /// from the watcher thread with the one of `dirs` that changed.
+    pub(crate) fn start<S, F>(dirs: Vec<PathBuf>, subdirs: S, on_change: F) -> Result<Watcher>
+    where
+        S: Fn(&Path) -> Vec<PathBuf> + Send + 'static,
+        F: Fn(&Path) + Send + 'static,
+    {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            bail!("inotify_init1 failed: {}", std::io::Error::last_os_error());
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            std::thread::Builder::new()
+                .name("applist-watch".to_string())
+                .spawn(move || run(fd, dirs, &stop, &subdirs, &on_change))?
+        };
+        Ok(Watcher { stop, thread: Some(thread) })
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn add_watch(fd: libc::c_int, dir: &Path) -> Option<libc::c_int> {
+    let path = CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let wd = unsafe { libc::inotify_add_watch(fd, path.as_ptr(), WATCH_MASK) };
+    (wd >= 0).then_some(wd)
+}
+
+fn run(
+    fd: libc::c_int,
+    dirs: Vec<PathBuf>,
+    stop: &AtomicBool,
+    subdirs: &dyn Fn(&Path) -> Vec<PathBuf>,
+    on_change: &dyn Fn(&Path),
+) {
+    // watch descriptor -> the directory it watches and the index of the one
+    // of `dirs` it's in, for the directories watched right now
+    let mut watched: HashMap<libc::c_int, (usize, PathBuf)> = HashMap::new();
+    let mut buf = [0u8; 4096];
+    let mut first_pass = true;
+
+    while !stop.load(Ordering::Relaxed) {
+        for (index, dir) in dirs.iter().enumerate() {
+            if !watched.values().any(|(_, path)| path == dir) {
+                let Some(wd) = add_watch(fd, dir) else {
+                    continue;
+                };
+                watched.insert(wd, (index, dir.clone()));
+                // it may have been created with files in it since the last scan
+                if !first_pass {
+                    on_change(dir);
+                }
+            }
+            // a change the scan read before the watch was added still shows
+            // in the subdirectory's mtime
+            for subdir in subdirs(dir) {
+                if !watched.values().any(|(_, path)| *path == subdir)
+                    && let Some(wd) = add_watch(fd, &subdir)
+                {
+                    watched.insert(wd, (index, subdir));
+                }
+            }
+        }
+        first_pass = false;
+
+        let mut pollfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+        if unsafe { libc::poll(&mut pollfd, 1, POLL_TIMEOUT_MS) } <= 0 {
+            continue;
+        }
+        let read = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+        if read <= 0 {
+            continue;
+        }
+
+        let mut changed = Vec::new();
+        let mut offset = 0;
+        let header = std::mem::size_of::<libc::inotify_event>();
+        while offset + header <= read as usize {
+            // the buffer has no alignment guarantee for the event structs
+            let event: libc::inotify_event =
+                unsafe { std::ptr::read_unaligned(buf.as_ptr().add(offset).cast()) };
+            offset += header + event.len as usize;
+
+            if let Some(&(index, _)) = watched.get(&event.wd) {
+                if !changed.contains(&index) {
+                    changed.push(index);
+                }
+                // the directory is gone, watch it again once it comes back
+                if event.mask & libc::IN_IGNORED != 0 {
+                    watched.remove(&event.wd);
+                }
+            }
+        }
+        for index in changed {
+            on_change(&dirs[index]);
+        }
+    }
+    unsafe { libc::close(fd) };
+}
+
+static WATCHER: Mutex<Option<Watcher>> = Mutex::new(None);
+
+/// Replaces the running watcher, if any, by one watching `dirs` and
+/// invalidating them in the shared cache.
+pub(crate) fn restart(dirs: Vec<PathBuf>) {
+    let mut watcher = WATCHER.lock().unwrap_or_else(|e| e.into_inner());
+    // the old thread is joined before the new one starts
+    *watcher = None;
+    let subdirs = |dir: &Path| crate::cache::global().lock().unwrap_or_else(|e| e.into_inner()).subdirs(dir);
+    match Watcher::start(dirs, subdirs, |dir| {
+        log_debug!("{} changed", dir.display());
+        crate::cache::global()
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .invalidate(dir);
+    }) {
+        Ok(started) => *watcher = Some(started),
+        Err(e) => log_warn!("Failed to watch application directories: {}", e),
+    }
+}
+
+/// Stops the running watcher, if any.
+pub(crate) fn stop() {
+    *WATCHER.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ScanOptions;
+    use crate::cache::Cache;
+    use crate::test_util::TestDir;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    fn watch(cache: &Arc<Mutex<Cache>>, dir: &Path) -> (Watcher, mpsc::Receiver<PathBuf>) {
+        let (sender, receiver) = mpsc::channel();
+        let cache = cache.clone();
+        let subdirs = {
+            let cache = cache.clone();
+            move |dir: &Path| cache.lock().unwrap().subdirs(dir)
+        };
+        let watcher = Watcher::start(vec![dir.to_path_buf()], subdirs, move |dir| {
+            cache.lock().unwrap().invalidate(dir);
+            let _ = sender.send(dir.to_path_buf());
+        })
+        .unwrap();
+        (watcher, receiver)
+    }
+
+    fn names(cache: &Arc<Mutex<Cache>>, dir: &Path) -> Vec<String> {
+        names_with(cache, dir, &Default::default())
+    }
+
+    fn names_with(cache: &Arc<Mutex<Cache>>, dir: &Path, options: &ScanOptions) -> Vec<String> {
+        let mut cache = cache.lock().unwrap();
+        let apps = cache.dir_apps(dir, options).unwrap();
+        let mut names: Vec<String> = apps.iter().map(|app| app.name.clone()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn rewritten_file_refreshes_cache() {
+        let dir = TestDir::new("watch-rewrite");
+        let app = dir.write("applications/app.desktop", "[Desktop Entry]\nExec=true\nName=Before\n");
+        let apps = dir.path().join("applications");
+        let cache = Arc::new(Mutex::new(Cache::default()));
+        assert_eq!(names(&cache, &apps), vec!["Before"]);
+
+        let (_watcher, changes) = watch(&cache, &apps);
+        // give the thread time to add its watch
+        std::thread::sleep(Duration::from_millis(100));
+        dir.write("applications/new.desktop", "[Desktop Entry]\nExec=true\nName=New\n");
+        // rewritten in place, which leaves the directory mtime alone
+        std::fs::write(&app, "[Desktop Entry]\nExec=true\nName=After\n").unwrap();
+
+        assert_eq!(changes.recv_timeout(Duration::from_secs(5)).unwrap(), apps);
+        while changes.recv_timeout(Duration::from_millis(100)).is_ok() {}
+        assert_eq!(names(&cache, &apps), vec!["After", "New"]);
+    }
+
+    #[test]
+    fn rescanned_subdirectories_are_watched() {
+        let dir = TestDir::new("watch-subdirs");
+        let nested =
+            dir.write("applications/vendor/app.desktop", "[Desktop Entry]\nExec=true\nName=Nested\n");
+        let apps = dir.path().join("applications");
+        let cache = Arc::new(Mutex::new(Cache::default()));
+        let recursive = ScanOptions { recursive: true, ..Default::default() };
+        assert_eq!(names_with(&cache, &apps, &recursive), vec!["Nested"]);
+
+        let (_watcher, changes) = watch(&cache, &apps);
+        std::thread::sleep(Duration::from_millis(100));
+        std::fs::write(&nested, "[Desktop Entry]\nExec=true\nName=Rewritten\n").unwrap();
+        assert_eq!(changes.recv_timeout(Duration::from_secs(5)).unwrap(), apps);
+        while changes.recv_timeout(Duration::from_millis(100)).is_ok() {}
+        assert_eq!(names_with(&cache, &apps, &recursive), vec!["Rewritten"]);
+
+        // found by the rescan the new directory caused, then watched as well
+        let later =
+            dir.write("applications/later/app.desktop", "[Desktop Entry]\nExec=true\nName=Later\n");
+        assert_eq!(changes.recv_timeout(Duration::from_secs(5)).unwrap(), apps);
+        while changes.recv_timeout(Duration::from_millis(100)).is_ok() {}
+        assert_eq!(names_with(&cache, &apps, &recursive), vec!["Later", "Rewritten"]);
+        // the next pass of the thread adds it
+        std::thread::sleep(Duration::from_millis(500));
+        std::fs::write(&later, "[Desktop Entry]\nExec=true\nName=Later Rewritten\n").unwrap();
+        assert_eq!(changes.recv_timeout(Duration::from_secs(5)).unwrap(), apps);
+        while changes.recv_timeout(Duration::from_millis(100)).is_ok() {}
+        assert_eq!(names_with(&cache, &apps, &recursive), vec!["Later Rewritten", "Rewritten"]);
+    }
+
+    #[test]
+    fn directory_created_later_is_watched() {
+        let dir = TestDir::new("watch-missing");
+        let apps = dir.path().join("applications");
+        let cache = Arc::new(Mutex::new(Cache::default()));
+        let (_watcher, changes) = watch(&cache, &apps);
+
+        std::thread::sleep(Duration::from_millis(100));
+        dir.write("applications/late.desktop", "[Desktop Entry]\nExec=true\nName=Late\n");
+        assert_eq!(changes.recv_timeout(Duration::from_secs(5)).unwrap(), apps);
+        assert_eq!(names(&cache, &apps), vec!["Late"]);
+    }
+}